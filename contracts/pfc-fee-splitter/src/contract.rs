@@ -9,17 +9,22 @@ const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
 #[cfg(not(feature = "library"))]
 use cosmwasm_std::entry_point;
 use cosmwasm_std::{
-    to_json_binary, Binary, CosmosMsg, Deps, DepsMut, Env, MessageInfo, Response, StdResult,
+    to_json_binary, Binary, CosmosMsg, Deps, DepsMut, Env, MessageInfo, Reply, Response, StdResult,
     WasmMsg,
 };
 use pfc_fee_split::fee_split_msg::{
-    AllocationHolding, ExecuteMsg, InstantiateMsg, MigrateMsg, QueryMsg,
+    AllocationChange, AllocationDetail, AllocationHolding, AllocationKind, AllocationStatus,
+    ExecuteMsg, InstantiateMsg, MigrateMsg, QueryMsg, SudoMsg,
 };
 
 use crate::{
     error::ContractError,
-    handler::{exec as ExecHandler, query as QueryHandler},
-    migrations::{ConfigV100, SendTypeV100},
+    handler::{
+        exec as ExecHandler, filter as FilterHandler, group as GroupHandler, ibc as IbcHandler,
+        payout as PayoutHandler, query as QueryHandler, receiver as ReceiverHandler,
+        timelock as TimelockHandler,
+    },
+    migrations::{AllocationHoldingV152, ConfigV100, SendTypeV100},
     state,
     state::{ADMIN, ALLOCATION_HOLDINGS, CONFIG},
 };
@@ -39,6 +44,7 @@ pub fn instantiate(
             gov_contract: deps.api.addr_validate(msg.gov_contract.as_str())?,
             new_gov_contract: None,
             change_gov_contract_by_height: None,
+            change_delay: 0,
         },
     )?;
 
@@ -49,27 +55,38 @@ pub fn instantiate(
     if dupe_check.len() != msg.allocation.len() {
         return Err(ContractError::FundAllocationNotUnique {});
     }
+    if !msg.allocation.iter().any(|row| row.kind == AllocationKind::Weighted) {
+        return Err(ContractError::NoWeightedAllocation {});
+    }
     for row in msg.allocation {
-        if row.send_after.denom.trim().is_empty() {
-            return Err(ContractError::InvalidCoin {
-                coin: row.send_after,
-            });
-        }
-        // row.send_type.verify(deps.api)?;
-        if row.allocation == 0 {
-            return Err(ContractError::AllocationZero {});
-        }
+        // the same checks as adding one
+        ExecHandler::validate_allocation(&deps, &env, &row)?;
 
         let allocation_holding: AllocationHolding = AllocationHolding {
             name: row.name.clone(),
             //contract: deps.api.addr_validate(row.contract.as_str())?,
             allocation: row.allocation,
             send_after: row.send_after,
+            send_after_mode: row.send_after_mode,
             send_type: row.send_type,
             balance: vec![],
+            balance_cw20: vec![],
+            send_after_blocks: row.send_after_blocks,
+            send_after_seconds: row.send_after_seconds,
+            payout_mode: row.payout_mode,
+            status: AllocationStatus::Active,
+            kind: row.kind,
+            filled: vec![],
+            period_start: None,
+            schedule: vec![],
+            expires: None,
+            last_payout_height: Some(env.block.height),
+            last_payout_time: Some(env.block.time),
+            payout_error: None,
         };
         ALLOCATION_HOLDINGS.save(deps.storage, row.name.clone(), &allocation_holding)?
     }
+    ExecHandler::get_total_weight(&deps)?;
 
     let admin = deps.api.addr_validate(&msg.gov_contract)?;
     ADMIN.set(deps.branch(), Some(admin))?;
@@ -96,19 +113,50 @@ pub fn execute(
     match msg {
         ExecuteMsg::Deposit {
             flush,
-        } => ExecHandler::execute_deposit(deps, env, info, flush),
+            table,
+        } => ExecHandler::execute_deposit(deps, env, info, flush, table),
+        ExecuteMsg::Receive(cw20_msg) => ExecHandler::execute_receive(deps, env, info, cw20_msg),
+        // allocation changes go through TimelockHandler::execute_allocation_change, which does the
+        // admin check and the change delay. the handlers it calls don't check the admin again
         ExecuteMsg::AddAllocationDetail {
             name,
             allocation,
             send_after,
+            send_after_mode,
             send_type,
-        } => ExecHandler::execute_add_allocation_detail(
-            deps, env, info, name, allocation, send_after, send_type,
+            send_after_blocks,
+            send_after_seconds,
+            payout_mode,
+            kind,
+        } => TimelockHandler::execute_allocation_change(
+            deps,
+            env,
+            info,
+            AllocationChange::AddAllocationDetail {
+                allocation: AllocationDetail {
+                    name,
+                    allocation,
+                    send_after,
+                    send_after_mode,
+                    send_type,
+                    send_after_blocks,
+                    send_after_seconds,
+                    payout_mode,
+                    kind,
+                },
+            },
         ),
 
         ExecuteMsg::RemoveAllocationDetail {
             name,
-        } => ExecHandler::execute_remove_allocation_detail(deps, env, info, name),
+        } => TimelockHandler::execute_allocation_change(
+            deps,
+            env,
+            info,
+            AllocationChange::RemoveAllocationDetail {
+                name,
+            },
+        ),
 
         ExecuteMsg::TransferGovContract {
             gov_contract,
@@ -122,10 +170,125 @@ pub fn execute(
 
             allocation,
             send_after,
+            send_after_mode,
             send_type,
-        } => ExecHandler::execute_modify_allocation_detail(
-            deps, env, info, name, allocation, send_after, send_type,
+            send_after_blocks,
+            send_after_seconds,
+            payout_mode,
+            kind,
+        } => TimelockHandler::execute_allocation_change(
+            deps,
+            env,
+            info,
+            AllocationChange::ModifyAllocationDetail {
+                allocation: AllocationDetail {
+                    name,
+                    allocation,
+                    send_after,
+                    send_after_mode,
+                    send_type,
+                    send_after_blocks,
+                    send_after_seconds,
+                    payout_mode,
+                    kind,
+                },
+            },
+        ),
+        ExecuteMsg::ReplaceAllocations {
+            allocations,
+            balance_policy,
+        } => TimelockHandler::execute_allocation_change(
+            deps,
+            env,
+            info,
+            AllocationChange::ReplaceAllocations {
+                allocations,
+                balance_policy,
+            },
+        ),
+        ExecuteMsg::SetChangeDelay {
+            blocks,
+        } => TimelockHandler::execute_allocation_change(
+            deps,
+            env,
+            info,
+            AllocationChange::SetChangeDelay {
+                blocks,
+            },
+        ),
+        ExecuteMsg::SetWeightSchedule {
+            name,
+            schedule,
+            expires,
+        } => TimelockHandler::execute_allocation_change(
+            deps,
+            env,
+            info,
+            AllocationChange::SetWeightSchedule {
+                name,
+                schedule,
+                expires,
+            },
         ),
+        ExecuteMsg::ApplyChange {
+            id,
+        } => TimelockHandler::execute_apply_change(deps, env, info, id),
+        ExecuteMsg::CancelChange {
+            id,
+        } => TimelockHandler::execute_cancel_change(deps, info, id),
+        ExecuteMsg::Claim {
+            name,
+        } => PayoutHandler::execute_claim(deps, env, info, name),
+        ExecuteMsg::SetClaimDelegate {
+            name,
+            delegate,
+        } => PayoutHandler::execute_set_claim_delegate(deps, info, name, delegate),
+        ExecuteMsg::UpdateMyReceiver {
+            name,
+            new_receiver,
+        } => ReceiverHandler::execute_update_my_receiver(deps, env, info, name, new_receiver),
+        ExecuteMsg::AcceptMyReceiver {
+            name,
+        } => ReceiverHandler::execute_accept_my_receiver(deps, info, name),
+        ExecuteMsg::RetryPayout {
+            name,
+        } => PayoutHandler::execute_retry_payout(deps, env, info, name),
+        ExecuteMsg::SetAllocationStatus {
+            name,
+            status,
+        } => TimelockHandler::execute_allocation_change(
+            deps,
+            env,
+            info,
+            AllocationChange::SetAllocationStatus {
+                name,
+                status,
+            },
+        ),
+        ExecuteMsg::MemberChangedHook(msg) => {
+            GroupHandler::execute_member_changed_hook(deps, env, info, msg)
+        },
+        ExecuteMsg::Sweep {} => ExecHandler::execute_sweep(deps, env, info),
+        ExecuteMsg::SetDenomFilter {
+            mode,
+            denoms,
+            on_filtered,
+            max_denoms,
+        } => TimelockHandler::execute_allocation_change(
+            deps,
+            env,
+            info,
+            AllocationChange::SetDenomFilter {
+                mode,
+                denoms,
+                on_filtered,
+                max_denoms,
+            },
+        ),
+        ExecuteMsg::SweepQuarantine {
+            to,
+            denom,
+        } => FilterHandler::execute_sweep_quarantine(deps, info, to, denom),
         ExecuteMsg::Reconcile {} => ExecHandler::execute_reconcile(deps, env, info),
         ExecuteMsg::AddToFlushWhitelist {
             address,
@@ -133,21 +296,166 @@ pub fn execute(
         ExecuteMsg::RemoveFromFlushWhitelist {
             address,
         } => ExecHandler::execute_remove_flush_whitelist(deps, env, info, address),
+        ExecuteMsg::SetDenomWeights {
+            denom,
+            weights,
+        } => TimelockHandler::execute_allocation_change(
+            deps,
+            env,
+            info,
+            AllocationChange::SetDenomWeights {
+                denom,
+                weights,
+            },
+        ),
+        ExecuteMsg::ClearDenomWeights {
+            denom,
+        } => TimelockHandler::execute_allocation_change(
+            deps,
+            env,
+            info,
+            AllocationChange::ClearDenomWeights {
+                denom,
+            },
+        ),
+        ExecuteMsg::SetTable {
+            table,
+            weights,
+        } => TimelockHandler::execute_allocation_change(
+            deps,
+            env,
+            info,
+            AllocationChange::SetTable {
+                table,
+                weights,
+            },
+        ),
+        ExecuteMsg::RemoveTable {
+            table,
+        } => TimelockHandler::execute_allocation_change(
+            deps,
+            env,
+            info,
+            AllocationChange::RemoveTable {
+                table,
+            },
+        ),
+        ExecuteMsg::SetSenderTable {
+            sender,
+            table,
+        } => TimelockHandler::execute_allocation_change(
+            deps,
+            env,
+            info,
+            AllocationChange::SetSenderTable {
+                sender,
+                table,
+            },
+        ),
     }
 }
 #[cfg_attr(not(feature = "library"), entry_point)]
-pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
+pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
     match msg {
         QueryMsg::Ownership {} => to_json_binary(&QueryHandler::query_gov_contract(deps)?),
         QueryMsg::Allocations {
             start_after,
             limit,
-        } => to_json_binary(&QueryHandler::query_allocations(deps, start_after, limit)?),
+            table,
+        } => match table {
+            None => to_json_binary(&QueryHandler::query_allocations(deps, start_after, limit)?),
+            Some(table) => to_json_binary(&QueryHandler::query_table_allocations(
+                deps,
+                table,
+                start_after,
+                limit,
+            )?),
+        },
 
         QueryMsg::Allocation {
             name,
-        } => to_json_binary(&QueryHandler::query_allocation(deps, name)?),
+            table,
+        } => match table {
+            None => to_json_binary(&QueryHandler::query_allocation(deps, name)?),
+            Some(table) => {
+                to_json_binary(&QueryHandler::query_table_allocation(deps, table, name)?)
+            },
+        },
+        QueryMsg::Tables {
+            start_after,
+            limit,
+        } => to_json_binary(&QueryHandler::query_tables(deps, start_after, limit)?),
+        QueryMsg::SenderTables {
+            start_after,
+            limit,
+        } => to_json_binary(&QueryHandler::query_sender_tables(deps, start_after, limit)?),
+        QueryMsg::AllocationStats {
+            name,
+        } => to_json_binary(&QueryHandler::query_allocation_stats(deps, name)?),
+        QueryMsg::Stats {
+            start_after,
+            limit,
+        } => to_json_binary(&QueryHandler::query_stats(deps, start_after, limit)?),
         QueryMsg::FlushWhitelist {} => to_json_binary(&QueryHandler::query_flush_whitelist(deps)?),
+        QueryMsg::PendingIbcTransfers {
+            start_after,
+            limit,
+        } => to_json_binary(&QueryHandler::query_pending_ibc_transfers(deps, start_after, limit)?),
+        QueryMsg::DenomSplit {
+            denom,
+        } => to_json_binary(&QueryHandler::query_denom_split(deps, &env, denom)?),
+        QueryMsg::DenomWeights {
+            start_after,
+            limit,
+        } => to_json_binary(&QueryHandler::query_denom_weights(deps, start_after, limit)?),
+        QueryMsg::WeightSchedules {
+            start_after,
+            limit,
+        } => to_json_binary(&QueryHandler::query_weight_schedules(deps, &env, start_after, limit)?),
+        QueryMsg::Tranche {
+            name,
+        } => to_json_binary(&QueryHandler::query_tranche(deps, &env, name)?),
+        QueryMsg::ClaimDelegate {
+            name,
+        } => to_json_binary(&QueryHandler::query_claim_delegate(deps, name)?),
+        QueryMsg::PendingReceiver {
+            name,
+        } => to_json_binary(&QueryHandler::query_pending_receiver(deps, name)?),
+        QueryMsg::DenomFilter {} => to_json_binary(&QueryHandler::query_denom_filter(deps)?),
+        QueryMsg::Quarantine {} => to_json_binary(&QueryHandler::query_quarantine(deps)?),
+        QueryMsg::PendingChanges {
+            start_after,
+            limit,
+        } => to_json_binary(&QueryHandler::query_pending_changes(deps, start_after, limit)?),
+        QueryMsg::SimulateDeposit {
+            funds,
+            flush,
+            sender,
+            cw20,
+            table,
+        } => to_json_binary(&QueryHandler::query_simulate_deposit(
+            deps, &env, funds, cw20, flush, sender, table,
+        )?),
+    }
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn reply(deps: DepsMut, _env: Env, msg: Reply) -> Result<Response, ContractError> {
+    match msg.id {
+        state::REPLY_IBC_TRANSFER => IbcHandler::reply_ibc_transfer(deps, msg),
+        id if id > state::REPLY_PAYOUT => PayoutHandler::reply_payout(deps, msg),
+        id => Err(ContractError::UnknownReplyId {
+            id,
+        }),
+    }
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn sudo(deps: DepsMut, _env: Env, msg: SudoMsg) -> Result<Response, ContractError> {
+    match msg {
+        SudoMsg::IbcLifecycleComplete(lifecycle) => {
+            IbcHandler::sudo_ibc_lifecycle_complete(deps, lifecycle)
+        },
     }
 }
 
@@ -175,6 +483,9 @@ pub fn migrate(mut deps: DepsMut, _env: Env, _msg: MigrateMsg) -> Result<Respons
         },
     }
 
+    // allocation weights moved from u8 to Uint128 (fees_002 -> fees_003)
+    AllocationHoldingV152::migrate_allocation_v152(deps.branch())?;
+
     set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
 
     Ok(Response::new()
@@ -192,9 +503,10 @@ mod tests {
     };
 
     mod instantiate {
-        use cosmwasm_std::{coin, Api, Binary};
+        use cosmwasm_std::{coin, Api, Binary, Uint128};
         use pfc_fee_split::fee_split_msg::{
-            AllocationDetail, AllocationHolding, InitHook, InstantiateMsg, SendType,
+            AllocationDetail, AllocationHolding, AllocationKind, AllocationStatus, InitHook,
+            InstantiateMsg, PayoutMode, SendAfterMode, SendType,
         };
 
         use super::*;
@@ -237,12 +549,26 @@ mod tests {
                 query_allocation(deps.as_ref(), ALLOCATION_1.into()).unwrap().unwrap(),
                 AllocationHolding {
                     name: ALLOCATION_1.to_string(),
-                    allocation: 1,
-                    send_after: coin(1_000u128, DENOM_1),
+                    allocation: Uint128::new(1),
+                    send_after_blocks: None,
+                    send_after_seconds: None,
+                    payout_mode: PayoutMode::Push,
+                    status: AllocationStatus::Active,
+                    kind: AllocationKind::Weighted,
+                    filled: vec![],
+                    period_start: None,
+                    schedule: vec![],
+                    expires: None,
+                    send_after: vec![coin(1_000u128, DENOM_1)],
+                    send_after_mode: SendAfterMode::AnyOf,
                     send_type: SendType::Wallet {
                         receiver: deps.api.addr_validate("allocation_1_addr").unwrap()
                     },
-                    balance: vec![]
+                    balance: vec![],
+                    balance_cw20: vec![],
+                    last_payout_height: Some(mock_env().block.height),
+                    last_payout_time: Some(mock_env().block.time),
+                    payout_error: None,
                 }
             );
             let instantiate_no_allocation_msg = InstantiateMsg {
@@ -278,24 +604,39 @@ mod tests {
                 allocation: vec![
                     AllocationDetail {
                         name: ALLOCATION_1.to_string(),
-                        allocation: 1,
-                        send_after: coin(1_000u128, DENOM_1),
+                        allocation: Uint128::new(1),
+                        send_after_blocks: None,
+                        send_after_seconds: None,
+                        payout_mode: PayoutMode::Push,
+                        kind: AllocationKind::Weighted,
+                        send_after: vec![coin(1_000u128, DENOM_1)],
+                        send_after_mode: SendAfterMode::AnyOf,
                         send_type: SendType::Wallet {
                             receiver: deps.api.addr_validate("allocation_1_addr").unwrap(),
                         },
                     },
                     AllocationDetail {
                         name: ALLOCATION_2.to_string(),
-                        allocation: 1,
-                        send_after: coin(10_000_000u128, DENOM_1),
+                        allocation: Uint128::new(1),
+                        send_after_blocks: None,
+                        send_after_seconds: None,
+                        payout_mode: PayoutMode::Push,
+                        kind: AllocationKind::Weighted,
+                        send_after: vec![coin(10_000_000u128, DENOM_1)],
+                        send_after_mode: SendAfterMode::AnyOf,
                         send_type: SendType::Wallet {
                             receiver: deps.api.addr_validate("allocation_2_addr").unwrap(),
                         },
                     },
                     AllocationDetail {
                         name: ALLOCATION_1.to_string(),
-                        allocation: 3,
-                        send_after: coin(10_000_000u128, DENOM_1),
+                        allocation: Uint128::new(3),
+                        send_after_blocks: None,
+                        send_after_seconds: None,
+                        payout_mode: PayoutMode::Push,
+                        kind: AllocationKind::Weighted,
+                        send_after: vec![coin(10_000_000u128, DENOM_1)],
+                        send_after_mode: SendAfterMode::AnyOf,
                         send_type: SendType::Wallet {
                             receiver: deps.api.addr_validate("allocation_3_addr").unwrap(),
                         },
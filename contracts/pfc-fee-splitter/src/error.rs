@@ -47,6 +47,10 @@ pub enum ContractError {
     KeyNotFound {
         key: String,
     },
+    #[error("PFC-FeeSplit: {kind} can't be blank")]
+    BlankName {
+        kind: String,
+    },
 
     #[error("PFC-FeeSplit: No fees are defined. Add one before sending deposits")]
     NoFeesError {},
@@ -56,6 +60,9 @@ pub enum ContractError {
     #[error("PFC-FeeSplit: Allocation has to be greater than zero")]
     AllocationZero {},
 
+    #[error("PFC-FeeSplit: send_after can only have one coin per denom")]
+    SendAfterNotUnique {},
+
     #[error("PFC-FeeSplit: Contract can't be migrated! {current_name:?} {current_version:?}")]
     MigrationError {
         current_name: String,
@@ -63,4 +70,78 @@ pub enum ContractError {
     },
     #[error("PFC-FeeSplit: Reconcile should not be sent funds")]
     ReconcileWithFunds {},
+
+    #[error("PFC-FeeSplit: IBC transfer reply without a queued transfer")]
+    IbcTransferNotQueued {},
+
+    #[error("PFC-FeeSplit: Table not found - {table:?}")]
+    TableNotFound {
+        table: String,
+    },
+
+    #[error("PFC-FeeSplit: Pending change not found - {id}")]
+    ChangeNotFound {
+        id: u64,
+    },
+
+    #[error("PFC-FeeSplit: Change {id} can't be applied until height {execute_after}")]
+    ChangeTimelocked {
+        id: u64,
+        execute_after: u64,
+    },
+
+    #[error("PFC-FeeSplit: Change delay can be at most {max} blocks, not {blocks}")]
+    ChangeDelayTooLong {
+        blocks: u64,
+        max: u64,
+    },
+
+    #[error("PFC-FeeSplit: Allocation {name} has no failed payout to retry")]
+    NoFailedPayout {
+        name: String,
+    },
+
+    #[error("PFC-FeeSplit: Allocation {name} is {status}, and can't be paid out")]
+    AllocationNotActive {
+        name: String,
+        status: String,
+    },
+
+    #[error("PFC-FeeSplit: Invalid allocation kind {kind} - {reason}")]
+    InvalidAllocationKind {
+        kind: String,
+        reason: String,
+    },
+
+    #[error("PFC-FeeSplit: Need at least one weighted allocation for what is left to go to")]
+    NoWeightedAllocation {},
+
+    #[error("PFC-FeeSplit: Invalid denom filter - {reason}")]
+    InvalidDenomFilter {
+        reason: String,
+    },
+
+    #[error("PFC-FeeSplit: Deposits can send at most {max} denoms, not {count}")]
+    TooManyDenoms {
+        count: usize,
+        max: u32,
+    },
+
+    #[error("PFC-FeeSplit: At least one weighted allocation must stay active")]
+    NoActiveWeightedAllocation {},
+
+    #[error("PFC-FeeSplit: Allocation {name} has no receiver change to accept")]
+    NoPendingReceiver {
+        name: String,
+    },
+
+    #[error("PFC-FeeSplit: Pull allocations need a receiver on this chain to claim - {send_type}")]
+    PullWithoutReceiver {
+        send_type: String,
+    },
+
+    #[error("PFC-FeeSplit: Unknown reply id {id}")]
+    UnknownReplyId {
+        id: u64,
+    },
 }
@@ -1,23 +1,40 @@
-use std::{collections::HashMap, iter::FromIterator, ops::Mul, str::FromStr};
+use std::{
+    collections::{HashMap, HashSet},
+    iter::FromIterator,
+};
 
 use cosmwasm_std::{
-    to_json_binary, Addr, AllBalanceResponse, BankMsg, BankQuery, Coin, CosmosMsg, Decimal,
-    DepsMut, Env, MessageInfo, Order, QuerierWrapper, QueryRequest, Response, StdResult, Uint128,
-    WasmMsg,
+    from_json, to_json_binary, Addr, AllBalanceResponse, BankMsg, BankQuery, Coin, CosmosMsg, Deps,
+    DepsMut, Env, MessageInfo, Order, QuerierWrapper, QueryRequest, Response, StdResult, Storage,
+    SubMsg, Timestamp, Uint128, Uint256, WasmMsg,
+};
+use cw20::{Cw20CoinVerified, Cw20ExecuteMsg, Cw20ReceiveMsg};
+use cw_storage_plus::Map;
+use pfc_fee_split::fee_split_msg::{
+    validate_ibc_memo, AllocationDetail, AllocationHolding, AllocationKind, AllocationStatus,
+    AllocationWeight, BalancePolicy, Cw20HookMsg, PayoutMode, PendingIbcTransfer, ScheduleAt,
+    SendAfterMode, SendType, WeightChange,
 };
-use pfc_fee_split::fee_split_msg::{AllocationHolding, SendType};
-use pfc_steak::hub::Cw20HookMsg;
+use pfc_steak::hub::Cw20HookMsg as SteakCw20HookMsg;
 
 use crate::{
     error::ContractError,
-    state::{ADMIN, ALLOCATION_HOLDINGS, CONFIG, FLUSH_WHITELIST},
+    handler::{filter, group, payout, stats},
+    lst,
+    proto::{MsgFundCommunityPool, MsgTransfer},
+    state::{
+        ADMIN, ALLOCATION_HOLDINGS, CLAIM_DELEGATES, CONFIG, DENOM_WEIGHTS, FLUSH_WHITELIST,
+        IBC_TRANSFER_QUEUE, PENDING_RECEIVERS, QUARANTINE, REPLY_IBC_TRANSFER, SENDER_TABLES,
+        TABLES,
+    },
 };
 
 pub fn execute_deposit(
     deps: DepsMut,
-    _env: Env,
+    env: Env,
     info: MessageInfo,
     flush: bool,
+    table: Option<String>,
 ) -> Result<Response, ContractError> {
     if info.funds.is_empty() && !flush {
         // sometimes funds are empty.
@@ -31,9 +48,107 @@ pub fn execute_deposit(
 
         return Ok(res);
     }
+    assert_flush_allowed(deps.as_ref(), &info.sender, flush)?;
+    let table = deposit_table(deps.as_ref(), &info.sender, table)?;
+    filter::assert_denom_count(deps.storage, info.funds.len())?;
+
+    let mut funds_in: HashMap<String, Uint128> =
+        HashMap::from_iter(info.funds.iter().map(|c| (c.denom.clone(), c.amount)));
+    let filtered = filter::filter_funds(deps.storage, &mut funds_in, false, true)?;
+
+    let mut res =
+        Response::new().add_attribute("action", "deposit").add_attribute("from", &info.sender);
+    if let Some(table) = &table {
+        res = res.add_attribute("table", table);
+    }
+    let res = filtered.add_to(res, &info.sender, false)?;
+    if funds_in.is_empty() && !flush {
+        return Ok(res.add_attribute("no-action", "no allowed denoms sent, and flush false"));
+    }
+    let msgs = do_deposit(deps, &env, funds_in, HashMap::default(), flush, table.as_deref())?;
+
+    Ok(res.add_submessages(msgs))
+}
+
+/// CW20 tokens arrive here via the token contract's 'send'.
+/// info.sender is the token contract, the cw20_msg.sender is who sent them
+pub fn execute_receive(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    cw20_msg: Cw20ReceiveMsg,
+) -> Result<Response, ContractError> {
+    match from_json(&cw20_msg.msg)? {
+        Cw20HookMsg::Deposit {
+            flush,
+            table,
+        } => {
+            let sender = deps.api.addr_validate(&cw20_msg.sender)?;
+            assert_flush_allowed(deps.as_ref(), &sender, flush)?;
+            let table = deposit_table(deps.as_ref(), &sender, table)?;
+            filter::assert_denom_count(deps.storage, 1)?;
+
+            let mut cw20_in: HashMap<String, Uint128> =
+                HashMap::from([(info.sender.to_string(), cw20_msg.amount)]);
+            let filtered = filter::filter_funds(deps.storage, &mut cw20_in, true, true)?;
+
+            let mut res = Response::new()
+                .add_attribute("action", "deposit_cw20")
+                .add_attribute("from", &sender)
+                .add_attribute("token", info.sender)
+                .add_attribute("amount", cw20_msg.amount);
+            if let Some(table) = &table {
+                res = res.add_attribute("table", table);
+            }
+            let res = filtered.add_to(res, &sender, true)?;
+            if cw20_in.is_empty() && !flush {
+                return Ok(res.add_attribute("no-action", "token not allowed, and flush false"));
+            }
+            let msgs =
+                do_deposit(deps, &env, HashMap::default(), cw20_in, flush, table.as_deref())?;
+
+            Ok(res.add_submessages(msgs))
+        },
+    }
+}
+
+/// picks the table a deposit is split with. only whitelisted senders can ask for one,
+/// everyone else gets the table set for them (if any)
+pub(crate) fn deposit_table(
+    deps: Deps,
+    sender: &Addr,
+    table: Option<String>,
+) -> Result<Option<String>, ContractError> {
+    match table {
+        Some(table) => {
+            if !FLUSH_WHITELIST.contains(deps.storage, sender.clone())
+                && !ADMIN.is_admin(deps, sender)?
+            {
+                return Err(ContractError::Unauthorized {
+                    action: "sender is not on whitelist".to_string(),
+                    expected: "table:null".to_string(),
+                    actual: format!("table:{}", table),
+                });
+            }
+            if !TABLES.has(deps.storage, &table) {
+                return Err(ContractError::TableNotFound {
+                    table,
+                });
+            }
+            Ok(Some(table))
+        },
+        None => Ok(SENDER_TABLES.may_load(deps.storage, sender)?),
+    }
+}
+
+pub(crate) fn assert_flush_allowed(
+    deps: Deps,
+    sender: &Addr,
+    flush: bool,
+) -> Result<(), ContractError> {
     if flush
-        && !FLUSH_WHITELIST.contains(deps.storage, info.sender.clone())
-        && !ADMIN.is_admin(deps.as_ref(), &info.sender)?
+        && !FLUSH_WHITELIST.contains(deps.storage, sender.clone())
+        && !ADMIN.is_admin(deps, sender)?
     {
         return Err(ContractError::Unauthorized {
             action: "sender is not on whitelist".to_string(),
@@ -41,48 +156,48 @@ pub fn execute_deposit(
             actual: "flush:true".to_string(),
         });
     }
-
-    let funds_in: HashMap<String, Uint128> =
-        HashMap::from_iter(info.funds.iter().map(|c| (c.denom.clone(), c.amount)));
-    let msgs = do_deposit(deps, funds_in, flush)?;
-
-    let res = Response::new()
-        .add_attribute("action", "deposit")
-        .add_attribute("from", info.sender)
-        .add_messages(msgs);
-
-    Ok(res)
+    Ok(())
 }
 
-pub fn execute_add_allocation_detail(
+pub(crate) fn execute_add_allocation_detail(
     deps: DepsMut,
     env: Env,
     info: MessageInfo,
     name: String,
     // contract_unverified: String,
-    allocation: u8,
-    send_after: Coin,
+    allocation: Uint128,
+    send_after: Vec<Coin>,
+    send_after_mode: SendAfterMode,
     send_type_unverified: SendType,
+    send_after_blocks: Option<u64>,
+    send_after_seconds: Option<u64>,
+    payout_mode: PayoutMode,
+    kind: AllocationKind,
 ) -> Result<Response, ContractError> {
-    ADMIN.assert_admin(deps.as_ref(), &info.sender)?;
     //let contract = deps.api.addr_validate(contract_unverified.as_str())?;
-    send_type_unverified.verify_details(&deps, &env.contract.address)?;
-
-    if allocation == 0 {
-        return Err(ContractError::AllocationZero {});
-    }
-
-    if send_after.denom.trim().is_empty() {
-        return Err(ContractError::InvalidCoin {
-            coin: send_after,
-        });
-    }
+    validate_allocation(
+        &deps,
+        &env,
+        &AllocationDetail {
+            name: name.clone(),
+            allocation,
+            send_after: send_after.clone(),
+            send_after_mode,
+            send_type: send_type_unverified.clone(),
+            send_after_blocks,
+            send_after_seconds,
+            payout_mode,
+            kind: kind.clone(),
+        },
+    )?;
 
     if ALLOCATION_HOLDINGS.has(deps.storage, name.clone()) {
         return Err(ContractError::FeeAlreadyThere {
             name,
         });
     }
+    // make sure the new weights still add up
+    get_total_weight(&deps)?.checked_add(allocation)?;
     ALLOCATION_HOLDINGS.save(
         deps.storage,
         name.clone(),
@@ -90,89 +205,149 @@ pub fn execute_add_allocation_detail(
             name: name.clone(),
             send_type: send_type_unverified.clone(),
             send_after: send_after.clone(),
+            send_after_mode,
             allocation,
 
             balance: vec![],
+            balance_cw20: vec![],
+            send_after_blocks,
+            send_after_seconds,
+            payout_mode,
+            status: AllocationStatus::Active,
+            kind,
+            filled: vec![],
+            period_start: None,
+            schedule: vec![],
+            expires: None,
+            last_payout_height: Some(env.block.height),
+            last_payout_time: Some(env.block.time),
+            payout_error: None,
         },
     )?;
+
     let res = Response::new()
         .add_attribute("action", "add_fee_detail")
         .add_attribute("from", info.sender)
         .add_attribute("name", name)
         .add_attribute("allocation", format!("{}", allocation))
-        .add_attribute("send_after", send_after.to_string())
+        .add_attribute("send_after", coins_to_string(&send_after))
         .add_attribute("send_type", send_type_unverified.to_string());
     Ok(res)
 }
 
-pub fn execute_modify_allocation_detail(
+pub(crate) fn execute_modify_allocation_detail(
     deps: DepsMut,
     env: Env,
     info: MessageInfo,
     name: String,
-    allocation: u8,
-    send_after: Coin,
+    allocation: Uint128,
+    send_after: Vec<Coin>,
+    send_after_mode: SendAfterMode,
     send_type_unverified: SendType,
+    send_after_blocks: Option<u64>,
+    send_after_seconds: Option<u64>,
+    payout_mode: PayoutMode,
+    kind: AllocationKind,
 ) -> Result<Response, ContractError> {
-    ADMIN.assert_admin(deps.as_ref(), &info.sender)?;
-
-    //send_type_unverified.verify(deps.api)?;
-    send_type_unverified.verify_details(&deps, &env.contract.address)?;
+    validate_allocation(
+        &deps,
+        &env,
+        &AllocationDetail {
+            name: name.clone(),
+            allocation,
+            send_after: send_after.clone(),
+            send_after_mode,
+            send_type: send_type_unverified.clone(),
+            send_after_blocks,
+            send_after_seconds,
+            payout_mode,
+            kind: kind.clone(),
+        },
+    )?;
+    let mut fee_holding =
+        ALLOCATION_HOLDINGS.may_load(deps.storage, name.clone())?.ok_or_else(|| {
+            ContractError::KeyNotFound {
+                key: name.to_string(),
+            }
+        })?;
+    // make sure the new weights still add up
+    let counted = if is_weighted(&fee_holding) {
+        fee_holding.allocation
+    } else {
+        Uint128::zero()
+    };
+    get_total_weight(&deps)?.checked_sub(counted)?.checked_add(allocation)?;
 
-    if allocation == 0 {
-        return Err(ContractError::AllocationZero {});
+    fee_holding.send_type = send_type_unverified.clone();
+    fee_holding.send_after = send_after.clone();
+    fee_holding.send_after_mode = send_after_mode;
+    fee_holding.allocation = allocation;
+    fee_holding.send_after_blocks = send_after_blocks;
+    fee_holding.send_after_seconds = send_after_seconds;
+    fee_holding.payout_mode = payout_mode;
+    // a new kind starts from nothing
+    if fee_holding.kind != kind {
+        fee_holding.filled = vec![];
+        fee_holding.period_start = None;
     }
-
-    ALLOCATION_HOLDINGS.update(deps.storage, name.clone(), |rec| -> Result<_, ContractError> {
-        if let Some(mut fee_holding) = rec {
-            fee_holding.send_type = send_type_unverified.clone();
-            fee_holding.send_after = send_after.clone();
-            fee_holding.allocation = allocation;
-            Ok(fee_holding)
-        } else {
-            Err(ContractError::KeyNotFound {
-                key: name.to_string(),
-            })
-        }
-    })?;
+    fee_holding.kind = kind;
+    validate_active_weighted(deps.storage, &name, Some(&fee_holding))?;
+    ALLOCATION_HOLDINGS.save(deps.storage, name.clone(), &fee_holding)?;
 
     let res = Response::new()
         .add_attribute("action", "modify_fee_detail")
         .add_attribute("from", info.sender)
         .add_attribute("name", name)
         .add_attribute("allocation", format!("{}", allocation))
-        .add_attribute("send_after", send_after.to_string())
+        .add_attribute("send_after", coins_to_string(&send_after))
         .add_attribute("send_type", send_type_unverified.to_string());
 
     Ok(res)
 }
 
-pub fn execute_remove_allocation_detail(
+pub(crate) fn execute_remove_allocation_detail(
     deps: DepsMut,
-    _env: Env,
+    env: Env,
     info: MessageInfo,
     name: String,
 ) -> Result<Response, ContractError> {
-    ADMIN.assert_admin(deps.as_ref(), &info.sender)?;
-
     if ALLOCATION_HOLDINGS.keys(deps.storage, None, None, Order::Ascending).count() <= 1 {
         return Err(ContractError::NoFeesError {});
     }
-    if let Some(fee_holding) = ALLOCATION_HOLDINGS.may_load(deps.storage, name.clone())? {
-        let balances =
-            fee_holding.balance.into_iter().filter(|f| f.amount > Uint128::zero()).collect();
+    if let Some(mut fee_holding) = ALLOCATION_HOLDINGS.may_load(deps.storage, name.clone())? {
+        validate_active_weighted(deps.storage, &name, None)?;
         ALLOCATION_HOLDINGS.remove(deps.storage, name.clone());
+        forget_allocation(deps.storage, &name)?;
 
         let res = Response::new()
             .add_attribute("action", "remove_fee_detail")
             .add_attribute("from", info.sender)
-            .add_attribute("fee", name);
+            .add_attribute("fee", &name);
 
-        if let Some(msg) = generate_cosmos_msg(fee_holding.send_type, balances)? {
-            Ok(res.add_message(msg))
+        // what it can't pay out goes to the rest instead: everything, for a cw4 group with no
+        // weight, and CW20 tokens for send types that don't take them
+        let mut pool: HashMap<String, Uint128> = HashMap::new();
+        let mut pool_cw20: HashMap<String, Uint128> = HashMap::new();
+        let msgs = if group::has_weight(&deps.querier, &fee_holding.send_type)? {
+            pool_unsent_cw20(deps.storage, &name, &mut fee_holding, &mut pool_cw20)?;
+            payout_holding(deps.storage, &deps.querier, &env, &name, fee_holding)?
         } else {
-            Ok(res)
+            pool_holding(deps.storage, &name, &fee_holding, &mut pool, &mut pool_cw20)?;
+            vec![]
+        };
+        if pool.is_empty() && pool_cw20.is_empty() {
+            return Ok(res.add_submessages(msgs));
         }
+        let mut redistributed =
+            pool.iter().chain(&pool_cw20).map(|(d, a)| Coin::new(a.u128(), d)).collect::<Vec<_>>();
+        redistributed.sort_by(|a, b| a.denom.cmp(&b.denom));
+        let remaining = ALLOCATION_HOLDINGS
+            .range(deps.storage, None, None, Order::Ascending)
+            .collect::<StdResult<Vec<_>>>()?;
+        credit_pool(deps.storage, remaining, &pool, &pool_cw20)?;
+        Ok(res
+            .add_attribute("redistributed", coins_to_string(&redistributed))
+            .add_submessages(msgs))
     } else {
         Err(ContractError::AllocationNotFound {
             name,
@@ -180,6 +355,515 @@ pub fn execute_remove_allocation_detail(
     }
 }
 
+/// replaces every allocation in one go. 'balance_policy' says what happens to what the current
+/// allocations hold
+pub(crate) fn execute_replace_allocations(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    allocations: Vec<AllocationDetail>,
+    balance_policy: BalancePolicy,
+) -> Result<Response, ContractError> {
+    validate_allocations(&deps, &env, &allocations)?;
+    let names: HashSet<String> = allocations.iter().map(|a| a.name.clone()).collect();
+
+    let current = ALLOCATION_HOLDINGS
+        .range(deps.storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<_>>>()?;
+    // allocations that stay keep their status, so check a weighted one is left active up front
+    let active_weighted = allocations.iter().any(|row| {
+        row.kind == AllocationKind::Weighted
+            && current
+                .iter()
+                .find(|(name, _)| name == &row.name)
+                .map_or(true, |(_, h)| is_active(h))
+    });
+    if !active_weighted {
+        return Err(ContractError::NoActiveWeightedAllocation {});
+    }
+    let mut msgs: Vec<SubMsg> = vec![];
+    let mut kept: HashMap<String, AllocationHolding> = HashMap::new();
+    let mut pool: HashMap<String, Uint128> = HashMap::new();
+    let mut pool_cw20: HashMap<String, Uint128> = HashMap::new();
+    let mut removed = 0u32;
+    for (name, holding) in current {
+        ALLOCATION_HOLDINGS.remove(deps.storage, name.clone());
+        let stays = names.contains(&name);
+        if !stays {
+            forget_allocation(deps.storage, &name)?;
+            removed += 1;
+        }
+        match balance_policy {
+            BalancePolicy::CarryOver if stays => {
+                kept.insert(name, holding);
+            },
+            BalancePolicy::Redistribute => {
+                pool_holding(deps.storage, &name, &holding, &mut pool, &mut pool_cw20)?;
+                if stays {
+                    kept.insert(name, holding);
+                }
+            },
+            // a cw4 group with no weight has no one to pay, so what it holds is pooled instead
+            _ if !group::has_weight(&deps.querier, &holding.send_type)? => {
+                pool_holding(deps.storage, &name, &holding, &mut pool, &mut pool_cw20)?;
+            },
+            _ => {
+                let mut holding = holding;
+                pool_unsent_cw20(deps.storage, &name, &mut holding, &mut pool_cw20)?;
+                msgs.extend(payout_holding(deps.storage, &deps.querier, &env, &name, holding)?)
+            },
+        }
+    }
+
+    let mut replacements = Vec::with_capacity(allocations.len());
+    for row in allocations {
+        let previous = kept.remove(&row.name);
+        let (balance, balance_cw20) = match (&balance_policy, &previous) {
+            (BalancePolicy::CarryOver, Some(previous)) => {
+                (previous.balance.clone(), previous.balance_cw20.clone())
+            },
+            _ => (vec![], vec![]),
+        };
+        // allocations that stay keep their payout clock, status & schedule (and what they've
+        // been given, if they are still the same kind). new ones start out active
+        let (last_payout_height, last_payout_time) = match &previous {
+            Some(previous) => (previous.last_payout_height, previous.last_payout_time),
+            None => (Some(env.block.height), Some(env.block.time)),
+        };
+        let (status, schedule, expires) = match &previous {
+            Some(previous) => {
+                (previous.status, previous.schedule.clone(), previous.expires.clone())
+            },
+            None => (AllocationStatus::Active, vec![], None),
+        };
+        let (filled, period_start) = match previous {
+            Some(previous) if previous.kind == row.kind => (previous.filled, previous.period_start),
+            _ => (vec![], None),
+        };
+        replacements.push((
+            row.name.clone(),
+            AllocationHolding {
+                name: row.name,
+                allocation: row.allocation,
+                send_after: row.send_after,
+                send_after_mode: row.send_after_mode,
+                send_type: row.send_type,
+                balance,
+                balance_cw20,
+                send_after_blocks: row.send_after_blocks,
+                send_after_seconds: row.send_after_seconds,
+                payout_mode: row.payout_mode,
+                status,
+                kind: row.kind,
+                filled,
+                period_start,
+                schedule,
+                expires,
+                last_payout_height,
+                last_payout_time,
+                payout_error: None,
+            },
+        ));
+    }
+
+    credit_pool(deps.storage, replacements, &pool, &pool_cw20)?;
+
+    let res = Response::new()
+        .add_attribute("action", "replace_allocations")
+        .add_attribute("from", info.sender)
+        .add_attribute("balance_policy", balance_policy.to_string())
+        .add_attribute("allocations", names_to_string(&names))
+        .add_attribute("removed", removed.to_string());
+    Ok(res.add_submessages(msgs))
+}
+
+/// checks an allocation being added or changed
+pub(crate) fn validate_allocation(
+    deps: &DepsMut,
+    env: &Env,
+    detail: &AllocationDetail,
+) -> Result<(), ContractError> {
+    detail.send_type.verify_details(deps, &env.contract.address)?;
+    validate_kind(&detail.kind)?;
+    // fixed & capped allocations don't use their weight
+    if detail.kind == AllocationKind::Weighted && detail.allocation.is_zero() {
+        return Err(ContractError::AllocationZero {});
+    }
+    // someone has to be able to claim it
+    if detail.payout_mode == PayoutMode::Pull && detail.send_type.local_receiver().is_none() {
+        return Err(ContractError::PullWithoutReceiver {
+            send_type: detail.send_type.to_string(),
+        });
+    }
+    validate_send_after(&detail.send_after)
+}
+
+/// checks a whole set of allocations, for ReplaceAllocations
+pub(crate) fn validate_allocations(
+    deps: &DepsMut,
+    env: &Env,
+    allocations: &[AllocationDetail],
+) -> Result<(), ContractError> {
+    if allocations.is_empty() {
+        return Err(ContractError::NoFeesError {});
+    }
+    let names: HashSet<&String> = allocations.iter().map(|a| &a.name).collect();
+    if names.len() != allocations.len() {
+        return Err(ContractError::FundAllocationNotUnique {});
+    }
+    if !allocations.iter().any(|a| a.kind == AllocationKind::Weighted) {
+        return Err(ContractError::NoWeightedAllocation {});
+    }
+    let mut total_weight = Uint128::zero();
+    for row in allocations {
+        validate_allocation(deps, env, row)?;
+        // fixed & capped allocations don't use their weight (as in get_total_weight)
+        if row.kind == AllocationKind::Weighted {
+            total_weight = total_weight.checked_add(row.allocation)?;
+        }
+    }
+    Ok(())
+}
+
+/// fixed & capped allocations need something to fill, and fixed ones a period
+pub(crate) fn validate_kind(kind: &AllocationKind) -> Result<(), ContractError> {
+    let invalid = |reason: &str| ContractError::InvalidAllocationKind {
+        kind: kind.to_string(),
+        reason: reason.to_string(),
+    };
+    if let AllocationKind::Fixed {
+        period: 0,
+        ..
+    } = kind
+    {
+        return Err(invalid("period must be more than 0 seconds"));
+    }
+    if *kind == AllocationKind::Weighted {
+        return Ok(());
+    }
+    let limit = kind.limit();
+    if limit.is_empty() || limit.iter().any(|c| c.amount.is_zero() || c.denom.trim().is_empty()) {
+        return Err(invalid("needs an amount of at least one denom"));
+    }
+    let denoms: HashSet<&String> = limit.iter().map(|c| &c.denom).collect();
+    if denoms.len() != limit.len() {
+        return Err(invalid("each denom can only be listed once"));
+    }
+    Ok(())
+}
+
+fn names_to_string(names: &HashSet<String>) -> String {
+    let mut names = names.iter().map(|n| n.as_str()).collect::<Vec<_>>();
+    names.sort();
+    names.join(",")
+}
+
+/// adds what an allocation holds to the pool, to be split again by credit_pool
+fn pool_holding(
+    storage: &mut dyn Storage,
+    name: &str,
+    holding: &AllocationHolding,
+    pool: &mut HashMap<String, Uint128>,
+    pool_cw20: &mut HashMap<String, Uint128>,
+) -> Result<(), ContractError> {
+    let held_cw20 = cw20_as_coins(&holding.balance_cw20);
+    // it gets counted again as it is re-split
+    stats::unrecord_deposit(storage, name, &holding.balance)?;
+    stats::unrecord_deposit(storage, name, &held_cw20)?;
+    for coin in &holding.balance {
+        *pool.entry(coin.denom.clone()).or_default() += coin.amount;
+    }
+    for coin in held_cw20 {
+        *pool_cw20.entry(coin.denom).or_default() += coin.amount;
+    }
+    Ok(())
+}
+
+/// CW20 tokens an allocation can't send (see sends_cw20) are added to the pool as it is removed,
+/// rather than being left in the contract with no one holding them
+fn pool_unsent_cw20(
+    storage: &mut dyn Storage,
+    name: &str,
+    holding: &mut AllocationHolding,
+    pool_cw20: &mut HashMap<String, Uint128>,
+) -> Result<(), ContractError> {
+    if sends_cw20(&holding.send_type) {
+        return Ok(());
+    }
+    let held_cw20 = cw20_as_coins(&std::mem::take(&mut holding.balance_cw20));
+    stats::unrecord_deposit(storage, name, &held_cw20)?;
+    for coin in held_cw20 {
+        *pool_cw20.entry(coin.denom).or_default() += coin.amount;
+    }
+    Ok(())
+}
+
+/// splits the pooled balances over the allocations the way a deposit would be (per-denom
+/// weights included), and saves them. they are held (thresholds aren't checked)
+fn credit_pool(
+    storage: &mut dyn Storage,
+    allocation_holdings: Vec<(String, AllocationHolding)>,
+    pool: &HashMap<String, Uint128>,
+    pool_cw20: &HashMap<String, Uint128>,
+) -> Result<(), ContractError> {
+    let (excluded, weights) = default_weights(&allocation_holdings, &HashSet::new());
+    let shares = split_funds(storage, &weights, &excluded, None, pool)?;
+    let shares_cw20 = split_funds(storage, &weights, &excluded, None, pool_cw20)?;
+    for (name, mut holding) in allocation_holdings {
+        if let Some(share) = shares.get(&name) {
+            stats::record_deposit(storage, &name, share)?;
+            holding.balance = merge_balance(&holding.balance, share);
+        }
+        if let Some(share_cw20) = shares_cw20.get(&name) {
+            stats::record_deposit(storage, &name, share_cw20)?;
+            holding.balance_cw20 =
+                coins_as_cw20(merge_balance(&cw20_as_coins(&holding.balance_cw20), share_cw20));
+        }
+        ALLOCATION_HOLDINGS.save(storage, name, &holding)?;
+    }
+    Ok(())
+}
+
+/// pays out everything an allocation holds, as it is removed
+fn payout_holding(
+    storage: &mut dyn Storage,
+    querier: &QuerierWrapper,
+    env: &Env,
+    name: &str,
+    holding: AllocationHolding,
+) -> Result<Vec<SubMsg>, ContractError> {
+    let balances: Vec<Coin> =
+        holding.balance.into_iter().filter(|f| f.amount > Uint128::zero()).collect();
+    record_ibc_transfers(storage, name, &holding.send_type, &balances)?;
+    let mut paid = balances.clone();
+    if sends_cw20(&holding.send_type) {
+        paid.extend(cw20_as_coins(&holding.balance_cw20));
+    }
+    stats::record_payout(storage, name, &paid, env.block.height)?;
+    let msgs =
+        generate_payout_msgs(querier, env, &holding.send_type, balances, holding.balance_cw20)?;
+    payout::track_payouts(storage, env, name, msgs)
+}
+
+/// drops a removed allocation from the denom weights & tables
+fn forget_allocation(storage: &mut dyn Storage, name: &str) -> Result<(), ContractError> {
+    for denom in remove_from_weights(storage, &DENOM_WEIGHTS, name)? {
+        DENOM_WEIGHTS.remove(storage, &denom);
+    }
+    for table in remove_from_weights(storage, &TABLES, name)? {
+        remove_table(storage, &table)?;
+    }
+    CLAIM_DELEGATES.prefix(name).clear(storage, None);
+    PENDING_RECEIVERS.remove(storage, name);
+    Ok(())
+}
+
+/// takes an allocation out of a set of weights (denom weights or tables).
+/// returns the keys left with nothing to split to, for the caller to remove
+fn remove_from_weights(
+    storage: &mut dyn Storage,
+    weights_map: &Map<&str, Vec<AllocationWeight>>,
+    name: &str,
+) -> Result<Vec<String>, ContractError> {
+    let all_weights =
+        weights_map.range(storage, None, None, Order::Ascending).collect::<StdResult<Vec<_>>>()?;
+    let mut emptied = vec![];
+    for (key, weights) in all_weights {
+        if !weights.iter().any(|w| w.name == name) {
+            continue;
+        }
+        let weights: Vec<AllocationWeight> =
+            weights.into_iter().filter(|w| w.name != name).collect();
+        if weights.iter().all(|w| w.allocation.is_zero()) {
+            emptied.push(key);
+        } else {
+            weights_map.save(storage, &key, &weights)?;
+        }
+    }
+    Ok(emptied)
+}
+
+/// checks a denom's own weights before they are set
+pub(crate) fn validate_denom_weights(
+    deps: &DepsMut,
+    denom: &str,
+    weights: &[AllocationWeight],
+) -> Result<(), ContractError> {
+    if denom.trim().is_empty() {
+        return Err(ContractError::BlankName {
+            kind: "denom".to_string(),
+        });
+    }
+    validate_weights(deps, weights)
+}
+
+/// checks a table before it is set
+pub(crate) fn validate_table(
+    deps: &DepsMut,
+    table: &str,
+    weights: &[AllocationWeight],
+) -> Result<(), ContractError> {
+    if table.trim().is_empty() {
+        return Err(ContractError::BlankName {
+            kind: "table".to_string(),
+        });
+    }
+    validate_weights(deps, weights)
+}
+
+fn validate_weights(deps: &DepsMut, weights: &[AllocationWeight]) -> Result<(), ContractError> {
+    let names: HashSet<&String> = weights.iter().map(|w| &w.name).collect();
+    if names.len() != weights.len() {
+        return Err(ContractError::FundAllocationNotUnique {});
+    }
+    for weight in weights {
+        if !ALLOCATION_HOLDINGS.has(deps.storage, weight.name.clone()) {
+            return Err(ContractError::AllocationNotFound {
+                name: weight.name.clone(),
+            });
+        }
+    }
+    let total = weights.iter().try_fold(Uint128::zero(), |acc, w| acc.checked_add(w.allocation))?;
+    if total.is_zero() {
+        return Err(ContractError::AllocationZero {});
+    }
+    Ok(())
+}
+
+fn weights_to_string(weights: &[AllocationWeight]) -> String {
+    weights.iter().map(|w| format!("{}:{}", w.name, w.allocation)).collect::<Vec<_>>().join(",")
+}
+
+pub(crate) fn execute_set_denom_weights(
+    deps: DepsMut,
+    info: MessageInfo,
+    denom: String,
+    weights: Vec<AllocationWeight>,
+) -> Result<Response, ContractError> {
+    validate_denom_weights(&deps, &denom, &weights)?;
+    DENOM_WEIGHTS.save(deps.storage, &denom, &weights)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "set_denom_weights")
+        .add_attribute("from", info.sender)
+        .add_attribute("denom", denom)
+        .add_attribute("weights", weights_to_string(&weights)))
+}
+
+pub(crate) fn execute_clear_denom_weights(
+    deps: DepsMut,
+    info: MessageInfo,
+    denom: String,
+) -> Result<Response, ContractError> {
+    assert_denom_weights_exist(deps.storage, &denom)?;
+    DENOM_WEIGHTS.remove(deps.storage, &denom);
+
+    Ok(Response::new()
+        .add_attribute("action", "clear_denom_weights")
+        .add_attribute("from", info.sender)
+        .add_attribute("denom", denom))
+}
+
+pub(crate) fn execute_set_table(
+    deps: DepsMut,
+    info: MessageInfo,
+    table: String,
+    weights: Vec<AllocationWeight>,
+) -> Result<Response, ContractError> {
+    validate_table(&deps, &table, &weights)?;
+    TABLES.save(deps.storage, &table, &weights)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "set_table")
+        .add_attribute("from", info.sender)
+        .add_attribute("table", table)
+        .add_attribute("weights", weights_to_string(&weights)))
+}
+
+pub(crate) fn execute_remove_table(
+    deps: DepsMut,
+    info: MessageInfo,
+    table: String,
+) -> Result<Response, ContractError> {
+    assert_table_exists(deps.storage, &table)?;
+    remove_table(deps.storage, &table)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "remove_table")
+        .add_attribute("from", info.sender)
+        .add_attribute("table", table))
+}
+
+pub(crate) fn assert_denom_weights_exist(
+    storage: &dyn Storage,
+    denom: &str,
+) -> Result<(), ContractError> {
+    if !DENOM_WEIGHTS.has(storage, denom) {
+        return Err(ContractError::KeyNotFound {
+            key: denom.to_string(),
+        });
+    }
+    Ok(())
+}
+
+pub(crate) fn assert_table_exists(storage: &dyn Storage, table: &str) -> Result<(), ContractError> {
+    if !TABLES.has(storage, table) {
+        return Err(ContractError::TableNotFound {
+            table: table.to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// senders using the table go back to the default weights
+fn remove_table(storage: &mut dyn Storage, table: &str) -> Result<(), ContractError> {
+    TABLES.remove(storage, table);
+    let senders = SENDER_TABLES
+        .range(storage, None, None, Order::Ascending)
+        .filter(|item| item.as_ref().map(|(_, t)| t == table).unwrap_or(true))
+        .map(|item| item.map(|(sender, _)| sender))
+        .collect::<StdResult<Vec<Addr>>>()?;
+    for sender in senders {
+        SENDER_TABLES.remove(storage, &sender);
+    }
+    Ok(())
+}
+
+/// checks a sender's table before it is set
+pub(crate) fn validate_sender_table(
+    deps: &DepsMut,
+    sender: &str,
+    table: Option<&str>,
+) -> Result<Addr, ContractError> {
+    if let Some(table) = table {
+        assert_table_exists(deps.storage, table)?;
+    }
+    Ok(deps.api.addr_validate(sender)?)
+}
+
+pub(crate) fn execute_set_sender_table(
+    deps: DepsMut,
+    info: MessageInfo,
+    sender: String,
+    table: Option<String>,
+) -> Result<Response, ContractError> {
+    let sender = validate_sender_table(&deps, &sender, table.as_deref())?;
+    let res = Response::new()
+        .add_attribute("action", "set_sender_table")
+        .add_attribute("from", info.sender)
+        .add_attribute("sender", &sender);
+    match table {
+        Some(table) => {
+            SENDER_TABLES.save(deps.storage, &sender, &table)?;
+            Ok(res.add_attribute("table", table))
+        },
+        None => {
+            SENDER_TABLES.remove(deps.storage, &sender);
+            Ok(res.add_attribute("table", "default"))
+        },
+    }
+}
+
 pub fn execute_add_flush_whitelist(
     deps: DepsMut,
     _env: Env,
@@ -238,7 +922,11 @@ pub fn execute_reconcile(
     if keys.is_empty() {
         return Err(ContractError::NoFeesError {});
     }
+    // only native balances are re-assigned. CW20 balances are left as-is
     for key in keys {
+        let old_balance = ALLOCATION_HOLDINGS.load(deps.storage, key.clone())?.balance;
+        // it gets counted again as it is re-deposited
+        stats::unrecord_deposit(deps.storage, &key, &old_balance)?;
         ALLOCATION_HOLDINGS.update(deps.storage, key.clone(), |rec| {
             if let Some(mut record) = rec.clone() {
                 record.balance = vec![];
@@ -250,35 +938,175 @@ pub fn execute_reconcile(
             }
         })?;
     }
-    let funds = get_native_balances(&deps.querier, env.contract.address)?;
-    if funds.is_empty() {
-        return Ok(Response::new()
-            .add_attribute("action", "reconcile")
-            .add_attribute("info", "no funds. clearing balances"));
+    // the quarantine stays as it is. denoms the filter doesn't take join it
+    let quarantined = QUARANTINE.may_load(deps.storage)?.unwrap_or_default().balance;
+    let mut funds_in: HashMap<String, Uint128> =
+        get_native_balances(&deps.querier, env.contract.address.clone())?
+            .into_iter()
+            .map(|c| {
+                let held = quarantined.iter().find(|q| q.denom == c.denom).map(|q| q.amount);
+                (c.denom, c.amount.saturating_sub(held.unwrap_or_default()))
+            })
+            .filter(|(_, amount)| !amount.is_zero())
+            .collect();
+    let filtered = filter::filter_funds(deps.storage, &mut funds_in, false, false)?;
+    let res = filtered.add_to(
+        Response::new().add_attribute("action", "reconcile"),
+        &info.sender,
+        false,
+    )?;
+    if funds_in.is_empty() {
+        return Ok(res.add_attribute("info", "no funds. clearing balances"));
     }
-    let funds_in: HashMap<String, Uint128> =
-        HashMap::from_iter(funds.iter().map(|c| (c.denom.clone(), c.amount)));
 
-    let msgs = do_deposit(deps, funds_in, false)?;
-    let res = Response::new().add_attribute("action", "reconcile").add_messages(msgs);
-    Ok(res)
+    let msgs = do_deposit(deps, &env, funds_in, HashMap::default(), false, None)?;
+    Ok(res.add_submessages(msgs))
 }
 
-pub fn execute_update_gov_contract(
+/// checks an allocation's status change
+///
+/// returns: the allocation
+pub(crate) fn validate_allocation_status(
+    deps: &DepsMut,
+    name: &str,
+    status: AllocationStatus,
+) -> Result<AllocationHolding, ContractError> {
+    let allocation_holding = ALLOCATION_HOLDINGS
+        .may_load(deps.storage, name.to_string())?
+        .ok_or_else(|| ContractError::AllocationNotFound {
+            name: name.to_string(),
+        })?;
+    if status != AllocationStatus::Active {
+        let changed = AllocationHolding {
+            status,
+            ..allocation_holding.clone()
+        };
+        validate_active_weighted(deps.storage, name, Some(&changed))?;
+    }
+    Ok(allocation_holding)
+}
+
+pub(crate) fn execute_set_allocation_status(
     deps: DepsMut,
-    env: Env,
     info: MessageInfo,
-    gov_contract: String,
-    blocks: u64,
+    name: String,
+    status: AllocationStatus,
 ) -> Result<Response, ContractError> {
-    ADMIN.assert_admin(deps.as_ref(), &info.sender)?;
-    let new_admin = deps.api.addr_validate(&gov_contract)?;
-    let mut config = CONFIG.load(deps.storage)?;
-    config.new_gov_contract = Some(new_admin);
-    config.change_gov_contract_by_height = Some(env.block.height + blocks);
+    let mut allocation_holding = validate_allocation_status(&deps, &name, status)?;
+    allocation_holding.status = status;
+    ALLOCATION_HOLDINGS.save(deps.storage, name.clone(), &allocation_holding)?;
 
-    CONFIG.save(deps.storage, &config)?;
-    let res = Response::new().add_attribute("action", "update_gov_contract");
+    Ok(Response::new()
+        .add_attribute("action", "set_allocation_status")
+        .add_attribute("from", info.sender)
+        .add_attribute("name", name)
+        .add_attribute("status", status.to_string()))
+}
+
+pub(crate) fn execute_set_weight_schedule(
+    deps: DepsMut,
+    info: MessageInfo,
+    name: String,
+    schedule: Vec<WeightChange>,
+    expires: Option<ScheduleAt>,
+) -> Result<Response, ContractError> {
+    validate_schedule(&schedule)?;
+    let mut allocation_holding = ALLOCATION_HOLDINGS
+        .may_load(deps.storage, name.clone())?
+        .ok_or_else(|| ContractError::AllocationNotFound {
+            name: name.clone(),
+        })?;
+    if !schedule.is_empty() && allocation_holding.kind != AllocationKind::Weighted {
+        return Err(ContractError::InvalidAllocationKind {
+            kind: allocation_holding.kind.to_string(),
+            reason: "only weighted allocations have a weight to schedule".to_string(),
+        });
+    }
+    let changes = schedule.len();
+    allocation_holding.schedule = schedule;
+    allocation_holding.expires = expires.clone();
+    validate_active_weighted(deps.storage, &name, Some(&allocation_holding))?;
+    ALLOCATION_HOLDINGS.save(deps.storage, name.clone(), &allocation_holding)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "set_weight_schedule")
+        .add_attribute("from", info.sender)
+        .add_attribute("name", name)
+        .add_attribute("changes", changes.to_string())
+        .add_attribute(
+            "expires",
+            expires.map(|at| at.to_string()).unwrap_or_else(|| "never".into()),
+        ))
+}
+
+/// scheduled weights can't be zero. use an expiry to stop an allocation
+pub(crate) fn validate_schedule(schedule: &[WeightChange]) -> Result<(), ContractError> {
+    if schedule.iter().any(|change| change.weight.is_zero()) {
+        return Err(ContractError::AllocationZero {});
+    }
+    Ok(())
+}
+
+/// splits funds that arrived without a deposit (eg. commission withdrawn to this contract).
+/// only what the allocations (and the quarantine) aren't already holding is split, so anyone can
+/// call it
+pub fn execute_sweep(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+) -> Result<Response, ContractError> {
+    let mut held: HashMap<String, Uint128> = HashMap::new();
+    for item in ALLOCATION_HOLDINGS.range(deps.storage, None, None, Order::Ascending) {
+        for coin in item?.1.balance {
+            *held.entry(coin.denom).or_default() += coin.amount;
+        }
+    }
+    for coin in QUARANTINE.may_load(deps.storage)?.unwrap_or_default().balance {
+        *held.entry(coin.denom).or_default() += coin.amount;
+    }
+    let mut untracked: HashMap<String, Uint128> =
+        get_native_balances(&deps.querier, env.contract.address.clone())?
+            .into_iter()
+            .map(|c| {
+                let amount =
+                    c.amount.saturating_sub(held.get(&c.denom).copied().unwrap_or_default());
+                (c.denom, amount)
+            })
+            .filter(|(_, amount)| !amount.is_zero())
+            .collect();
+
+    let res = Response::new().add_attribute("action", "sweep").add_attribute("from", &info.sender);
+    if untracked.is_empty() {
+        return Ok(res.add_attribute("no-action", "no untracked funds"));
+    }
+    // there is no one to refund
+    let filtered = filter::filter_funds(deps.storage, &mut untracked, false, false)?;
+    let res = filtered.add_to(res, &info.sender, false)?;
+    if untracked.is_empty() {
+        return Ok(res.add_attribute("no-action", "only filtered denoms"));
+    }
+    let mut swept = untracked.iter().map(|(d, a)| Coin::new(a.u128(), d)).collect::<Vec<_>>();
+    swept.sort_by(|a, b| a.denom.cmp(&b.denom));
+
+    let msgs = do_deposit(deps, &env, untracked, HashMap::default(), false, None)?;
+    Ok(res.add_attribute("swept", coins_to_string(&swept)).add_submessages(msgs))
+}
+
+pub fn execute_update_gov_contract(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    gov_contract: String,
+    blocks: u64,
+) -> Result<Response, ContractError> {
+    ADMIN.assert_admin(deps.as_ref(), &info.sender)?;
+    let new_admin = deps.api.addr_validate(&gov_contract)?;
+    let mut config = CONFIG.load(deps.storage)?;
+    config.new_gov_contract = Some(new_admin);
+    config.change_gov_contract_by_height = Some(env.block.height + blocks);
+
+    CONFIG.save(deps.storage, &config)?;
+    let res = Response::new().add_attribute("action", "update_gov_contract");
     Ok(res)
 }
 
@@ -327,10 +1155,166 @@ pub fn execute_accept_gov_contract(
     }
 }
 
-pub(crate) fn get_total_weight(deps: &DepsMut) -> Result<u8, ContractError> {
-    Ok(ALLOCATION_HOLDINGS
-        .range(deps.storage, None, None, Order::Ascending)
-        .fold(0, |acc, x| acc + x.unwrap().1.allocation))
+/// the weight deposits are split over. frozen, fixed & capped allocations don't count
+pub(crate) fn get_total_weight(deps: &DepsMut) -> Result<Uint128, ContractError> {
+    ALLOCATION_HOLDINGS.range(deps.storage, None, None, Order::Ascending).try_fold(
+        Uint128::zero(),
+        |acc, x| -> Result<Uint128, ContractError> {
+            let holding = x?.1;
+            if !is_weighted(&holding) {
+                return Ok(acc);
+            }
+            Ok(acc.checked_add(holding.allocation)?)
+        },
+    )
+}
+
+/// applies the scheduled weight changes that have been reached (the last one wins).
+///
+/// returns: true once the allocation has reached its expiry
+pub(crate) fn apply_schedule(holding: &mut AllocationHolding, env: &Env) -> bool {
+    let (reached, upcoming): (Vec<_>, Vec<_>) = std::mem::take(&mut holding.schedule)
+        .into_iter()
+        .partition(|change| change.at.is_reached(&env.block));
+    if let Some(change) = reached.last() {
+        holding.allocation = change.weight;
+    }
+    holding.schedule = upcoming;
+    holding.expires.as_ref().map(|at| at.is_reached(&env.block)).unwrap_or_default()
+}
+
+/// deposits split what is left over the weighted allocations, so one of them has to stay active.
+/// checks that one does with 'name' changed to 'changed' (or removed, if None)
+pub(crate) fn validate_active_weighted(
+    storage: &dyn Storage,
+    name: &str,
+    changed: Option<&AllocationHolding>,
+) -> Result<(), ContractError> {
+    for item in ALLOCATION_HOLDINGS.range(storage, None, None, Order::Ascending) {
+        let (key, holding) = item?;
+        let holding = match changed {
+            _ if key != name => &holding,
+            Some(changed) => changed,
+            None => continue,
+        };
+        if is_active_weighted(holding) {
+            return Ok(());
+        }
+    }
+    Err(ContractError::NoActiveWeightedAllocation {})
+}
+
+/// can the allocation take what is left of a deposit, and pay it out?
+fn is_active_weighted(holding: &AllocationHolding) -> bool {
+    holding.kind == AllocationKind::Weighted && is_active(holding)
+}
+
+/// an allocation set to expire will go, so it doesn't count
+fn is_active(holding: &AllocationHolding) -> bool {
+    holding.status == AllocationStatus::Active && holding.expires.is_none()
+}
+
+/// does the allocation take part in the weighted split?
+fn is_weighted(holding: &AllocationHolding) -> bool {
+    holding.kind == AllocationKind::Weighted && holding.status != AllocationStatus::Frozen
+}
+
+/// what a fixed or capped allocation has been given so far. a fixed allocation's period rolls
+/// over (to the start of the period the block is in) once 'period' seconds have passed
+///
+/// returns: (start of the current period, what has been given in it)
+pub(crate) fn current_fill(
+    holding: &AllocationHolding,
+    env: &Env,
+) -> (Option<Timestamp>, Vec<Coin>) {
+    let AllocationKind::Fixed {
+        period,
+        ..
+    } = holding.kind
+    else {
+        return (holding.period_start, holding.filled.clone());
+    };
+    match holding.period_start {
+        Some(start) if env.block.time < start.plus_seconds(period) => {
+            (Some(start), holding.filled.clone())
+        },
+        Some(start) => {
+            let elapsed = env.block.time.seconds() - start.seconds();
+            (Some(start.plus_seconds(elapsed - elapsed % period)), vec![])
+        },
+        None => (Some(env.block.time), vec![]),
+    }
+}
+
+/// how much more of each denom a fixed or capped allocation can be given
+pub(crate) fn tranche_room(kind: &AllocationKind, filled: &[Coin]) -> Vec<Coin> {
+    kind.limit()
+        .iter()
+        .map(|limit| {
+            let given = filled
+                .iter()
+                .find(|c| c.denom == limit.denom)
+                .map(|c| c.amount)
+                .unwrap_or_default();
+            Coin {
+                denom: limit.denom.clone(),
+                amount: limit.amount.saturating_sub(given),
+            }
+        })
+        .collect()
+}
+
+/// fills the fixed & capped allocations in priority order (then by name), out of the deposit.
+/// what they take is removed from 'funds_in'/'cw20_in', leaving the rest for the weighted split
+///
+/// returns: (name -> (denom -> share)) for the native coins, and for the CW20 tokens
+#[allow(clippy::type_complexity)]
+fn fill_tranches(
+    allocation_holdings: &mut [(String, AllocationHolding)],
+    env: &Env,
+    expired: &HashSet<String>,
+    funds_in: &mut HashMap<String, Uint128>,
+    cw20_in: &mut HashMap<String, Uint128>,
+) -> (HashMap<String, HashMap<String, Uint128>>, HashMap<String, HashMap<String, Uint128>>) {
+    let mut shares: HashMap<String, HashMap<String, Uint128>> = HashMap::new();
+    let mut shares_cw20: HashMap<String, HashMap<String, Uint128>> = HashMap::new();
+    let mut order = allocation_holdings
+        .iter()
+        .enumerate()
+        .filter(|(_, (name, holding))| {
+            holding.status != AllocationStatus::Frozen && !expired.contains(name)
+        })
+        .filter_map(|(i, (name, holding))| holding.kind.priority().map(|p| (p, name.clone(), i)))
+        .collect::<Vec<_>>();
+    order.sort();
+
+    for (_, name, i) in order {
+        let holding = &mut allocation_holdings[i].1;
+        let (period_start, mut filled) = current_fill(holding, env);
+        for room in tranche_room(&holding.kind, &filled) {
+            // a 'denom' is either a native coin or a CW20 token address
+            let (funds, shares) = if cw20_in.contains_key(&room.denom) {
+                (&mut *cw20_in, &mut shares_cw20)
+            } else {
+                (&mut *funds_in, &mut shares)
+            };
+            let Some(available) = funds.get_mut(&room.denom) else {
+                continue;
+            };
+            let take = room.amount.min(*available);
+            if take.is_zero() {
+                continue;
+            }
+            *available -= take;
+            *shares.entry(name.clone()).or_default().entry(room.denom.clone()).or_default() += take;
+            add_to_balance(&mut filled, Coin::new(take.u128(), room.denom));
+        }
+        holding.period_start = period_start;
+        holding.filled = filled;
+    }
+    funds_in.retain(|_, amount| !amount.is_zero());
+    cw20_in.retain(|_, amount| !amount.is_zero());
+    (shares, shares_cw20)
 }
 
 ///
@@ -340,12 +1324,11 @@ pub(crate) fn get_total_weight(deps: &DepsMut) -> Result<u8, ContractError> {
 ///
 /// returns: Vec<Coin> - amount after deposit
 pub(crate) fn determine_allocation(
-    allocation_amt: u8,
-    total_allocation: u8,
+    allocation_amt: Uint128,
+    total_allocation: Uint128,
     funds_sent: &HashMap<String, Uint128>,
     funds_held: &[Coin],
 ) -> Result<Vec<Coin>, ContractError> {
-    let fraction = Decimal::from_ratio(allocation_amt, total_allocation);
     let funds_sent_alloc: HashMap<String, Uint128> = funds_sent
         .iter()
         .map(|(denom, amount)| {
@@ -353,23 +1336,17 @@ pub(crate) fn determine_allocation(
             if allocation_amt == total_allocation {
                 (denom.clone(), *amount)
             } else {
-                let dec_amt: Decimal = Decimal::from_str(&amount.to_string()).unwrap();
-                let portion = dec_amt.mul(fraction);
-
-                // ignore dust
-                if portion.is_zero() || portion < Decimal::from_ratio(1u32, 10_000u32) {
-                    (denom.clone(), Uint128::zero())
-                } else {
-                    let places = portion.decimal_places();
-                    let portion_u128 =
-                        portion.atomics().checked_div(Uint128::from(10u32).pow(places)).unwrap();
-
-                    (denom.clone(), portion_u128)
-                }
+                // multiply_ratio uses a 256 bit intermediate, and rounds down.
+                (denom.clone(), amount.multiply_ratio(allocation_amt, total_allocation))
             }
         })
         .collect();
 
+    Ok(merge_balance(funds_held, &funds_sent_alloc))
+}
+
+/// adds an allocation's share of a deposit to what it already holds
+fn merge_balance(funds_held: &[Coin], funds_sent_alloc: &HashMap<String, Uint128>) -> Vec<Coin> {
     let bal: HashMap<String, Uint128> = funds_held
         .iter()
         .map(|c| {
@@ -380,74 +1357,569 @@ pub(crate) fn determine_allocation(
             }
         })
         .collect();
-    //eprintln!("bal1 = {:?}", bal);
-    let send_coins = bal
-        .iter()
+    bal.iter()
         .chain(
             funds_sent_alloc.iter().filter(|(denom, _amount)| !bal.contains_key(&(*denom).clone())),
         )
         .map(|(denom, amount)| Coin::new(u128::from(*amount), denom))
-        .collect::<Vec<Coin>>();
+        .collect::<Vec<Coin>>()
+}
 
-    Ok(send_coins)
+/// applies the allocations' scheduled weight changes that have been reached
+///
+/// returns: the allocations that have reached their expiry
+pub(crate) fn apply_schedules(
+    allocation_holdings: &mut [(String, AllocationHolding)],
+    env: &Env,
+) -> HashSet<String> {
+    allocation_holdings
+        .iter_mut()
+        .filter_map(|(name, holding)| apply_schedule(holding, env).then(|| name.clone()))
+        .collect()
 }
 
-pub(crate) fn do_deposit(
-    deps: DepsMut,
-    funds_in: HashMap<String, Uint128>,
-    flush: bool,
-) -> Result<Vec<CosmosMsg>, ContractError> {
-    let total_allocation = get_total_weight(&deps)?;
+/// the allocations left out of the weighted split (frozen, fixed & capped, and expired ones),
+/// and the weights everyone else splits deposits with by default
+pub(crate) fn default_weights(
+    allocation_holdings: &[(String, AllocationHolding)],
+    expired: &HashSet<String>,
+) -> (HashSet<String>, Vec<(String, Uint128)>) {
+    let excluded = allocation_holdings
+        .iter()
+        .filter(|(name, holding)| !is_weighted(holding) || expired.contains(name))
+        .map(|(name, _)| name.clone())
+        .collect::<HashSet<_>>();
+    let weights = allocation_holdings
+        .iter()
+        .filter(|(name, _)| !excluded.contains(name))
+        .map(|(name, holding)| (name.clone(), holding.allocation))
+        .collect::<Vec<_>>();
+    (excluded, weights)
+}
+
+/// the weights a deposit of 'denom' is split with: the table's (which apply to every denom) if
+/// there is one, else the denom's own weights (DENOM_WEIGHTS), else 'default_weights'.
+/// 'excluded' allocations are left out of the table & denom weights too. if that leaves them
+/// with no weight, 'default_weights' are used instead
+///
+/// returns: the weights, and whether they came from a table or the denom's own weights
+pub(crate) fn split_weights(
+    storage: &dyn Storage,
+    default_weights: &[(String, Uint128)],
+    excluded: &HashSet<String>,
+    table: Option<&str>,
+    denom: &str,
+) -> Result<(Vec<(String, Uint128)>, bool), ContractError> {
+    let weights = match table {
+        Some(table) => {
+            Some(TABLES.may_load(storage, table)?.ok_or_else(|| ContractError::TableNotFound {
+                table: table.to_string(),
+            })?)
+        },
+        None => DENOM_WEIGHTS.may_load(storage, denom)?,
+    };
+    let Some(weights) = weights else {
+        return Ok((default_weights.to_vec(), false));
+    };
+    let weights = weights
+        .into_iter()
+        .filter(|w| !excluded.contains(&w.name) && !w.allocation.is_zero())
+        .map(|w| (w.name, w.allocation))
+        .collect::<Vec<_>>();
+    if weights.is_empty() {
+        return Ok((default_weights.to_vec(), false));
+    }
+    Ok((weights, true))
+}
 
-    let mut msgs: Vec<CosmosMsg> = Vec::new();
+///
+/// works out each allocation's share of funds_sent, a denom at a time, with the weights
+/// split_weights picks for it
+///
+/// returns: name -> (denom -> share)
+pub(crate) fn split_funds(
+    storage: &dyn Storage,
+    default_weights: &[(String, Uint128)],
+    excluded: &HashSet<String>,
+    table: Option<&str>,
+    funds_sent: &HashMap<String, Uint128>,
+) -> Result<HashMap<String, HashMap<String, Uint128>>, ContractError> {
+    let mut shares: HashMap<String, HashMap<String, Uint128>> = HashMap::new();
+    for (denom, amount) in funds_sent {
+        let (weights, _) = split_weights(storage, default_weights, excluded, table, denom)?;
+        add_shares(&mut shares, &weights, &HashMap::from([(denom.clone(), *amount)]))?;
+    }
+    Ok(shares)
+}
 
-    let keys =
-        ALLOCATION_HOLDINGS.keys(deps.storage, None, None, Order::Ascending).collect::<Vec<_>>();
-    if keys.is_empty() {
+fn add_shares(
+    shares: &mut HashMap<String, HashMap<String, Uint128>>,
+    weights: &[(String, Uint128)],
+    funds_sent: &HashMap<String, Uint128>,
+) -> Result<(), ContractError> {
+    if funds_sent.is_empty() {
+        return Ok(());
+    }
+    let total_allocation =
+        weights.iter().try_fold(Uint128::zero(), |acc, (_, weight)| acc.checked_add(*weight))?;
+    if total_allocation.is_zero() {
         return Err(ContractError::NoFeesError {});
     }
-    for key in keys {
-        let key_name = key?;
-        let mut allocation_holding = ALLOCATION_HOLDINGS.load(deps.storage, key_name.clone())?;
-
-        let merged_coins = determine_allocation(
-            allocation_holding.allocation,
-            total_allocation,
-            &funds_in,
-            &allocation_holding.balance,
-        )?;
-        if flush {
-            if let Some(new_msg) =
-                generate_cosmos_msg(allocation_holding.send_type.clone(), merged_coins)?
-            {
-                msgs.push(new_msg);
+    let extras = allocate_remainders(weights, total_allocation, funds_sent)?;
+    for (name, weight) in weights {
+        let mut coins = determine_allocation(*weight, total_allocation, funds_sent, &[])?;
+        add_extras(&mut coins, extras.get(name));
+        let share = shares.entry(name.clone()).or_default();
+        for coin in coins {
+            *share.entry(coin.denom).or_default() += coin.amount;
+        }
+    }
+    Ok(())
+}
+
+///
+/// determine_allocation rounds each share down, so a deposit can leave a few units unallocated.
+/// this works out (per denom) what was lost to rounding, and hands it out one unit at a time
+/// to the allocations with the largest remainder. ties go to the allocation first by name.
+/// between determine_allocation and this, the shares always add up to funds_sent
+///
+/// returns: name -> (denom -> extra amount)
+pub(crate) fn allocate_remainders(
+    weights: &[(String, Uint128)],
+    total_allocation: Uint128,
+    funds_sent: &HashMap<String, Uint128>,
+) -> Result<HashMap<String, HashMap<String, Uint128>>, ContractError> {
+    let mut extras: HashMap<String, HashMap<String, Uint128>> = HashMap::new();
+    if total_allocation.is_zero() {
+        return Ok(extras);
+    }
+    for (denom, amount) in funds_sent {
+        let allocated = weights.iter().try_fold(Uint128::zero(), |acc, (_, weight)| {
+            acc.checked_add(amount.multiply_ratio(*weight, total_allocation))
+        })?;
+        let residual = amount.checked_sub(allocated)?;
+        if residual.is_zero() {
+            continue;
+        }
+        let mut remainders = weights
+            .iter()
+            .map(|(name, weight)| {
+                (name, amount.full_mul(*weight) % Uint256::from(total_allocation))
+            })
+            .collect::<Vec<_>>();
+        // stable sort, so equal remainders stay in name order
+        remainders.sort_by_key(|r| std::cmp::Reverse(r.1));
+        // the residual is always smaller than the number of allocations
+        for (name, _) in remainders.into_iter().take(residual.u128() as usize) {
+            let extra = extras.entry(name.clone()).or_default().entry(denom.clone()).or_default();
+            *extra += Uint128::one();
+        }
+    }
+    Ok(extras)
+}
+
+fn add_extras(coins: &mut [Coin], extras: Option<&HashMap<String, Uint128>>) {
+    if let Some(extras) = extras {
+        for coin in coins.iter_mut() {
+            if let Some(extra) = extras.get(&coin.denom) {
+                coin.amount += *extra;
             }
-            allocation_holding.balance = vec![];
-        } else {
-            let check_coin =
-                merged_coins.iter().find(|c| c.denom == allocation_holding.send_after.denom);
-            if let Some(coin) = check_coin {
-                if coin.amount > allocation_holding.send_after.amount {
-                    if let Some(new_msg) =
-                        generate_cosmos_msg(allocation_holding.send_type.clone(), merged_coins)?
-                    {
-                        msgs.push(new_msg);
-                    }
-                    allocation_holding.balance = vec![];
-                } else {
-                    allocation_holding.balance = merged_coins;
-                }
+        }
+    }
+}
+
+/// has enough time passed since the last payout for the allocation to pay out regardless of
+/// its send_after threshold?
+fn payout_time_reached(allocation_holding: &AllocationHolding, env: &Env) -> bool {
+    let by_blocks =
+        match (allocation_holding.send_after_blocks, allocation_holding.last_payout_height) {
+            (Some(blocks), Some(last_height)) => {
+                env.block.height >= last_height.saturating_add(blocks)
+            },
+            _ => false,
+        };
+    let by_seconds =
+        match (allocation_holding.send_after_seconds, allocation_holding.last_payout_time) {
+            (Some(seconds), Some(last_time)) => env.block.time >= last_time.plus_seconds(seconds),
+            _ => false,
+        };
+    by_blocks || by_seconds
+}
+
+/// checks the merged balance against the allocation's send_after coins.
+/// an empty send_after list never triggers a payout by itself
+fn send_after_reached(
+    allocation_holding: &AllocationHolding,
+    merged_coins: &[Coin],
+    merged_cw20: &[Coin],
+) -> bool {
+    if allocation_holding.send_after.is_empty() {
+        return false;
+    }
+    let over_threshold = |threshold: &Coin| {
+        merged_coins
+            .iter()
+            .chain(merged_cw20.iter())
+            .find(|c| c.denom == threshold.denom)
+            .map(|coin| coin.amount > threshold.amount)
+            .unwrap_or_default()
+    };
+    match allocation_holding.send_after_mode {
+        SendAfterMode::AnyOf => allocation_holding.send_after.iter().any(over_threshold),
+        SendAfterMode::AllOf => allocation_holding.send_after.iter().all(over_threshold),
+    }
+}
+
+pub(crate) fn validate_send_after(send_after: &[Coin]) -> Result<(), ContractError> {
+    if let Some(coin) = send_after.iter().find(|c| c.denom.trim().is_empty()) {
+        return Err(ContractError::InvalidCoin {
+            coin: coin.clone(),
+        });
+    }
+    let denoms: HashSet<&String> = send_after.iter().map(|c| &c.denom).collect();
+    if denoms.len() != send_after.len() {
+        return Err(ContractError::SendAfterNotUnique {});
+    }
+    Ok(())
+}
+
+pub(crate) fn coins_to_string(coins: &[Coin]) -> String {
+    coins.iter().map(|c| c.to_string()).collect::<Vec<_>>().join(",")
+}
+
+/// what a deposit does to one allocation
+pub(crate) struct AllocationUpdate {
+    pub name: String,
+    /// the allocation after the deposit (and payout, if there was one)
+    pub holding: AllocationHolding,
+    /// its share of the deposit: denom -> amount
+    pub share: HashMap<String, Uint128>,
+    pub share_cw20: HashMap<String, Uint128>,
+    /// the native coins & CW20 tokens paid out, if it paid out
+    pub payout: Option<(Vec<Coin>, Vec<Coin>)>,
+    /// the messages paying it out
+    pub msgs: Vec<PayoutMsg>,
+    /// it reached its expiry, and is removed
+    pub expired: bool,
+}
+
+/// works out what a deposit would do, without changing anything.
+/// do_deposit applies the result, and the SimulateDeposit query just returns it
+///
+/// returns: the updated allocations (with the messages paying them out)
+pub(crate) fn plan_deposit(
+    storage: &dyn Storage,
+    querier: &QuerierWrapper,
+    env: &Env,
+    funds_in: &HashMap<String, Uint128>,
+    cw20_in: &HashMap<String, Uint128>,
+    flush: bool,
+    table: Option<&str>,
+) -> Result<Vec<AllocationUpdate>, ContractError> {
+    let mut allocation_holdings = ALLOCATION_HOLDINGS
+        .range(storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<_>>>()?;
+    if allocation_holdings.is_empty() {
+        return Err(ContractError::NoFeesError {});
+    }
+    // scheduled weight changes & expiry are applied as they are reached
+    let expired = apply_schedules(&mut allocation_holdings, env);
+    // fixed & capped allocations are filled first. the rest is split by weight
+    let mut funds_in = funds_in.clone();
+    let mut cw20_in = cw20_in.clone();
+    let (tranche_shares, tranche_shares_cw20) =
+        fill_tranches(&mut allocation_holdings, env, &expired, &mut funds_in, &mut cw20_in);
+    let (funds_in, cw20_in) = (&funds_in, &cw20_in);
+
+    let (excluded, weights) = default_weights(&allocation_holdings, &expired);
+    // CW20s go through the same maths, using the token address as the 'denom'
+    let mut shares = split_funds(storage, &weights, &excluded, table, funds_in)?;
+    let mut shares_cw20 = split_funds(storage, &weights, &excluded, table, cw20_in)?;
+    for (all, tranche) in [(&mut shares, tranche_shares), (&mut shares_cw20, tranche_shares_cw20)] {
+        for (name, share) in tranche {
+            let entry = all.entry(name).or_default();
+            for (denom, amount) in share {
+                *entry.entry(denom).or_default() += amount;
+            }
+        }
+    }
+
+    let mut updates = Vec::with_capacity(allocation_holdings.len());
+    for (key_name, mut allocation_holding) in allocation_holdings {
+        let share = shares.remove(&key_name).unwrap_or_default();
+        let share_cw20 = shares_cw20.remove(&key_name).unwrap_or_default();
+        let merged_coins = merge_balance(&allocation_holding.balance, &share);
+        let merged_cw20 =
+            merge_balance(&cw20_as_coins(&allocation_holding.balance_cw20), &share_cw20);
+
+        // pull allocations wait to be claimed, and paused/frozen ones wait to be made active.
+        // an expired allocation pays out what it has when it can
+        let is_expired = expired.contains(&key_name);
+        let mut send_now = allocation_holding.payout_mode == PayoutMode::Push
+            && allocation_holding.status == AllocationStatus::Active
+            && (is_expired
+                || flush
+                || payout_time_reached(&allocation_holding, env)
+                || send_after_reached(&allocation_holding, &merged_coins, &merged_cw20));
+        // a cw4 group with no weight has no one to pay. it holds on to its balance (and isn't
+        // removed if it has expired) until it does
+        if send_now && !group::has_weight(querier, &allocation_holding.send_type)? {
+            send_now = false;
+        }
+
+        let mut msgs = vec![];
+        let payout = if send_now {
+            // tokens that can't go where this allocation sends stay in its balance
+            let (tokens, held_cw20) = if sends_cw20(&allocation_holding.send_type) {
+                (coins_as_cw20(merged_cw20), vec![])
             } else {
-                allocation_holding.balance = merged_coins;
+                (vec![], coins_as_cw20(merged_cw20))
+            };
+            let paid = (merged_coins.clone(), cw20_as_coins(&tokens));
+            msgs = generate_payout_msgs(
+                querier,
+                env,
+                &allocation_holding.send_type,
+                merged_coins,
+                tokens,
+            )?;
+            if !msgs.is_empty() {
+                allocation_holding.last_payout_height = Some(env.block.height);
+                allocation_holding.last_payout_time = Some(env.block.time);
+                // a new payout retries whatever failed before. if it fails, it is set again
+                allocation_holding.payout_error = None;
             }
+            allocation_holding.balance = vec![];
+            allocation_holding.balance_cw20 = held_cw20;
+            Some(paid)
+        } else {
+            allocation_holding.balance = merged_coins;
+            allocation_holding.balance_cw20 = coins_as_cw20(merged_cw20);
+            None
+        };
+        // an expired allocation is removed once it holds nothing. until then it gets no more of
+        // the deposits, and waits to be claimed or made active
+        let has_expired = is_expired
+            && allocation_holding.balance.iter().all(|c| c.amount.is_zero())
+            && allocation_holding.balance_cw20.iter().all(|t| t.amount.is_zero());
+        // allocations from before payout times were tracked start their clock now
+        if allocation_holding.last_payout_height.is_none() {
+            allocation_holding.last_payout_height = Some(env.block.height);
+            allocation_holding.last_payout_time = Some(env.block.time);
+        }
+
+        updates.push(AllocationUpdate {
+            name: key_name,
+            holding: allocation_holding,
+            share,
+            share_cw20,
+            payout,
+            msgs,
+            expired: has_expired,
+        });
+    }
+    Ok(updates)
+}
+
+pub(crate) fn do_deposit(
+    deps: DepsMut,
+    env: &Env,
+    funds_in: HashMap<String, Uint128>,
+    cw20_in: HashMap<String, Uint128>,
+    flush: bool,
+    table: Option<&str>,
+) -> Result<Vec<SubMsg>, ContractError> {
+    let updates =
+        plan_deposit(deps.storage, &deps.querier, env, &funds_in, &cw20_in, flush, table)?;
+
+    let mut msgs: Vec<SubMsg> = Vec::new();
+    for update in updates {
+        stats::record_deposit(deps.storage, &update.name, &update.share)?;
+        stats::record_deposit(deps.storage, &update.name, &update.share_cw20)?;
+        if let Some((coins, tokens)) = update.payout {
+            // same order as the messages, so the replies line up
+            record_ibc_transfers(deps.storage, &update.name, &update.holding.send_type, &coins)?;
+            let paid = coins.into_iter().chain(tokens).collect::<Vec<_>>();
+            stats::record_payout(deps.storage, &update.name, &paid, env.block.height)?;
+        }
+        msgs.extend(payout::track_payouts(deps.storage, env, &update.name, update.msgs)?);
+        if update.expired {
+            ALLOCATION_HOLDINGS.remove(deps.storage, update.name.clone());
+            forget_allocation(deps.storage, &update.name)?;
+        } else {
+            ALLOCATION_HOLDINGS.save(deps.storage, update.name, &update.holding)?;
         }
+    }
+    Ok(msgs)
+}
+
+/// adds a coin to a balance
+pub(crate) fn add_to_balance(balance: &mut Vec<Coin>, coin: Coin) {
+    match balance.iter_mut().find(|c| c.denom == coin.denom) {
+        Some(existing) => existing.amount += coin.amount,
+        None => balance.push(coin),
+    }
+}
+
+pub(crate) fn cw20_as_coins(tokens: &[Cw20CoinVerified]) -> Vec<Coin> {
+    tokens.iter().map(|t| Coin::new(t.amount.u128(), t.address.to_string())).collect()
+}
+
+pub(crate) fn coins_as_cw20(coins: Vec<Coin>) -> Vec<Cw20CoinVerified> {
+    // the 'denoms' here all started life as validated token addresses
+    coins
+        .into_iter()
+        .map(|c| Cw20CoinVerified {
+            address: Addr::unchecked(c.denom),
+            amount: c.amount,
+        })
+        .collect()
+}
 
-        ALLOCATION_HOLDINGS.save(deps.storage, key_name, &allocation_holding)?;
+/// a payout message, and what it carries (so it can be credited back if it fails)
+pub(crate) struct PayoutMsg {
+    pub msg: SubMsg,
+    pub coins: Vec<Coin>,
+    pub tokens: Vec<Cw20CoinVerified>,
+}
+
+/// all the messages required to pay out native coins & CW20 tokens to an allocation
+pub(crate) fn generate_payout_msgs(
+    querier: &QuerierWrapper,
+    env: &Env,
+    send_type: &SendType,
+    coins: Vec<Coin>,
+    tokens: Vec<Cw20CoinVerified>,
+) -> Result<Vec<PayoutMsg>, ContractError> {
+    // each member gets their own messages
+    if let SendType::Cw4Group {
+        group,
+    } = send_type
+    {
+        let members = group::list_members(querier, group)?;
+        return group::group_payout_msgs(&members, coins, tokens);
+    }
+    let coins: Vec<Coin> = coins.into_iter().filter(|c| !c.amount.is_zero()).collect();
+    let mut msgs: Vec<PayoutMsg> = match send_type {
+        SendType::IbcTransfer {
+            ..
+        } => generate_ibc_msgs(env, send_type, coins.clone())?
+            .into_iter()
+            .zip(coins)
+            .map(|(msg, coin)| PayoutMsg {
+                msg,
+                coins: vec![coin],
+                tokens: vec![],
+            })
+            .collect(),
+        _ => generate_cosmos_msg(env, send_type.clone(), coins.clone())?
+            .into_iter()
+            .map(|msg| PayoutMsg {
+                msg: SubMsg::new(msg),
+                coins: coins.clone(),
+                tokens: vec![],
+            })
+            .collect(),
+    };
+    // one message per token
+    for token in tokens {
+        for msg in generate_cw20_msgs(send_type, vec![token.clone()])? {
+            msgs.push(PayoutMsg {
+                msg: SubMsg::new(msg),
+                coins: vec![],
+                tokens: vec![token.clone()],
+            });
+        }
     }
     Ok(msgs)
 }
 
+/// ICS20 can't carry CW20 tokens, and the community pool only takes native coins
+pub(crate) fn sends_cw20(send_type: &SendType) -> bool {
+    !matches!(send_type, SendType::IbcTransfer { .. } | SendType::CommunityPool {})
+}
+
+/// the replies to IBC transfers come back in the order they were sent, so the queue order has to
+/// match the order of the messages from generate_payout_msgs
+pub(crate) fn record_ibc_transfers(
+    storage: &mut dyn Storage,
+    name: &str,
+    send_type: &SendType,
+    coins: &[Coin],
+) -> Result<(), ContractError> {
+    if let SendType::IbcTransfer {
+        channel_id,
+        ..
+    } = send_type
+    {
+        for coin in coins.iter().filter(|c| !c.amount.is_zero()) {
+            IBC_TRANSFER_QUEUE.push_back(
+                storage,
+                &PendingIbcTransfer {
+                    name: name.to_string(),
+                    channel_id: channel_id.clone(),
+                    sequence: 0,
+                    coin: coin.clone(),
+                },
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// one transfer per coin. the reply gives us the sequence number to match the ack/timeout against
+fn generate_ibc_msgs(
+    env: &Env,
+    send_type: &SendType,
+    coins: Vec<Coin>,
+) -> Result<Vec<SubMsg>, ContractError> {
+    let SendType::IbcTransfer {
+        channel_id,
+        receiver,
+        timeout_seconds,
+        memo,
+    } = send_type
+    else {
+        return Ok(vec![]);
+    };
+    let memo = ibc_memo(memo.as_deref(), &env.contract.address)?;
+    Ok(coins
+        .into_iter()
+        .map(|coin| {
+            let msg = MsgTransfer {
+                source_port: "transfer".to_string(),
+                source_channel: channel_id.clone(),
+                token: Some(coin.into()),
+                sender: env.contract.address.to_string(),
+                receiver: receiver.clone(),
+                timeout_height: None,
+                timeout_timestamp: env.block.time.plus_seconds(*timeout_seconds).nanos(),
+                memo: memo.clone(),
+            };
+            SubMsg::reply_always(msg, REPLY_IBC_TRANSFER)
+        })
+        .collect())
+}
+
+/// adds the ibc-hooks callback to the memo, so we hear about acks & timeouts.
+/// errors if the memo isn't a JSON object, or has a callback of its own
+pub(crate) fn ibc_memo(memo: Option<&str>, this: &Addr) -> Result<String, ContractError> {
+    let callback = format!("\"ibc_callback\":\"{}\"", this);
+    let Some(memo) = memo else {
+        return Ok(format!("{{{}}}", callback));
+    };
+    validate_ibc_memo(memo)?;
+    // a JSON object, so the callback goes in first
+    let rest = memo.trim().trim_start_matches('{').trim_start();
+    if rest.starts_with('}') {
+        Ok(format!("{{{}}}", callback))
+    } else {
+        Ok(format!("{{{},{}", callback, rest))
+    }
+}
+
 fn generate_cosmos_msg(
+    env: &Env,
     send_type: SendType,
     coins: Vec<Coin>,
 ) -> Result<Option<CosmosMsg>, ContractError> {
@@ -484,7 +1956,7 @@ fn generate_cosmos_msg(
         } => {
             let msg = pfc_steak::hub::ExecuteMsg::Bond {
                 receiver: Some(receiver.to_string()),
-                exec_msg: Some(to_json_binary(&Cw20HookMsg::Distribute {})?),
+                exec_msg: Some(to_json_binary(&SteakCw20HookMsg::Distribute {})?),
             };
             Ok(Some(CosmosMsg::Wasm(WasmMsg::Execute {
                 contract_addr: steak.to_string(),
@@ -498,7 +1970,7 @@ fn generate_cosmos_msg(
         } => {
             let msg = pfc_steak::hub::ExecuteMsg::Bond {
                 receiver: Some(receiver.to_string()),
-                exec_msg: Some(to_json_binary(&Cw20HookMsg::Transfer {})?),
+                exec_msg: Some(to_json_binary(&SteakCw20HookMsg::Transfer {})?),
             };
             Ok(Some(CosmosMsg::Wasm(WasmMsg::Execute {
                 contract_addr: steak.to_string(),
@@ -506,14 +1978,134 @@ fn generate_cosmos_msg(
                 funds: coins,
             })))
         },
+        SendType::Contract {
+            contract,
+            msg,
+        } => Ok(Some(CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: contract.to_string(),
+            msg,
+            funds: coins,
+        }))),
+        SendType::FeeSplit {
+            contract,
+        } => {
+            let msg = pfc_fee_split::fee_split_msg::ExecuteMsg::Deposit {
+                flush: false,
+                table: None,
+            };
+            Ok(Some(CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: contract.to_string(),
+                msg: to_json_binary(&msg)?,
+                funds: coins,
+            })))
+        },
+        SendType::LiquidStake {
+            hub,
+            receiver,
+            protocol,
+        } => Ok(Some(CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: hub.to_string(),
+            msg: lst::bond_msg(protocol, &receiver)?,
+            funds: coins,
+        }))),
+        SendType::Burn {} => Ok(Some(CosmosMsg::Bank(BankMsg::Burn {
+            amount: coins,
+        }))),
+        SendType::CommunityPool {} => Ok(Some(
+            MsgFundCommunityPool {
+                amount: coins.into_iter().map(Into::into).collect(),
+                depositor: env.contract.address.to_string(),
+            }
+            .into(),
+        )),
+        // handled by generate_ibc_msgs
+        SendType::IbcTransfer {
+            ..
+        } => Ok(None),
+        // handled by group::group_payout_msgs
+        SendType::Cw4Group {
+            ..
+        } => Ok(None),
     }
 }
 
-pub(crate) fn get_native_balances(
-    querier: &QuerierWrapper,
-    account_addr: Addr,
-) -> StdResult<Vec<Coin>> {
-    let balances: AllBalanceResponse =
+/// CW20 tokens can't be bonded into a steak hub, so those go directly to the receiver.
+/// contracts get them via 'send', with the allocation's msg as the hook message
+fn generate_cw20_msgs(
+    send_type: &SendType,
+    tokens: Vec<Cw20CoinVerified>,
+) -> Result<Vec<CosmosMsg>, ContractError> {
+    tokens
+        .into_iter()
+        .filter(|t| !t.amount.is_zero())
+        .map(|t| {
+            let cw20_msg = match send_type {
+                SendType::Wallet {
+                    receiver,
+                }
+                | SendType::SteakRewards {
+                    receiver,
+                    ..
+                }
+                | SendType::DistributeSteakRewards {
+                    receiver,
+                    ..
+                }
+                | SendType::TransferSteakRewards {
+                    receiver,
+                    ..
+                }
+                | SendType::LiquidStake {
+                    receiver,
+                    ..
+                } => Cw20ExecuteMsg::Transfer {
+                    recipient: receiver.to_string(),
+                    amount: t.amount,
+                },
+                SendType::Contract {
+                    contract,
+                    msg,
+                } => Cw20ExecuteMsg::Send {
+                    contract: contract.to_string(),
+                    amount: t.amount,
+                    msg: msg.clone(),
+                },
+                SendType::FeeSplit {
+                    contract,
+                } => Cw20ExecuteMsg::Send {
+                    contract: contract.to_string(),
+                    amount: t.amount,
+                    msg: to_json_binary(&Cw20HookMsg::Deposit {
+                        flush: false,
+                        table: None,
+                    })?,
+                },
+                SendType::Burn {} => Cw20ExecuteMsg::Burn {
+                    amount: t.amount,
+                },
+                SendType::IbcTransfer {
+                    ..
+                }
+                | SendType::CommunityPool {}
+                | SendType::Cw4Group {
+                    ..
+                } => return Ok(None),
+            };
+            Ok(Some(CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: t.address.to_string(),
+                msg: to_json_binary(&cw20_msg)?,
+                funds: vec![],
+            })))
+        })
+        .filter_map(Result::transpose)
+        .collect()
+}
+
+pub(crate) fn get_native_balances(
+    querier: &QuerierWrapper,
+    account_addr: Addr,
+) -> StdResult<Vec<Coin>> {
+    let balances: AllBalanceResponse =
         querier.query(&QueryRequest::Bank(BankQuery::AllBalances {
             address: account_addr.to_string(),
         }))?;
@@ -525,38 +2117,47 @@ mod exec_test {
     use cosmwasm_std::{
         coin,
         testing::{mock_dependencies, mock_dependencies_with_balance, mock_env, mock_info},
+        Deps,
+    };
+    use pfc_fee_split::fee_split_msg::{
+        AllocationKind, AllocationStatus, DenomFilterMode, DenomStats, ExecuteMsg,
+        FilteredDenomAction,
     };
-    use pfc_fee_split::fee_split_msg::ExecuteMsg;
 
     use super::*;
     use crate::{
         contract::execute,
-        handler::query::{query_allocation, query_allocations},
+        handler::query::{
+            query_allocation, query_allocation_stats, query_allocations, query_denom_split,
+            query_sender_tables, query_simulate_deposit, query_stats, query_table_allocation,
+            query_table_allocations, query_tranche, query_weight_schedules,
+        },
         test_helpers::{
             do_instantiate, one_allocation, two_allocation, ALLOCATION_1, ALLOCATION_2, CREATOR,
-            DENOM_1, DENOM_2, DENOM_3, GOV_CONTRACT, USER_1,
+            DENOM_1, DENOM_2, DENOM_3, GOV_CONTRACT, TOKEN_1, USER_1,
         },
     };
 
     #[test]
     fn allocations_1() -> Result<(), ContractError> {
-        let zero = determine_allocation(1, 1, &HashMap::default(), &[])?;
+        let zero =
+            determine_allocation(Uint128::new(1), Uint128::new(1), &HashMap::default(), &[])?;
         assert!(zero.is_empty(), "should have been empty");
         let funds: HashMap<String, Uint128> =
             HashMap::from([(DENOM_1.into(), Uint128::from(1_000_000u128))]);
-        let full = determine_allocation(1, 1, &funds, &[])?;
+        let full = determine_allocation(Uint128::new(1), Uint128::new(1), &funds, &[])?;
         assert_eq!(full, vec![coin(1_000_000u128, String::from(DENOM_1))]);
-        let tenth = determine_allocation(1, 10, &funds, &[])?;
+        let tenth = determine_allocation(Uint128::new(1), Uint128::new(10), &funds, &[])?;
         assert_eq!(tenth, vec![coin(100_000, String::from(DENOM_1))]);
-        let third = determine_allocation(1, 3, &funds, &[])?;
+        let third = determine_allocation(Uint128::new(1), Uint128::new(3), &funds, &[])?;
         assert_eq!(third, vec![coin(333_333, String::from(DENOM_1))]);
-        let three_quarters = determine_allocation(3, 4, &funds, &[])?;
+        let three_quarters = determine_allocation(Uint128::new(3), Uint128::new(4), &funds, &[])?;
         assert_eq!(three_quarters, vec![coin(750_000, String::from(DENOM_1))]);
         let funds2: HashMap<String, Uint128> = HashMap::from([
             (DENOM_1.into(), Uint128::from(1_000_000u128)),
             (DENOM_2.into(), Uint128::from(9_000u128)),
         ]);
-        let two_parts = determine_allocation(3, 4, &funds2, &[])?;
+        let two_parts = determine_allocation(Uint128::new(3), Uint128::new(4), &funds2, &[])?;
         assert_eq!(
             two_parts.iter().find(|c| c.denom == DENOM_1).unwrap(),
             &coin(750_000, String::from(DENOM_1))
@@ -576,7 +2177,7 @@ mod exec_test {
             (DENOM_2.into(), Uint128::from(9_000u128)),
         ]);
         let funds_held = vec![coin(100_000, String::from(DENOM_1))];
-        let test_1 = determine_allocation(3, 4, &funds2, &funds_held)?;
+        let test_1 = determine_allocation(Uint128::new(3), Uint128::new(4), &funds2, &funds_held)?;
         assert_eq!(
             test_1.iter().find(|c| c.denom == DENOM_1).unwrap(),
             &coin(850_000, String::from(DENOM_1))
@@ -588,7 +2189,7 @@ mod exec_test {
         let funds_held =
             vec![coin(100_000, String::from(DENOM_1)), coin(20_000, String::from(DENOM_2))];
 
-        let test_2 = determine_allocation(3, 4, &funds2, &funds_held)?;
+        let test_2 = determine_allocation(Uint128::new(3), Uint128::new(4), &funds2, &funds_held)?;
         assert_eq!(
             test_2.iter().find(|c| c.denom == DENOM_1).unwrap(),
             &coin(850_000, String::from(DENOM_1))
@@ -603,7 +2204,7 @@ mod exec_test {
             coin(90_000, String::from(DENOM_3)),
         ];
 
-        let test_3 = determine_allocation(3, 4, &funds2, &funds_held)?;
+        let test_3 = determine_allocation(Uint128::new(3), Uint128::new(4), &funds2, &funds_held)?;
         assert_eq!(
             test_3.iter().find(|c| c.denom == DENOM_1).unwrap(),
             &coin(850_000, String::from(DENOM_1))
@@ -623,115 +2224,1393 @@ mod exec_test {
             (DENOM_3.into(), Uint128::from(9_000u128)),
         ]);
 
-        let test_4 = determine_allocation(3, 4, &funds3, &funds_held_2)?;
-        assert_eq!(
-            test_4.iter().find(|c| c.denom == DENOM_1).unwrap(),
-            &coin(850_000, String::from(DENOM_1))
-        );
-        assert_eq!(
-            test_4.iter().find(|c| c.denom == DENOM_2).unwrap(),
-            &coin(20_000, String::from(DENOM_2))
-        );
+        let test_4 =
+            determine_allocation(Uint128::new(3), Uint128::new(4), &funds3, &funds_held_2)?;
+        assert_eq!(
+            test_4.iter().find(|c| c.denom == DENOM_1).unwrap(),
+            &coin(850_000, String::from(DENOM_1))
+        );
+        assert_eq!(
+            test_4.iter().find(|c| c.denom == DENOM_2).unwrap(),
+            &coin(20_000, String::from(DENOM_2))
+        );
+        assert_eq!(
+            test_4.iter().find(|c| c.denom == DENOM_3).unwrap(),
+            &coin(6_750, String::from(DENOM_3))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn allocations_remainder() -> Result<(), ContractError> {
+        let weights = vec![
+            ("a".to_string(), Uint128::new(1)),
+            ("b".to_string(), Uint128::new(1)),
+            ("c".to_string(), Uint128::new(1)),
+        ];
+        let total = Uint128::new(3);
+        let funds: HashMap<String, Uint128> = HashMap::from([
+            (DENOM_1.into(), Uint128::from(1_000_000u128)),
+            (DENOM_2.into(), Uint128::from(11u128)),
+            (DENOM_3.into(), Uint128::from(9u128)),
+        ]);
+        let extras = allocate_remainders(&weights, total, &funds)?;
+        // 1_000_000 leaves 1 over, 11 leaves 2 over. ties go in name order
+        assert_eq!(extras.get("a").unwrap().get(DENOM_1), Some(&Uint128::one()));
+        assert_eq!(extras.get("a").unwrap().get(DENOM_2), Some(&Uint128::one()));
+        assert_eq!(extras.get("b").unwrap().get(DENOM_1), None);
+        assert_eq!(extras.get("b").unwrap().get(DENOM_2), Some(&Uint128::one()));
+        assert!(!extras.contains_key("c"));
+
+        // the biggest remainder wins. 10 * 1/6 = 1.67, 10 * 2/6 = 3.33, 10 * 3/6 = 5
+        let weights = vec![
+            ("a".to_string(), Uint128::new(1)),
+            ("b".to_string(), Uint128::new(2)),
+            ("c".to_string(), Uint128::new(3)),
+        ];
+        let funds: HashMap<String, Uint128> = HashMap::from([(DENOM_1.into(), Uint128::new(10))]);
+        let extras = allocate_remainders(&weights, Uint128::new(6), &funds)?;
+        assert_eq!(extras.len(), 1);
+        assert_eq!(extras.get("a").unwrap().get(DENOM_1), Some(&Uint128::one()));
+
+        let mut total_sent = Uint128::zero();
+        for (name, weight) in &weights {
+            let mut coins = determine_allocation(*weight, Uint128::new(6), &funds, &[])?;
+            add_extras(&mut coins, extras.get(name));
+            total_sent += coins[0].amount;
+        }
+        assert_eq!(total_sent, Uint128::new(10));
+
+        Ok(())
+    }
+
+    #[test]
+    fn deposit_basic() -> Result<(), ContractError> {
+        let mut deps = mock_dependencies();
+        let alloc = one_allocation(&deps.api);
+        let _res = do_instantiate(deps.as_mut(), CREATOR, alloc)?;
+        let msg = ExecuteMsg::Deposit {
+            flush: false,
+            table: None,
+        };
+        let info = mock_info(USER_1, &[]);
+        let env = mock_env();
+        let res = execute(deps.as_mut(), env.clone(), info.clone(), msg.clone())?;
+
+        assert_eq!(res.messages.len(), 0);
+        assert_eq!(res.attributes.len(), 3);
+        assert_eq!(res.attributes[2].key, "no-action");
+
+        let info_with_funds = mock_info(USER_1, &[coin(1_000_000u128, String::from(DENOM_1))]);
+        let res = execute(deps.as_mut(), env, info_with_funds, msg)?;
+        //eprintln!("{:?}", res.messages[0]);
+        assert_eq!(res.messages.len(), 1);
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "allocation_1_addr".to_string(),
+                amount: vec![coin(1_000_000, DENOM_1)],
+            })
+        );
+
+        assert_eq!(res.attributes.len(), 2);
+        let allocation = query_allocation(deps.as_ref(), ALLOCATION_1.into())?.unwrap();
+        assert!(allocation.balance.is_empty(), "no coins should be present");
+
+        Ok(())
+    }
+
+    #[test]
+    fn deposit_split() -> Result<(), ContractError> {
+        /*
+                eprintln!(
+                    "deposit_split:{}XX",
+                    &to_binary(&Cw20HookMsg::Distribute {})?
+                );
+        */
+        let mut deps = mock_dependencies();
+        let allocs = two_allocation(&deps.api);
+
+        let _res = do_instantiate(deps.as_mut(), CREATOR, allocs)?;
+        let msg = ExecuteMsg::Deposit {
+            flush: false,
+            table: None,
+        };
+        let info = mock_info(USER_1, &[]);
+        let env = mock_env();
+        let res = execute(deps.as_mut(), env.clone(), info.clone(), msg.clone())?;
+
+        assert_eq!(res.messages.len(), 0);
+        assert_eq!(res.attributes.len(), 3);
+        assert_eq!(res.attributes[2].key, "no-action");
+
+        let info_with_funds = mock_info(USER_1, &[coin(50_000_000u128, String::from(DENOM_1))]);
+        let res = execute(deps.as_mut(), env, info_with_funds, msg)?;
+        //eprintln!("{:?}", res.messages[0]);
+        assert_eq!(res.messages.len(), 2);
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "allocation_1_addr".to_string(),
+                amount: vec![coin(25_000_000, DENOM_1)],
+            })
+        );
+        match &res.messages[1].msg {
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr,
+                msg,
+                funds,
+            }) => {
+                assert_eq!(contract_addr, "steak_contract");
+                assert_eq!(funds.len(), 1);
+                assert_eq!(funds[0].amount, Uint128::new(25_000_000));
+                assert_eq!(funds[0].denom, DENOM_1);
+                let expected = to_json_binary(&pfc_steak::hub::ExecuteMsg::Bond {
+                    receiver: Some(String::from("receiver")),
+                    exec_msg: None,
+                })?;
+                assert_eq!(msg, &expected)
+            },
+            _ => {
+                unreachable!("Invalid MSG {:?}", res.messages[1].msg)
+            },
+        }
+
+        assert_eq!(res.attributes.len(), 2);
+        let allocation = query_allocation(deps.as_ref(), ALLOCATION_1.into())?.unwrap();
+        assert!(allocation.balance.is_empty(), "no coins should be present");
+
+        Ok(())
+    }
+
+    #[test]
+    fn deposit_cw20() -> Result<(), ContractError> {
+        let mut deps = mock_dependencies();
+        let allocs = two_allocation(&deps.api);
+        let _res = do_instantiate(deps.as_mut(), CREATOR, allocs)?;
+        let msg = ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: USER_1.to_string(),
+            amount: Uint128::new(1_000_000),
+            msg: to_json_binary(&Cw20HookMsg::Deposit {
+                flush: false,
+                table: None,
+            })?,
+        });
+        let info = mock_info(TOKEN_1, &[]);
+        let env = mock_env();
+        let res = execute(deps.as_mut(), env.clone(), info.clone(), msg)?;
+        // thresholds are in DENOM_1, so nothing is sent yet
+        assert_eq!(res.messages.len(), 0);
+        let allocation = query_allocation(deps.as_ref(), ALLOCATION_1.into())?.unwrap();
+        assert!(allocation.balance.is_empty(), "no native coins should be present");
+        assert_eq!(
+            allocation.balance_cw20,
+            vec![Cw20CoinVerified {
+                address: Addr::unchecked(TOKEN_1),
+                amount: Uint128::new(500_000)
+            }]
+        );
+
+        let msg_flush = ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: USER_1.to_string(),
+            amount: Uint128::new(1_000_000),
+            msg: to_json_binary(&Cw20HookMsg::Deposit {
+                flush: true,
+                table: None,
+            })?,
+        });
+        let err = execute(deps.as_mut(), env.clone(), info.clone(), msg_flush).err().unwrap();
+        match err {
+            ContractError::Unauthorized {
+                ..
+            } => {},
+            _ => panic!("wrong error {:?}", err),
+        }
+
+        let msg_flush = ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: GOV_CONTRACT.to_string(),
+            amount: Uint128::new(1_000_000),
+            msg: to_json_binary(&Cw20HookMsg::Deposit {
+                flush: true,
+                table: None,
+            })?,
+        });
+        let res = execute(deps.as_mut(), env, info, msg_flush)?;
+        assert_eq!(res.messages.len(), 2);
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: TOKEN_1.to_string(),
+                msg: to_json_binary(&Cw20ExecuteMsg::Transfer {
+                    recipient: "allocation_1_addr".to_string(),
+                    amount: Uint128::new(1_000_000),
+                })?,
+                funds: vec![],
+            })
+        );
+        // steak can't bond CW20s, so it goes directly to the receiver
+        assert_eq!(
+            res.messages[1].msg,
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: TOKEN_1.to_string(),
+                msg: to_json_binary(&Cw20ExecuteMsg::Transfer {
+                    recipient: "receiver".to_string(),
+                    amount: Uint128::new(1_000_000),
+                })?,
+                funds: vec![],
+            })
+        );
+        let allocation = query_allocation(deps.as_ref(), ALLOCATION_2.into())?.unwrap();
+        assert!(allocation.balance_cw20.is_empty(), "no tokens should be present");
+
+        Ok(())
+    }
+
+    #[test]
+    fn deposit_large_weights() -> Result<(), ContractError> {
+        let mut deps = mock_dependencies();
+        let mut allocs = two_allocation(&deps.api);
+        allocs[0].allocation = Uint128::new(3_333);
+        allocs[1].allocation = Uint128::new(6_667);
+        let _res = do_instantiate(deps.as_mut(), CREATOR, allocs)?;
+
+        let info = mock_info(USER_1, &[coin(1_000_000u128, DENOM_1)]);
+        let env = mock_env();
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            info,
+            ExecuteMsg::Deposit {
+                flush: false,
+                table: None,
+            },
+        )?;
+        assert_eq!(res.messages.len(), 1);
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "allocation_1_addr".to_string(),
+                amount: vec![coin(333_300, DENOM_1)],
+            })
+        );
+        let allocation = query_allocation(deps.as_ref(), ALLOCATION_2.into())?.unwrap();
+        assert_eq!(allocation.balance, vec![coin(666_700, DENOM_1)]);
+
+        let msg = ExecuteMsg::AddAllocationDetail {
+            name: "too-much".to_string(),
+            allocation: Uint128::MAX,
+            send_after_blocks: None,
+            send_after_seconds: None,
+            payout_mode: PayoutMode::Push,
+            kind: AllocationKind::Weighted,
+            send_after: vec![coin(0u128, DENOM_1)],
+            send_after_mode: SendAfterMode::AnyOf,
+            send_type: SendType::Wallet {
+                receiver: Addr::unchecked("too-much"),
+            },
+        };
+        let err = execute(deps.as_mut(), env, mock_info(GOV_CONTRACT, &[]), msg).err().unwrap();
+        match err {
+            ContractError::Overflow {
+                ..
+            } => {},
+            _ => panic!("wrong error {:?}", err),
+        }
+        assert!(query_allocation(deps.as_ref(), "too-much".into())?.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn deposit_time_trigger() -> Result<(), ContractError> {
+        let mut deps = mock_dependencies();
+        let mut allocs = two_allocation(&deps.api);
+        allocs[1].send_after_blocks = Some(100);
+        let _res = do_instantiate(deps.as_mut(), CREATOR, allocs)?;
+        let msg = ExecuteMsg::Deposit {
+            flush: false,
+            table: None,
+        };
+        let info = mock_info(USER_1, &[coin(1_000_000u128, DENOM_1)]);
+        let mut env = mock_env();
+        let start_height = env.block.height;
+
+        env.block.height = start_height + 50;
+        let res = execute(deps.as_mut(), env.clone(), info.clone(), msg.clone())?;
+        assert_eq!(res.messages.len(), 1);
+        let allocation = query_allocation(deps.as_ref(), ALLOCATION_2.into())?.unwrap();
+        assert_eq!(allocation.balance, vec![coin(500_000, DENOM_1)]);
+        assert_eq!(allocation.last_payout_height, Some(start_height));
+
+        // threshold is 10m, but 100 blocks have passed
+        env.block.height = start_height + 100;
+        env.block.time = env.block.time.plus_seconds(600);
+        let res = execute(deps.as_mut(), env.clone(), info.clone(), msg.clone())?;
+        assert_eq!(res.messages.len(), 2);
+        match &res.messages[1].msg {
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr,
+                funds,
+                ..
+            }) => {
+                assert_eq!(contract_addr, "steak_contract");
+                assert_eq!(funds, &vec![coin(1_000_000, DENOM_1)]);
+            },
+            _ => unreachable!("Invalid MSG {:?}", res.messages[1].msg),
+        }
+        let allocation = query_allocation(deps.as_ref(), ALLOCATION_2.into())?.unwrap();
+        assert!(allocation.balance.is_empty());
+        assert_eq!(allocation.last_payout_height, Some(env.block.height));
+        assert_eq!(allocation.last_payout_time, Some(env.block.time));
+
+        // and the clock restarts
+        env.block.height += 1;
+        let res = execute(deps.as_mut(), env.clone(), info.clone(), msg.clone())?;
+        assert_eq!(res.messages.len(), 1);
+
+        // switch to a seconds based trigger
+        let modify = ExecuteMsg::ModifyAllocationDetail {
+            name: ALLOCATION_2.to_string(),
+            allocation: Uint128::new(1),
+            send_after: vec![coin(10_000_000u128, DENOM_1)],
+            send_after_mode: SendAfterMode::AnyOf,
+            send_type: allocation.send_type,
+            send_after_blocks: None,
+            send_after_seconds: Some(3_600),
+            payout_mode: PayoutMode::Push,
+            kind: AllocationKind::Weighted,
+        };
+        execute(deps.as_mut(), env.clone(), mock_info(GOV_CONTRACT, &[]), modify)?;
+        env.block.height += 1_000;
+        let res = execute(deps.as_mut(), env.clone(), info.clone(), msg.clone())?;
+        assert_eq!(res.messages.len(), 1);
+        env.block.time = env.block.time.plus_seconds(3_600);
+        let res = execute(deps.as_mut(), env, info, msg)?;
+        assert_eq!(res.messages.len(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn deposit_multi_threshold() -> Result<(), ContractError> {
+        let mut deps = mock_dependencies();
+        let mut allocs = one_allocation(&deps.api);
+        allocs[0].send_after = vec![coin(1_000u128, DENOM_1), coin(1_000u128, DENOM_2)];
+        allocs[0].send_after_mode = SendAfterMode::AllOf;
+        let _res = do_instantiate(deps.as_mut(), CREATOR, allocs)?;
+        let msg = ExecuteMsg::Deposit {
+            flush: false,
+            table: None,
+        };
+        let env = mock_env();
+
+        // DENOM_1 is over, but DENOM_2 isn't
+        let info = mock_info(USER_1, &[coin(5_000u128, DENOM_1)]);
+        let res = execute(deps.as_mut(), env.clone(), info, msg.clone())?;
+        assert_eq!(res.messages.len(), 0);
+
+        let info = mock_info(USER_1, &[coin(5_000u128, DENOM_2)]);
+        let res = execute(deps.as_mut(), env.clone(), info, msg.clone())?;
+        assert_eq!(res.messages.len(), 1);
+        match &res.messages[0].msg {
+            CosmosMsg::Bank(BankMsg::Send {
+                amount,
+                ..
+            }) => {
+                assert_eq!(amount.len(), 2);
+                assert!(amount.contains(&coin(5_000u128, DENOM_1)));
+                assert!(amount.contains(&coin(5_000u128, DENOM_2)));
+            },
+            _ => unreachable!("Invalid MSG {:?}", res.messages[0].msg),
+        }
+
+        // any of them will do
+        let allocation = query_allocation(deps.as_ref(), ALLOCATION_1.into())?.unwrap();
+        let modify = ExecuteMsg::ModifyAllocationDetail {
+            name: ALLOCATION_1.to_string(),
+            allocation: allocation.allocation,
+            send_after: allocation.send_after,
+            send_after_mode: SendAfterMode::AnyOf,
+            send_type: allocation.send_type,
+            send_after_blocks: None,
+            send_after_seconds: None,
+            payout_mode: PayoutMode::Push,
+            kind: AllocationKind::Weighted,
+        };
+        execute(deps.as_mut(), env.clone(), mock_info(GOV_CONTRACT, &[]), modify)?;
+        let info = mock_info(USER_1, &[coin(5_000u128, DENOM_2)]);
+        let res = execute(deps.as_mut(), env.clone(), info, msg)?;
+        assert_eq!(res.messages.len(), 1);
+
+        // one threshold per denom
+        let modify = ExecuteMsg::ModifyAllocationDetail {
+            name: ALLOCATION_1.to_string(),
+            allocation: Uint128::new(1),
+            send_after: vec![coin(1_000u128, DENOM_1), coin(2_000u128, DENOM_1)],
+            send_after_mode: SendAfterMode::AnyOf,
+            send_type: SendType::Wallet {
+                receiver: Addr::unchecked("allocation_1_addr"),
+            },
+            send_after_blocks: None,
+            send_after_seconds: None,
+            payout_mode: PayoutMode::Push,
+            kind: AllocationKind::Weighted,
+        };
+        let err = execute(deps.as_mut(), env, mock_info(GOV_CONTRACT, &[]), modify).err().unwrap();
+        match err {
+            ContractError::SendAfterNotUnique {} => {},
+            _ => panic!("wrong error {:?}", err),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn deposit_denom_weights() -> Result<(), ContractError> {
+        let mut deps = mock_dependencies();
+        let alloc = two_allocation(&deps.api);
+        let _res = do_instantiate(deps.as_mut(), CREATOR, alloc)?;
+        let env = mock_env();
+        let weights = vec![AllocationWeight {
+            name: ALLOCATION_1.to_string(),
+            allocation: Uint128::new(1),
+        }];
+
+        let msg = ExecuteMsg::SetDenomWeights {
+            denom: DENOM_2.to_string(),
+            weights: weights.clone(),
+        };
+        let err = execute(deps.as_mut(), env.clone(), mock_info(USER_1, &[]), msg).unwrap_err();
+        match err {
+            ContractError::AdminError(_) => {},
+            _ => panic!("wrong error {:?}", err),
+        }
+        let msg = ExecuteMsg::SetDenomWeights {
+            denom: DENOM_2.to_string(),
+            weights: vec![AllocationWeight {
+                name: "not-there".to_string(),
+                allocation: Uint128::new(1),
+            }],
+        };
+        let err =
+            execute(deps.as_mut(), env.clone(), mock_info(GOV_CONTRACT, &[]), msg).unwrap_err();
+        match err {
+            ContractError::AllocationNotFound {
+                ..
+            } => {},
+            _ => panic!("wrong error {:?}", err),
+        }
+        let msg = ExecuteMsg::SetDenomWeights {
+            denom: " ".to_string(),
+            weights: weights.clone(),
+        };
+        let err =
+            execute(deps.as_mut(), env.clone(), mock_info(GOV_CONTRACT, &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::BlankName { .. }), "wrong error {:?}", err);
+        let msg = ExecuteMsg::SetDenomWeights {
+            denom: DENOM_2.to_string(),
+            weights: weights.clone(),
+        };
+        execute(deps.as_mut(), env.clone(), mock_info(GOV_CONTRACT, &[]), msg)?;
+
+        let split = query_denom_split(deps.as_ref(), &env, DENOM_2.to_string())?;
+        assert!(split.is_override);
+        assert_eq!(split.weights, weights);
+        let split = query_denom_split(deps.as_ref(), &env, DENOM_1.to_string())?;
+        assert!(!split.is_override);
+        assert_eq!(split.weights.len(), 2);
+
+        let msg = ExecuteMsg::Deposit {
+            flush: false,
+            table: None,
+        };
+        let info = mock_info(USER_1, &[coin(1_000_000, DENOM_1), coin(1_001, DENOM_2)]);
+        let res = execute(deps.as_mut(), env.clone(), info, msg)?;
+        assert_eq!(res.messages.len(), 1);
+        match &res.messages[0].msg {
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address,
+                amount,
+            }) => {
+                assert_eq!(to_address, "allocation_1_addr");
+                let mut amount = amount.clone();
+                amount.sort_by(|a, b| a.denom.cmp(&b.denom));
+                assert_eq!(amount, vec![coin(1_001, DENOM_2), coin(500_000, DENOM_1)]);
+            },
+            _ => panic!("wrong message {:?}", res.messages[0].msg),
+        }
+        let alloc_2 = query_allocation(deps.as_ref(), ALLOCATION_2.to_string())?.unwrap();
+        assert_eq!(alloc_2.balance, vec![coin(500_000, DENOM_1)]);
+
+        // removing the only allocation in a denom's weights puts it back on the default split
+        let msg = ExecuteMsg::RemoveAllocationDetail {
+            name: ALLOCATION_1.to_string(),
+        };
+        execute(deps.as_mut(), env.clone(), mock_info(GOV_CONTRACT, &[]), msg)?;
+        assert!(!query_denom_split(deps.as_ref(), &env, DENOM_2.to_string())?.is_override);
+        let msg = ExecuteMsg::ClearDenomWeights {
+            denom: DENOM_2.to_string(),
+        };
+        let err = execute(deps.as_mut(), env, mock_info(GOV_CONTRACT, &[]), msg).unwrap_err();
+        match err {
+            ContractError::KeyNotFound {
+                ..
+            } => {},
+            _ => panic!("wrong error {:?}", err),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn frozen_denom_weights() -> Result<(), ContractError> {
+        let mut deps = mock_dependencies();
+        let alloc = two_allocation(&deps.api);
+        let _res = do_instantiate(deps.as_mut(), CREATOR, alloc)?;
+        let env = mock_env();
+        let gov = mock_info(GOV_CONTRACT, &[]);
+        let weights = vec![AllocationWeight {
+            name: ALLOCATION_1.to_string(),
+            allocation: Uint128::new(1),
+        }];
+        let msg = ExecuteMsg::SetDenomWeights {
+            denom: DENOM_2.to_string(),
+            weights: weights.clone(),
+        };
+        execute(deps.as_mut(), env.clone(), gov.clone(), msg)?;
+        let msg = ExecuteMsg::SetTable {
+            table: "table".to_string(),
+            weights,
+        };
+        execute(deps.as_mut(), env.clone(), gov.clone(), msg)?;
+        let msg = ExecuteMsg::SetAllocationStatus {
+            name: ALLOCATION_1.to_string(),
+            status: AllocationStatus::Frozen,
+        };
+        execute(deps.as_mut(), env.clone(), gov.clone(), msg)?;
+
+        // with allocation_1 frozen, DENOM_2 and the table fall back to the default weights
+        let split = query_denom_split(deps.as_ref(), &env, DENOM_2.to_string())?;
+        assert!(!split.is_override);
+        assert_eq!(split.weights.len(), 1);
+        assert_eq!(split.weights[0].name, ALLOCATION_2);
+        let msg = ExecuteMsg::Deposit {
+            flush: false,
+            table: None,
+        };
+        execute(deps.as_mut(), env.clone(), mock_info(USER_1, &[coin(1_000, DENOM_2)]), msg)?;
+        let msg = ExecuteMsg::Deposit {
+            flush: false,
+            table: Some("table".to_string()),
+        };
+        execute(deps.as_mut(), env, mock_info(GOV_CONTRACT, &[coin(500, DENOM_1)]), msg)?;
+        let alloc_1 = query_allocation(deps.as_ref(), ALLOCATION_1.to_string())?.unwrap();
+        assert!(alloc_1.balance.is_empty());
+        let alloc_2 = query_allocation(deps.as_ref(), ALLOCATION_2.to_string())?.unwrap();
+        let mut balance = alloc_2.balance;
+        balance.sort_by(|a, b| a.denom.cmp(&b.denom));
+        assert_eq!(balance, vec![coin(1_000, DENOM_2), coin(500, DENOM_1)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn denom_split_is_effective() -> Result<(), ContractError> {
+        let mut deps = mock_dependencies();
+        let alloc = two_allocation(&deps.api);
+        let _res = do_instantiate(deps.as_mut(), CREATOR, alloc)?;
+        let mut env = mock_env();
+        let weight = |name: &str, allocation: u128| AllocationWeight {
+            name: name.to_string(),
+            allocation: Uint128::new(allocation),
+        };
+
+        let msg = ExecuteMsg::SetWeightSchedule {
+            name: ALLOCATION_1.to_string(),
+            schedule: vec![WeightChange {
+                at: ScheduleAt::Height(env.block.height + 10),
+                weight: Uint128::new(3),
+            }],
+            expires: None,
+        };
+        execute(deps.as_mut(), env.clone(), mock_info(GOV_CONTRACT, &[]), msg)?;
+        let split = query_denom_split(deps.as_ref(), &env, DENOM_1.to_string())?;
+        assert!(!split.is_override);
+        assert_eq!(split.weights, vec![weight(ALLOCATION_1, 1), weight(ALLOCATION_2, 1)]);
+        // a weight change that has been reached counts before a deposit applies it
+        env.block.height += 10;
+        let split = query_denom_split(deps.as_ref(), &env, DENOM_1.to_string())?;
+        assert_eq!(split.weights, vec![weight(ALLOCATION_1, 3), weight(ALLOCATION_2, 1)]);
+
+        // frozen allocations get nothing, with or without the denom's own weights
+        let msg = ExecuteMsg::SetAllocationStatus {
+            name: ALLOCATION_2.to_string(),
+            status: AllocationStatus::Frozen,
+        };
+        execute(deps.as_mut(), env.clone(), mock_info(GOV_CONTRACT, &[]), msg)?;
+        let split = query_denom_split(deps.as_ref(), &env, DENOM_1.to_string())?;
+        assert_eq!(split.weights, vec![weight(ALLOCATION_1, 3)]);
+        let msg = ExecuteMsg::SetDenomWeights {
+            denom: DENOM_2.to_string(),
+            weights: vec![weight(ALLOCATION_1, 1), weight(ALLOCATION_2, 2)],
+        };
+        execute(deps.as_mut(), env.clone(), mock_info(GOV_CONTRACT, &[]), msg)?;
+        let split = query_denom_split(deps.as_ref(), &env, DENOM_2.to_string())?;
+        assert!(split.is_override);
+        assert_eq!(split.weights, vec![weight(ALLOCATION_1, 1)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn deposit_tables() -> Result<(), ContractError> {
+        let mut deps = mock_dependencies();
+        let alloc = two_allocation(&deps.api);
+        let _res = do_instantiate(deps.as_mut(), CREATOR, alloc)?;
+        let env = mock_env();
+        let gov = mock_info(GOV_CONTRACT, &[]);
+
+        let msg = ExecuteMsg::SetSenderTable {
+            sender: USER_1.to_string(),
+            table: Some("royalties".to_string()),
+        };
+        let err = execute(deps.as_mut(), env.clone(), gov.clone(), msg.clone()).unwrap_err();
+        match err {
+            ContractError::TableNotFound {
+                ..
+            } => {},
+            _ => panic!("wrong error {:?}", err),
+        }
+        let set_table = |table: &str| ExecuteMsg::SetTable {
+            table: table.to_string(),
+            weights: vec![AllocationWeight {
+                name: ALLOCATION_2.to_string(),
+                allocation: Uint128::new(1),
+            }],
+        };
+        let err = execute(deps.as_mut(), env.clone(), gov.clone(), set_table("")).unwrap_err();
+        assert!(matches!(err, ContractError::BlankName { .. }), "wrong error {:?}", err);
+        execute(deps.as_mut(), env.clone(), gov.clone(), set_table("royalties"))?;
+        execute(deps.as_mut(), env.clone(), gov.clone(), msg)?;
+
+        // USER_1 is routed to the table
+        let msg = ExecuteMsg::Deposit {
+            flush: false,
+            table: None,
+        };
+        let info = mock_info(USER_1, &[coin(999, DENOM_1)]);
+        let res = execute(deps.as_mut(), env.clone(), info, msg.clone())?;
+        assert_eq!(res.messages.len(), 0);
+        let alloc_1 = query_allocation(deps.as_ref(), ALLOCATION_1.to_string())?.unwrap();
+        assert!(alloc_1.balance.is_empty());
+        let alloc_2 = query_allocation(deps.as_ref(), ALLOCATION_2.to_string())?.unwrap();
+        assert_eq!(alloc_2.balance, vec![coin(999, DENOM_1)]);
+
+        // everyone else gets the default
+        let info = mock_info("user-0002", &[coin(1_000, DENOM_1)]);
+        execute(deps.as_mut(), env.clone(), info, msg)?;
+        let alloc_1 = query_allocation(deps.as_ref(), ALLOCATION_1.to_string())?.unwrap();
+        assert_eq!(alloc_1.balance, vec![coin(500, DENOM_1)]);
+
+        // only whitelisted senders get to pick
+        let msg = ExecuteMsg::Deposit {
+            flush: false,
+            table: Some("royalties".to_string()),
+        };
+        let info = mock_info("user-0002", &[coin(1_000, DENOM_1)]);
+        let err = execute(deps.as_mut(), env.clone(), info, msg).unwrap_err();
+        match err {
+            ContractError::Unauthorized {
+                ..
+            } => {},
+            _ => panic!("wrong error {:?}", err),
+        }
+        let msg = ExecuteMsg::Deposit {
+            flush: false,
+            table: Some("missing".to_string()),
+        };
+        let info = mock_info(GOV_CONTRACT, &[coin(1_000, DENOM_1)]);
+        let err = execute(deps.as_mut(), env.clone(), info, msg).unwrap_err();
+        match err {
+            ContractError::TableNotFound {
+                ..
+            } => {},
+            _ => panic!("wrong error {:?}", err),
+        }
+
+        assert!(query_table_allocation(
+            deps.as_ref(),
+            "royalties".to_string(),
+            ALLOCATION_1.to_string()
+        )?
+        .is_none());
+        let in_table = query_table_allocations(deps.as_ref(), "royalties".to_string(), None, None)?;
+        assert_eq!(in_table.allocations.len(), 1);
+        assert_eq!(in_table.allocations[0].name, ALLOCATION_2);
+
+        let msg = ExecuteMsg::RemoveTable {
+            table: "royalties".to_string(),
+        };
+        execute(deps.as_mut(), env, gov, msg)?;
+        assert!(query_sender_tables(deps.as_ref(), None, None)?.senders.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn deposit_stats() -> Result<(), ContractError> {
+        let mut deps = mock_dependencies();
+        let alloc = two_allocation(&deps.api);
+        let _res = do_instantiate(deps.as_mut(), CREATOR, alloc)?;
+        let env = mock_env();
+        let msg = ExecuteMsg::Deposit {
+            flush: false,
+            table: None,
+        };
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(USER_1, &[coin(1_000, DENOM_1)]),
+            msg.clone(),
+        )?;
+        execute(deps.as_mut(), env.clone(), mock_info(USER_1, &[coin(2_000, DENOM_1)]), msg)?;
+
+        let stats = query_allocation_stats(deps.as_ref(), ALLOCATION_1.to_string())?;
+        assert_eq!(
+            stats.denoms,
+            vec![DenomStats {
+                denom: DENOM_1.to_string(),
+                deposited: Uint128::new(1_500),
+                paid_out: Uint128::new(1_500),
+                payouts: 1,
+                last_payout_height: Some(env.block.height),
+            }]
+        );
+        let stats = query_allocation_stats(deps.as_ref(), ALLOCATION_2.to_string())?;
+        assert_eq!(
+            stats.denoms,
+            vec![DenomStats {
+                denom: DENOM_1.to_string(),
+                deposited: Uint128::new(1_500),
+                paid_out: Uint128::zero(),
+                payouts: 0,
+                last_payout_height: None,
+            }]
+        );
+
+        let page = query_stats(deps.as_ref(), None, Some(1))?;
+        assert_eq!(page.allocations.len(), 1);
+        assert_eq!(page.allocations[0].name, ALLOCATION_1);
+        let page = query_stats(deps.as_ref(), Some(ALLOCATION_1.to_string()), None)?;
+        assert_eq!(page.allocations.len(), 1);
+        assert_eq!(page.allocations[0].name, ALLOCATION_2);
+
+        // removal pays out what is held, and the stats stay around
+        let msg = ExecuteMsg::RemoveAllocationDetail {
+            name: ALLOCATION_2.to_string(),
+        };
+        execute(deps.as_mut(), env, mock_info(GOV_CONTRACT, &[]), msg)?;
+        let stats = query_allocation_stats(deps.as_ref(), ALLOCATION_2.to_string())?;
+        assert_eq!(stats.denoms[0].paid_out, Uint128::new(1_500));
+        assert_eq!(stats.denoms[0].payouts, 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn simulate_deposit() -> Result<(), ContractError> {
+        let mut deps = mock_dependencies();
+        let allocs = two_allocation(&deps.api);
+        let _res = do_instantiate(deps.as_mut(), CREATOR, allocs)?;
+        let env = mock_env();
+
+        // allocation_1 goes over its threshold, allocation_2 doesn't
+        let sim = query_simulate_deposit(
+            deps.as_ref(),
+            &env,
+            vec![coin(5_000, DENOM_1)],
+            None,
+            false,
+            None,
+            None,
+        )?;
+        assert_eq!(
+            sim.messages,
+            vec![CosmosMsg::Bank(BankMsg::Send {
+                to_address: "allocation_1_addr".to_string(),
+                amount: vec![coin(2_500, DENOM_1)],
+            })]
+        );
+        assert_eq!(sim.allocations.len(), 2);
+        assert!(sim.allocations[0].balance.is_empty());
+        assert_eq!(sim.allocations[1].balance, vec![coin(2_500, DENOM_1)]);
+
+        // nothing changed
+        let allocation = query_allocation(deps.as_ref(), ALLOCATION_2.into())?.unwrap();
+        assert!(allocation.balance.is_empty());
+        assert!(query_stats(deps.as_ref(), None, None)?.allocations.is_empty());
+
+        // and the real thing does the same
+        let info = mock_info(USER_1, &[coin(5_000, DENOM_1)]);
+        let msg = ExecuteMsg::Deposit {
+            flush: false,
+            table: None,
+        };
+        let res = execute(deps.as_mut(), env.clone(), info, msg)?;
+        assert_eq!(res.messages.iter().map(|m| m.msg.clone()).collect::<Vec<_>>(), sim.messages);
+        assert_eq!(query_allocations(deps.as_ref(), None, None)?.allocations, sim.allocations);
+
+        // a flush pays out allocation_2's balance too
+        let sim = query_simulate_deposit(
+            deps.as_ref(),
+            &env,
+            vec![coin(1_000, DENOM_1)],
+            None,
+            true,
+            None,
+            None,
+        )?;
+        assert_eq!(sim.messages.len(), 2);
+        match &sim.messages[1] {
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr,
+                funds,
+                ..
+            }) => {
+                assert_eq!(contract_addr, "steak_contract");
+                assert_eq!(funds, &vec![coin(3_000, DENOM_1)]);
+            },
+            _ => unreachable!("Invalid MSG {:?}", sim.messages[1]),
+        }
+        assert!(sim.allocations.iter().all(|a| a.balance.is_empty()));
+
+        // with no allocations, it fails the same way a deposit does
+        let deps = mock_dependencies();
+        let err = query_simulate_deposit(
+            deps.as_ref(),
+            &env,
+            vec![coin(1_000, DENOM_1)],
+            None,
+            false,
+            None,
+            None,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains(&ContractError::NoFeesError {}.to_string()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn simulate_deposit_as_sender() -> Result<(), ContractError> {
+        let mut deps = mock_dependencies();
+        let allocs = two_allocation(&deps.api);
+        let _res = do_instantiate(deps.as_mut(), CREATOR, allocs)?;
+        let env = mock_env();
+        let gov = mock_info(GOV_CONTRACT, &[]);
+        let msg = ExecuteMsg::SetTable {
+            table: "table".to_string(),
+            weights: vec![AllocationWeight {
+                name: ALLOCATION_1.to_string(),
+                allocation: Uint128::new(1),
+            }],
+        };
+        execute(deps.as_mut(), env.clone(), gov.clone(), msg)?;
+        let msg = ExecuteMsg::SetSenderTable {
+            sender: USER_1.to_string(),
+            table: Some("table".to_string()),
+        };
+        execute(deps.as_mut(), env.clone(), gov.clone(), msg)?;
+        let msg = ExecuteMsg::SetDenomFilter {
+            mode: DenomFilterMode::Deny,
+            denoms: vec![DENOM_2.to_string(), TOKEN_1.to_string()],
+            on_filtered: FilteredDenomAction::Refund,
+            max_denoms: None,
+        };
+        execute(deps.as_mut(), env.clone(), gov, msg)?;
+
+        // USER_1's table sends it all to allocation_1, and DENOM_2 goes back to them
+        let funds = vec![coin(5_000, DENOM_1), coin(7, DENOM_2)];
+        let sim = query_simulate_deposit(
+            deps.as_ref(),
+            &env,
+            funds.clone(),
+            None,
+            false,
+            Some(USER_1.to_string()),
+            None,
+        )?;
+        assert_eq!(sim.refunded, vec![coin(7, DENOM_2)]);
+        assert!(sim.quarantined.is_empty());
+        let msg = ExecuteMsg::Deposit {
+            flush: false,
+            table: None,
+        };
+        let res = execute(deps.as_mut(), env.clone(), mock_info(USER_1, &funds), msg)?;
+        assert_eq!(res.messages.len(), 2);
+        assert_eq!(res.messages.iter().map(|m| m.msg.clone()).collect::<Vec<_>>(), sim.messages);
+        assert_eq!(query_allocations(deps.as_ref(), None, None)?.allocations, sim.allocations);
+
+        // so is a denied token
+        let sim = query_simulate_deposit(
+            deps.as_ref(),
+            &env,
+            vec![],
+            Some(coin(1_000, TOKEN_1)),
+            false,
+            Some(USER_1.to_string()),
+            None,
+        )?;
+        assert_eq!(sim.refunded, vec![coin(1_000, TOKEN_1)]);
+        let msg = ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: USER_1.to_string(),
+            amount: Uint128::new(1_000),
+            msg: to_json_binary(&Cw20HookMsg::Deposit {
+                flush: false,
+                table: None,
+            })?,
+        });
+        let res = execute(deps.as_mut(), env.clone(), mock_info(TOKEN_1, &[]), msg)?;
+        assert_eq!(res.messages.iter().map(|m| m.msg.clone()).collect::<Vec<_>>(), sim.messages);
+        assert_eq!(query_allocations(deps.as_ref(), None, None)?.allocations, sim.allocations);
+
+        // and USER_1 can't flush, or pick a table
+        for (flush, table) in [(true, None), (false, Some("table".to_string()))] {
+            let err = query_simulate_deposit(
+                deps.as_ref(),
+                &env,
+                vec![coin(1_000, DENOM_1)],
+                None,
+                flush,
+                Some(USER_1.to_string()),
+                table,
+            )
+            .unwrap_err();
+            assert!(err.to_string().contains("sender is not on whitelist"), "{}", err);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn sweep_untracked() -> Result<(), ContractError> {
+        let mut deps = mock_dependencies_with_balance(&[Coin::new(50_000, DENOM_1)]);
+        let alloc = two_allocation(&deps.api);
+        let _res = do_instantiate(deps.as_mut(), CREATOR, alloc)?;
+        let env = mock_env();
+
+        // 1,000 of the 50,000 came in via a deposit, and is held 500/500
+        let msg = ExecuteMsg::Deposit {
+            flush: false,
+            table: None,
+        };
+        execute(deps.as_mut(), env.clone(), mock_info(USER_1, &[coin(1_000, DENOM_1)]), msg)?;
+
+        let res =
+            execute(deps.as_mut(), env.clone(), mock_info(USER_1, &[]), ExecuteMsg::Sweep {})?;
+        assert_eq!(res.attributes[2].key, "swept");
+        assert_eq!(res.attributes[2].value, coin(49_000, DENOM_1).to_string());
+        assert_eq!(res.messages.len(), 1);
         assert_eq!(
-            test_4.iter().find(|c| c.denom == DENOM_3).unwrap(),
-            &coin(6_750, String::from(DENOM_3))
+            res.messages[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "allocation_1_addr".to_string(),
+                amount: vec![coin(25_000, DENOM_1)],
+            })
         );
+        // allocation_2 keeps what it had, plus its half of the sweep
+        let allocation = query_allocation(deps.as_ref(), ALLOCATION_2.into())?.unwrap();
+        assert_eq!(allocation.balance, vec![coin(25_000, DENOM_1)]);
+
+        // allocation_1's payout has left, so everything left is tracked
+        deps.querier.update_balance(env.contract.address.clone(), vec![coin(25_000, DENOM_1)]);
+        let res = execute(deps.as_mut(), env, mock_info(USER_1, &[]), ExecuteMsg::Sweep {})?;
+        assert!(res.messages.is_empty());
+        assert_eq!(res.attributes[2].key, "no-action");
+
         Ok(())
     }
 
     #[test]
-    fn deposit_basic() -> Result<(), ContractError> {
+    fn allocation_status() -> Result<(), ContractError> {
         let mut deps = mock_dependencies();
-        let alloc = one_allocation(&deps.api);
+        let alloc = two_allocation(&deps.api);
         let _res = do_instantiate(deps.as_mut(), CREATOR, alloc)?;
-        let msg = ExecuteMsg::Deposit {
+        let env = mock_env();
+        let gov = mock_info(GOV_CONTRACT, &[]);
+        let set_status = |name: &str, status: AllocationStatus| ExecuteMsg::SetAllocationStatus {
+            name: name.to_string(),
+            status,
+        };
+        let deposit = ExecuteMsg::Deposit {
             flush: false,
+            table: None,
         };
-        let info = mock_info(USER_1, &[]);
-        let env = mock_env();
-        let res = execute(deps.as_mut(), env.clone(), info.clone(), msg.clone())?;
+        let info = mock_info(USER_1, &[coin(5_000, DENOM_1)]);
 
-        assert_eq!(res.messages.len(), 0);
-        assert_eq!(res.attributes.len(), 3);
-        assert_eq!(res.attributes[2].key, "no-action");
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(USER_1, &[]),
+            set_status(ALLOCATION_1, AllocationStatus::Paused),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::AdminError(_)), "wrong error {:?}", err);
 
-        let info_with_funds = mock_info(USER_1, &[coin(1_000_000u128, String::from(DENOM_1))]);
-        let res = execute(deps.as_mut(), env, info_with_funds, msg)?;
-        //eprintln!("{:?}", res.messages[0]);
+        // paused: over its threshold, but it only accrues
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            gov.clone(),
+            set_status(ALLOCATION_1, AllocationStatus::Paused),
+        )?;
+        let res = execute(deps.as_mut(), env.clone(), info.clone(), deposit.clone())?;
+        assert!(res.messages.is_empty());
+        let allocation = query_allocation(deps.as_ref(), ALLOCATION_1.into())?.unwrap();
+        assert_eq!(allocation.status, AllocationStatus::Paused);
+        assert_eq!(allocation.balance, vec![coin(2_500, DENOM_1)]);
+
+        // frozen: its weight goes to allocation_2, and it keeps what it has
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            gov.clone(),
+            set_status(ALLOCATION_1, AllocationStatus::Frozen),
+        )?;
+        let res = execute(deps.as_mut(), env.clone(), info.clone(), deposit.clone())?;
+        assert!(res.messages.is_empty());
+        let allocation = query_allocation(deps.as_ref(), ALLOCATION_1.into())?.unwrap();
+        assert_eq!(allocation.balance, vec![coin(2_500, DENOM_1)]);
+        let allocation = query_allocation(deps.as_ref(), ALLOCATION_2.into())?.unwrap();
+        assert_eq!(allocation.balance, vec![coin(7_500, DENOM_1)]);
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("allocation_1_addr", &[]),
+            ExecuteMsg::Claim {
+                name: ALLOCATION_1.to_string(),
+            },
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::AllocationNotActive { .. }), "wrong error {:?}", err);
+
+        // someone has to be left to split to, and pay out
+        for status in [AllocationStatus::Frozen, AllocationStatus::Paused] {
+            let err =
+                execute(deps.as_mut(), env.clone(), gov.clone(), set_status(ALLOCATION_2, status))
+                    .unwrap_err();
+            assert!(
+                matches!(err, ContractError::NoActiveWeightedAllocation {}),
+                "wrong error {:?}",
+                err
+            );
+        }
+
+        // active again, it pays out what it held along with its new share
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            gov,
+            set_status(ALLOCATION_1, AllocationStatus::Active),
+        )?;
+        let res = execute(deps.as_mut(), env, info, deposit)?;
         assert_eq!(res.messages.len(), 1);
         assert_eq!(
             res.messages[0].msg,
             CosmosMsg::Bank(BankMsg::Send {
                 to_address: "allocation_1_addr".to_string(),
-                amount: vec![coin(1_000_000, DENOM_1)],
+                amount: vec![coin(5_000, DENOM_1)],
             })
         );
 
-        assert_eq!(res.attributes.len(), 2);
-        let allocation = query_allocation(deps.as_ref(), ALLOCATION_1.into())?.unwrap();
-        assert!(allocation.balance.is_empty(), "no coins should be present");
+        Ok(())
+    }
+
+    #[test]
+    fn tranches() -> Result<(), ContractError> {
+        let mut deps = mock_dependencies();
+        let alloc = two_allocation(&deps.api);
+        let _res = do_instantiate(deps.as_mut(), CREATOR, alloc)?;
+        let mut env = mock_env();
+        let gov = mock_info(GOV_CONTRACT, &[]);
+        let add = |name: &str, kind: AllocationKind| ExecuteMsg::AddAllocationDetail {
+            name: name.to_string(),
+            // not used by fixed & capped allocations
+            allocation: Uint128::zero(),
+            send_after: vec![coin(1_000_000, DENOM_1)],
+            send_after_mode: SendAfterMode::AnyOf,
+            send_type: SendType::Wallet {
+                receiver: Addr::unchecked(format!("{}_addr", name)),
+            },
+            send_after_blocks: None,
+            send_after_seconds: None,
+            payout_mode: PayoutMode::Push,
+            kind,
+        };
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            gov.clone(),
+            add(
+                "infra",
+                AllocationKind::Fixed {
+                    amount_per_period: vec![coin(1_000, DENOM_1)],
+                    period: 0,
+                    priority: 0,
+                },
+            ),
+        )
+        .unwrap_err();
+        assert!(
+            matches!(err, ContractError::InvalidAllocationKind { .. }),
+            "wrong error {:?}",
+            err
+        );
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            gov.clone(),
+            add(
+                "infra",
+                AllocationKind::Fixed {
+                    amount_per_period: vec![coin(1_000, DENOM_1)],
+                    period: 100,
+                    priority: 0,
+                },
+            ),
+        )?;
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            gov,
+            add(
+                "grant",
+                AllocationKind::Capped {
+                    cap: vec![coin(1_500, DENOM_1)],
+                    priority: 1,
+                },
+            ),
+        )?;
+        let start = env.block.time;
+        let deposit = ExecuteMsg::Deposit {
+            flush: false,
+            table: None,
+        };
+        let info = mock_info(USER_1, &[coin(2_000, DENOM_1)]);
+
+        // the fixed allocation first, then the capped one. nothing is left for the weighted ones
+        let res = execute(deps.as_mut(), env.clone(), info.clone(), deposit.clone())?;
+        assert!(res.messages.is_empty());
+        let balance = |deps: Deps, name: &str| -> StdResult<Vec<Coin>> {
+            Ok(query_allocation(deps, name.into())?.unwrap().balance)
+        };
+        assert_eq!(balance(deps.as_ref(), "infra")?, vec![coin(1_000, DENOM_1)]);
+        assert_eq!(balance(deps.as_ref(), "grant")?, vec![coin(1_000, DENOM_1)]);
+        assert!(balance(deps.as_ref(), ALLOCATION_1)?.is_empty());
+
+        // the fixed allocation is full for this period, and the cap is reached
+        execute(deps.as_mut(), env.clone(), info.clone(), deposit.clone())?;
+        assert_eq!(balance(deps.as_ref(), "infra")?, vec![coin(1_000, DENOM_1)]);
+        assert_eq!(balance(deps.as_ref(), "grant")?, vec![coin(1_500, DENOM_1)]);
+        assert_eq!(balance(deps.as_ref(), ALLOCATION_1)?, vec![coin(750, DENOM_1)]);
+        assert_eq!(balance(deps.as_ref(), ALLOCATION_2)?, vec![coin(750, DENOM_1)]);
+        let tranche = query_tranche(deps.as_ref(), &env, "infra".into())?;
+        assert_eq!(tranche.filled, vec![coin(1_000, DENOM_1)]);
+        assert_eq!(tranche.remaining, vec![coin(0, DENOM_1)]);
+        assert_eq!(tranche.period_ends, Some(start.plus_seconds(100)));
+        let tranche = query_tranche(deps.as_ref(), &env, "grant".into())?;
+        assert_eq!(tranche.filled, vec![coin(1_500, DENOM_1)]);
+        assert_eq!(tranche.period_ends, None);
+        let stats = query_allocation_stats(deps.as_ref(), "grant".into())?;
+        assert_eq!(stats.denoms[0].deposited, Uint128::new(1_500));
+
+        // a new period. the capped allocation stays capped
+        env.block.time = env.block.time.plus_seconds(250);
+        let tranche = query_tranche(deps.as_ref(), &env, "infra".into())?;
+        assert_eq!(tranche.remaining, vec![coin(1_000, DENOM_1)]);
+        let res = execute(deps.as_mut(), env.clone(), info, deposit)?;
+        assert_eq!(balance(deps.as_ref(), "infra")?, vec![coin(2_000, DENOM_1)]);
+        assert_eq!(balance(deps.as_ref(), "grant")?, vec![coin(1_500, DENOM_1)]);
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "allocation_1_addr".to_string(),
+                amount: vec![coin(1_250, DENOM_1)],
+            })
+        );
+        let tranche = query_tranche(deps.as_ref(), &env, "infra".into())?;
+        assert_eq!(tranche.period_ends, Some(start.plus_seconds(300)));
 
         Ok(())
     }
 
     #[test]
-    fn deposit_split() -> Result<(), ContractError> {
-        /*
-                eprintln!(
-                    "deposit_split:{}XX",
-                    &to_binary(&Cw20HookMsg::Distribute {})?
-                );
-        */
+    fn weight_schedule() -> Result<(), ContractError> {
         let mut deps = mock_dependencies();
-        let allocs = two_allocation(&deps.api);
+        let alloc = two_allocation(&deps.api);
+        let _res = do_instantiate(deps.as_mut(), CREATOR, alloc)?;
+        let mut env = mock_env();
+        let start_height = env.block.height;
+        let expires = env.block.time.plus_seconds(1_000);
+        let set_schedule = ExecuteMsg::SetWeightSchedule {
+            name: ALLOCATION_1.to_string(),
+            schedule: vec![WeightChange {
+                at: ScheduleAt::Height(start_height + 10),
+                weight: Uint128::new(3),
+            }],
+            expires: Some(ScheduleAt::Time(expires)),
+        };
+        let err = execute(deps.as_mut(), env.clone(), mock_info(USER_1, &[]), set_schedule.clone())
+            .unwrap_err();
+        assert!(matches!(err, ContractError::AdminError(_)), "wrong error {:?}", err);
+        execute(deps.as_mut(), env.clone(), mock_info(GOV_CONTRACT, &[]), set_schedule)?;
+        // once both expire, deposits have no one to split to
+        let set_expiry = ExecuteMsg::SetWeightSchedule {
+            name: ALLOCATION_2.to_string(),
+            schedule: vec![],
+            expires: Some(ScheduleAt::Time(expires.plus_seconds(1_000))),
+        };
+        let err = execute(deps.as_mut(), env.clone(), mock_info(GOV_CONTRACT, &[]), set_expiry)
+            .unwrap_err();
+        assert!(
+            matches!(err, ContractError::NoActiveWeightedAllocation {}),
+            "wrong error {:?}",
+            err
+        );
 
-        let _res = do_instantiate(deps.as_mut(), CREATOR, allocs)?;
-        let msg = ExecuteMsg::Deposit {
+        // DENOM_2 is under every threshold, so it just accrues
+        let deposit = ExecuteMsg::Deposit {
             flush: false,
+            table: None,
         };
-        let info = mock_info(USER_1, &[]);
-        let env = mock_env();
-        let res = execute(deps.as_mut(), env.clone(), info.clone(), msg.clone())?;
+        let info = mock_info(USER_1, &[coin(4_000, DENOM_2)]);
+        execute(deps.as_mut(), env.clone(), info.clone(), deposit.clone())?;
+        let schedules = query_weight_schedules(deps.as_ref(), &env, None, None)?.schedules;
+        assert_eq!(schedules.len(), 1);
+        assert_eq!(schedules[0].name, ALLOCATION_1);
+        assert_eq!(schedules[0].weight, Uint128::new(1));
+        assert_eq!(schedules[0].schedule.len(), 1);
 
-        assert_eq!(res.messages.len(), 0);
-        assert_eq!(res.attributes.len(), 3);
-        assert_eq!(res.attributes[2].key, "no-action");
+        // reached, but only applied by the next deposit
+        env.block.height = start_height + 10;
+        let schedules = query_weight_schedules(deps.as_ref(), &env, None, None)?.schedules;
+        assert_eq!(schedules[0].weight, Uint128::new(3));
+        assert!(schedules[0].schedule.is_empty());
+        let allocation = query_allocation(deps.as_ref(), ALLOCATION_1.into())?.unwrap();
+        assert_eq!(allocation.allocation, Uint128::new(1));
+        execute(deps.as_mut(), env.clone(), info.clone(), deposit.clone())?;
+        let allocation = query_allocation(deps.as_ref(), ALLOCATION_1.into())?.unwrap();
+        assert_eq!(allocation.allocation, Uint128::new(3));
+        assert_eq!(allocation.balance, vec![coin(5_000, DENOM_2)]);
+        let allocation = query_allocation(deps.as_ref(), ALLOCATION_2.into())?.unwrap();
+        assert_eq!(allocation.balance, vec![coin(3_000, DENOM_2)]);
 
-        let info_with_funds = mock_info(USER_1, &[coin(50_000_000u128, String::from(DENOM_1))]);
-        let res = execute(deps.as_mut(), env, info_with_funds, msg)?;
-        //eprintln!("{:?}", res.messages[0]);
-        assert_eq!(res.messages.len(), 2);
+        // expired: it is paid out and removed, and allocation_2 gets the whole deposit
+        env.block.time = expires;
+        let res = execute(deps.as_mut(), env.clone(), info, deposit)?;
+        assert_eq!(res.messages.len(), 1);
         assert_eq!(
             res.messages[0].msg,
             CosmosMsg::Bank(BankMsg::Send {
                 to_address: "allocation_1_addr".to_string(),
-                amount: vec![coin(25_000_000, DENOM_1)],
+                amount: vec![coin(5_000, DENOM_2)],
             })
         );
-        match &res.messages[1].msg {
-            CosmosMsg::Wasm(WasmMsg::Execute {
-                contract_addr,
-                msg,
-                funds,
+        assert!(query_allocation(deps.as_ref(), ALLOCATION_1.into())?.is_none());
+        let allocation = query_allocation(deps.as_ref(), ALLOCATION_2.into())?.unwrap();
+        assert_eq!(allocation.balance, vec![coin(7_000, DENOM_2)]);
+        assert!(query_weight_schedules(deps.as_ref(), &env, None, None)?.schedules.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn expiry_waits() -> Result<(), ContractError> {
+        let mut deps = mock_dependencies();
+        let alloc = two_allocation(&deps.api);
+        let _res = do_instantiate(deps.as_mut(), CREATOR, alloc)?;
+        let mut env = mock_env();
+        let gov = mock_info(GOV_CONTRACT, &[]);
+        let msg = ExecuteMsg::SetWeightSchedule {
+            name: ALLOCATION_1.to_string(),
+            schedule: vec![],
+            expires: Some(ScheduleAt::Height(env.block.height + 10)),
+        };
+        execute(deps.as_mut(), env.clone(), gov.clone(), msg)?;
+        // allocation_1 is DENOM_2's only weight, and the table's
+        let weights = vec![AllocationWeight {
+            name: ALLOCATION_1.to_string(),
+            allocation: Uint128::new(1),
+        }];
+        let msg = ExecuteMsg::SetDenomWeights {
+            denom: DENOM_2.to_string(),
+            weights: weights.clone(),
+        };
+        execute(deps.as_mut(), env.clone(), gov.clone(), msg)?;
+        let msg = ExecuteMsg::SetTable {
+            table: "table".to_string(),
+            weights,
+        };
+        execute(deps.as_mut(), env.clone(), gov.clone(), msg)?;
+        let msg = ExecuteMsg::SetAllocationStatus {
+            name: ALLOCATION_1.to_string(),
+            status: AllocationStatus::Paused,
+        };
+        execute(deps.as_mut(), env.clone(), gov.clone(), msg)?;
+        let deposit = ExecuteMsg::Deposit {
+            flush: false,
+            table: None,
+        };
+        let info = mock_info(USER_1, &[coin(2_000, DENOM_1), coin(100, DENOM_2)]);
+        let res = execute(deps.as_mut(), env.clone(), info.clone(), deposit.clone())?;
+        assert!(res.messages.is_empty());
+
+        // expired while paused: it isn't paid out or removed, and the deposit falls back to
+        // the default weights for DENOM_2 and the table
+        env.block.height += 10;
+        let res = execute(deps.as_mut(), env.clone(), info, deposit.clone())?;
+        assert!(res.messages.is_empty());
+        let table_deposit = ExecuteMsg::Deposit {
+            flush: false,
+            table: Some("table".to_string()),
+        };
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(GOV_CONTRACT, &[coin(1, DENOM_1)]),
+            table_deposit,
+        )?;
+        let allocation = query_allocation(deps.as_ref(), ALLOCATION_1.into())?.unwrap();
+        let mut balance = allocation.balance;
+        balance.sort_by(|a, b| a.denom.cmp(&b.denom));
+        assert_eq!(balance, vec![coin(100, DENOM_2), coin(1_000, DENOM_1)]);
+        let allocation = query_allocation(deps.as_ref(), ALLOCATION_2.into())?.unwrap();
+        let mut balance = allocation.balance;
+        balance.sort_by(|a, b| a.denom.cmp(&b.denom));
+        assert_eq!(balance, vec![coin(100, DENOM_2), coin(3_001, DENOM_1)]);
+
+        // once it is active again, it pays out and goes
+        let msg = ExecuteMsg::SetAllocationStatus {
+            name: ALLOCATION_1.to_string(),
+            status: AllocationStatus::Active,
+        };
+        execute(deps.as_mut(), env.clone(), gov, msg)?;
+        let info = mock_info(USER_1, &[coin(10, DENOM_2)]);
+        let res = execute(deps.as_mut(), env, info, deposit)?;
+        assert_eq!(res.messages.len(), 1);
+        match &res.messages[0].msg {
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address,
+                amount,
             }) => {
-                assert_eq!(contract_addr, "steak_contract");
-                assert_eq!(funds.len(), 1);
-                assert_eq!(funds[0].amount, Uint128::new(25_000_000));
-                assert_eq!(funds[0].denom, DENOM_1);
-                let expected = to_json_binary(&pfc_steak::hub::ExecuteMsg::Bond {
-                    receiver: Some(String::from("receiver")),
-                    exec_msg: None,
-                })?;
-                assert_eq!(msg, &expected)
-            },
-            _ => {
-                unreachable!("Invalid MSG {:?}", res.messages[1].msg)
+                assert_eq!(to_address, "allocation_1_addr");
+                let mut amount = amount.clone();
+                amount.sort_by(|a, b| a.denom.cmp(&b.denom));
+                assert_eq!(amount, vec![coin(100, DENOM_2), coin(1_000, DENOM_1)]);
             },
+            _ => panic!("wrong message {:?}", res.messages[0].msg),
         }
-
-        assert_eq!(res.attributes.len(), 2);
-        let allocation = query_allocation(deps.as_ref(), ALLOCATION_1.into())?.unwrap();
-        assert!(allocation.balance.is_empty(), "no coins should be present");
+        assert!(query_allocation(deps.as_ref(), ALLOCATION_1.into())?.is_none());
 
         Ok(())
     }
@@ -888,113 +3767,794 @@ mod exec_test {
 mod crud_allocations {
     use cosmwasm_std::{
         coin,
-        testing::{mock_dependencies, mock_env, mock_info},
-        Api, BankMsg, CosmosMsg,
+        testing::{mock_dependencies, mock_env, mock_info, MockApi, MockQuerier, MockStorage},
+        to_json_binary, Addr, Api, BankMsg, Binary, CosmosMsg, OwnedDeps, Uint128, WasmMsg,
+    };
+    use cw20::{Cw20ExecuteMsg, Cw20ReceiveMsg};
+    use pfc_fee_split::fee_split_msg::{
+        AllocationDetail, AllocationHolding, AllocationKind, AllocationStatus, AllocationWeight,
+        BalancePolicy, Cw20HookMsg, ExecuteMsg, LiquidStakeProtocol, PayoutMode, ScheduleAt,
+        SendAfterMode, SendType, WeightChange, MAX_CHAIN_DEPTH,
     };
-    use pfc_fee_split::fee_split_msg::{AllocationHolding, ExecuteMsg, SendType};
 
     use crate::{
         contract::execute,
         error::ContractError,
-        handler::query::{query_allocation, query_allocations},
+        handler::query::{
+            query_allocation, query_allocation_stats, query_allocations, query_tables,
+        },
+        proto::MsgFundCommunityPool,
         test_helpers::{
-            do_instantiate, two_allocation, ALLOCATION_1, ALLOCATION_2, CREATOR, DENOM_1,
-            GOV_CONTRACT, USER_1,
+            do_instantiate, fee_split_holding, mock_contracts, mock_raw_splitters, mock_splitters,
+            one_allocation, two_allocation, ALLOCATION_1, ALLOCATION_2, CREATOR, DENOM_1,
+            GOV_CONTRACT, TOKEN_1, USER_1,
         },
     };
 
     #[test]
-    fn add_line() -> Result<(), ContractError> {
+    fn add_line() -> Result<(), ContractError> {
+        let mut deps = mock_dependencies();
+        let alloc = two_allocation(&deps.api);
+        let _res = do_instantiate(deps.as_mut(), CREATOR, alloc)?;
+        let allocations = query_allocations(deps.as_ref(), None, None)?;
+        assert_eq!(allocations.allocations.len(), 2);
+        assert_eq!(allocations.allocations[0].name, ALLOCATION_1);
+        assert_eq!(allocations.allocations[1].name, ALLOCATION_2);
+        let msg = ExecuteMsg::AddAllocationDetail {
+            name: "line3".to_string(),
+
+            allocation: Uint128::new(1),
+
+            send_after_blocks: None,
+
+            send_after_seconds: None,
+            payout_mode: PayoutMode::Push,
+            kind: AllocationKind::Weighted,
+            send_after: vec![coin(0u128, DENOM_1)],
+            send_after_mode: SendAfterMode::AnyOf,
+            send_type: SendType::SteakRewards {
+                steak: deps.api.addr_validate("steak-contract")?,
+                receiver: deps.api.addr_validate("rewards")?,
+            },
+        };
+        //eprintln!("{}", serde_json::to_string(&msg).unwrap());
+        let info = mock_info(USER_1, &[]);
+        let env = mock_env();
+        let err = execute(deps.as_mut(), env.clone(), info.clone(), msg.clone()).err().unwrap();
+        match err {
+            ContractError::AdminError {
+                ..
+            } => {},
+            _ => panic!("wrong error {:?}", err),
+        }
+        let info = mock_info(CREATOR, &[]);
+
+        let err = execute(deps.as_mut(), env.clone(), info.clone(), msg.clone()).err().unwrap();
+        match err {
+            ContractError::AdminError {
+                ..
+            } => {},
+            _ => panic!("wrong error {:?}", err),
+        }
+        let info = mock_info(GOV_CONTRACT, &[]);
+        let msg_duplicate = ExecuteMsg::AddAllocationDetail {
+            name: ALLOCATION_2.to_string(),
+
+            allocation: Uint128::new(1),
+
+            send_after_blocks: None,
+
+            send_after_seconds: None,
+            payout_mode: PayoutMode::Push,
+            kind: AllocationKind::Weighted,
+            send_after: vec![coin(0u128, DENOM_1)],
+            send_after_mode: SendAfterMode::AnyOf,
+            send_type: SendType::SteakRewards {
+                steak: deps.api.addr_validate("steak-contract")?,
+                receiver: deps.api.addr_validate("rewards")?,
+            },
+        };
+        let err =
+            execute(deps.as_mut(), env.clone(), info.clone(), msg_duplicate.clone()).err().unwrap();
+        match err {
+            ContractError::FeeAlreadyThere {
+                ..
+            } => {},
+            _ => panic!("wrong error {:?}", err),
+        }
+
+        let res = execute(deps.as_mut(), env.clone(), info.clone(), msg.clone())?;
+        assert!(!res.attributes.is_empty(), "attributes should be present");
+
+        let allocations = query_allocations(deps.as_ref(), None, None)?;
+        assert_eq!(allocations.allocations.len(), 3);
+        assert_eq!(allocations.allocations[0].name, ALLOCATION_1);
+        assert_eq!(allocations.allocations[1].name, ALLOCATION_2);
+        assert_eq!(allocations.allocations[2].name, "line3");
+
+        let msg = ExecuteMsg::Deposit {
+            flush: false,
+            table: None,
+        };
+        let info = mock_info(USER_1, &[coin(1_000_000, DENOM_1)]);
+        let env = mock_env();
+        let res = execute(deps.as_mut(), env.clone(), info.clone(), msg.clone())?;
+        assert_eq!(res.attributes.len(), 2);
+        assert_eq!(res.messages.len(), 2);
+        // the 1 unit lost to rounding goes to the first allocation
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "allocation_1_addr".to_string(),
+                amount: vec![coin(333334, DENOM_1)],
+            })
+        );
+        assert_eq!(
+            &format!("{:?}", res.messages[1].msg),
+            "Wasm(Execute { contract_addr: \"steak-contract\", msg: \
+             {\"bond\":{\"receiver\":\"rewards\",\"exec_msg\":null}}, funds: [Coin { 333333 \
+             \"uxyz\" }] })"
+        );
+        let allocations = query_allocation(deps.as_ref(), String::from(ALLOCATION_2))?.unwrap();
+
+        assert_eq!(allocations.balance.len(), 1);
+        assert_eq!(allocations.balance[0], coin(333_333, DENOM_1));
+
+        Ok(())
+    }
+
+    #[test]
+    fn add_contract_line() -> Result<(), ContractError> {
         let mut deps = mock_dependencies();
-        let alloc = two_allocation(&deps.api);
+        let alloc = one_allocation(&deps.api);
         let _res = do_instantiate(deps.as_mut(), CREATOR, alloc)?;
-        let allocations = query_allocations(deps.as_ref(), None, None)?;
-        assert_eq!(allocations.allocations.len(), 2);
-        assert_eq!(allocations.allocations[0].name, ALLOCATION_1);
-        assert_eq!(allocations.allocations[1].name, ALLOCATION_2);
+        let hook = Binary::from(br#"{"dust_received":{}}"#);
         let msg = ExecuteMsg::AddAllocationDetail {
-            name: "line3".to_string(),
-
-            allocation: 1,
-            send_after: coin(0u128, DENOM_1),
-            send_type: SendType::SteakRewards {
-                steak: deps.api.addr_validate("steak-contract")?,
-                receiver: deps.api.addr_validate("rewards")?,
+            name: "dust".to_string(),
+            allocation: Uint128::new(1),
+            send_after: vec![coin(0u128, DENOM_1)],
+            send_after_mode: SendAfterMode::AnyOf,
+            send_type: SendType::Contract {
+                contract: deps.api.addr_validate("dust_collector")?,
+                msg: hook.clone(),
             },
+            send_after_blocks: None,
+            send_after_seconds: None,
+            payout_mode: PayoutMode::Push,
+            kind: AllocationKind::Weighted,
         };
-        //eprintln!("{}", serde_json::to_string(&msg).unwrap());
-        let info = mock_info(USER_1, &[]);
+        let info = mock_info(GOV_CONTRACT, &[]);
         let env = mock_env();
+        // not a contract (yet)
         let err = execute(deps.as_mut(), env.clone(), info.clone(), msg.clone()).err().unwrap();
         match err {
-            ContractError::AdminError {
+            ContractError::Std {
                 ..
             } => {},
             _ => panic!("wrong error {:?}", err),
         }
-        let info = mock_info(CREATOR, &[]);
+        mock_contracts(&mut deps.querier, &["dust_collector"]);
+        execute(deps.as_mut(), env.clone(), info, msg)?;
+
+        let msg = ExecuteMsg::Deposit {
+            flush: false,
+            table: None,
+        };
+        let info = mock_info(USER_1, &[coin(1_000_000, DENOM_1)]);
+        let res = execute(deps.as_mut(), env.clone(), info, msg)?;
+        assert_eq!(res.messages.len(), 2);
+        assert_eq!(
+            res.messages[1].msg,
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: "dust_collector".to_string(),
+                msg: hook.clone(),
+                funds: vec![coin(500_000, DENOM_1)],
+            })
+        );
+
+        let msg = ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: GOV_CONTRACT.to_string(),
+            amount: Uint128::new(1_000_000),
+            msg: to_json_binary(&Cw20HookMsg::Deposit {
+                flush: true,
+                table: None,
+            })?,
+        });
+        let res = execute(deps.as_mut(), env, mock_info(TOKEN_1, &[]), msg)?;
+        assert_eq!(res.messages.len(), 2);
+        assert_eq!(
+            res.messages[1].msg,
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: TOKEN_1.to_string(),
+                msg: to_json_binary(&Cw20ExecuteMsg::Send {
+                    contract: "dust_collector".to_string(),
+                    amount: Uint128::new(500_000),
+                    msg: hook,
+                })?,
+                funds: vec![],
+            })
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn add_fee_split_line() -> Result<(), ContractError> {
+        let mut deps = mock_dependencies();
+        let alloc = one_allocation(&deps.api);
+        let _res = do_instantiate(deps.as_mut(), CREATOR, alloc)?;
+        let env = mock_env();
+        let info = mock_info(GOV_CONTRACT, &[]);
+        let msg = ExecuteMsg::AddAllocationDetail {
+            name: "child".to_string(),
+            allocation: Uint128::new(1),
+            send_after: vec![coin(0u128, DENOM_1)],
+            send_after_mode: SendAfterMode::AnyOf,
+            send_type: SendType::FeeSplit {
+                contract: deps.api.addr_validate("child_splitter")?,
+            },
+            send_after_blocks: None,
+            send_after_seconds: None,
+            payout_mode: PayoutMode::Push,
+            kind: AllocationKind::Weighted,
+        };
 
+        // child -> grandchild -> us
+        mock_splitters(
+            &mut deps.querier,
+            vec![
+                ("child_splitter", vec![fee_split_holding("a", "grandchild_splitter")]),
+                (
+                    "grandchild_splitter",
+                    vec![
+                        fee_split_holding("a", "child_splitter"),
+                        fee_split_holding("b", env.contract.address.as_str()),
+                    ],
+                ),
+            ],
+        );
         let err = execute(deps.as_mut(), env.clone(), info.clone(), msg.clone()).err().unwrap();
         match err {
-            ContractError::AdminError {
+            ContractError::Std {
                 ..
             } => {},
             _ => panic!("wrong error {:?}", err),
         }
+        assert!(query_allocation(deps.as_ref(), "child".to_string())?.is_none());
+
+        // child -> grandchild -> child is someone else's problem
+        mock_splitters(
+            &mut deps.querier,
+            vec![
+                ("child_splitter", vec![fee_split_holding("a", "grandchild_splitter")]),
+                ("grandchild_splitter", vec![fee_split_holding("a", "child_splitter")]),
+            ],
+        );
+        execute(deps.as_mut(), env.clone(), info, msg)?;
+
+        let msg = ExecuteMsg::Deposit {
+            flush: false,
+            table: None,
+        };
+        let info = mock_info(USER_1, &[coin(1_000_000, DENOM_1)]);
+        let res = execute(deps.as_mut(), env, info, msg)?;
+        assert_eq!(res.messages.len(), 2);
+        assert_eq!(
+            res.messages[1].msg,
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: "child_splitter".to_string(),
+                msg: to_json_binary(&ExecuteMsg::Deposit {
+                    flush: false,
+                    table: None
+                })?,
+                funds: vec![coin(500_000, DENOM_1)],
+            })
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn add_fee_split_line_old_child() -> Result<(), ContractError> {
+        let mut deps = mock_dependencies();
+        let alloc = one_allocation(&deps.api);
+        let _res = do_instantiate(deps.as_mut(), CREATOR, alloc)?;
+        let env = mock_env();
         let info = mock_info(GOV_CONTRACT, &[]);
-        let msg_duplicate = ExecuteMsg::AddAllocationDetail {
-            name: ALLOCATION_2.to_string(),
+        let msg = |name: &str, contract: &str| ExecuteMsg::AddAllocationDetail {
+            name: name.to_string(),
+            allocation: Uint128::new(1),
+            send_after: vec![coin(0u128, DENOM_1)],
+            send_after_mode: SendAfterMode::AnyOf,
+            send_type: SendType::FeeSplit {
+                contract: Addr::unchecked(contract),
+            },
+            send_after_blocks: None,
+            send_after_seconds: None,
+            payout_mode: PayoutMode::Push,
+            kind: AllocationKind::Weighted,
+        };
+        // the deployed version answers with a u8 allocation and a single send_after coin
+        let old = |send_type: &str| {
+            format!(
+                r#"[{{"name":"a","allocation":1,"send_after":{{"denom":"uxyz","amount":"0"}},"send_type":{},"balance":[]}}]"#,
+                send_type
+            )
+        };
+        mock_raw_splitters(
+            &mut deps.querier,
+            vec![
+                ("old_wallet", &old(r#"{"Wallet":{"receiver":"someone"}}"#)),
+                ("old_loop", &old(r#"{"FeeSplit":{"contract":"cosmos2contract"}}"#)),
+            ],
+        );
+        execute(deps.as_mut(), env.clone(), info.clone(), msg("child", "old_wallet"))?;
+        assert!(query_allocation(deps.as_ref(), "child".to_string())?.is_some());
+        let err = execute(deps.as_mut(), env.clone(), info.clone(), msg("looped", "old_loop"))
+            .err()
+            .unwrap();
+        assert_eq!(err.to_string(), "PFC-FeeSplit: StdError:Generic error: address recursion");
 
-            allocation: 1,
-            send_after: coin(0u128, DENOM_1),
-            send_type: SendType::SteakRewards {
-                steak: deps.api.addr_validate("steak-contract")?,
-                receiver: deps.api.addr_validate("rewards")?,
+        // splitter_0 -> .. -> splitter_5 is deeper than we check
+        let chain: Vec<(String, Vec<AllocationHolding>)> = (0..=MAX_CHAIN_DEPTH)
+            .map(|i| {
+                let next = format!("splitter_{}", i + 1);
+                let holdings = if i < MAX_CHAIN_DEPTH {
+                    vec![fee_split_holding("a", &next)]
+                } else {
+                    vec![]
+                };
+                (format!("splitter_{}", i), holdings)
+            })
+            .collect();
+        mock_splitters(
+            &mut deps.querier,
+            chain.iter().map(|(addr, holdings)| (addr.as_str(), holdings.clone())).collect(),
+        );
+        let err = execute(deps.as_mut(), env.clone(), info.clone(), msg("deep", "splitter_0"))
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "PFC-FeeSplit: StdError:Generic error: fee split chain is too big to check"
+        );
+        // starting one hop further down is fine
+        execute(deps.as_mut(), env, info, msg("shallow", "splitter_1"))?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn add_sink_lines() -> Result<(), ContractError> {
+        let mut deps = mock_dependencies();
+        let alloc = one_allocation(&deps.api);
+        let _res = do_instantiate(deps.as_mut(), CREATOR, alloc)?;
+        let env = mock_env();
+        for (name, send_type) in
+            [("burn", SendType::Burn {}), ("community_pool", SendType::CommunityPool {})]
+        {
+            let msg = ExecuteMsg::AddAllocationDetail {
+                name: name.to_string(),
+                allocation: Uint128::new(1),
+                send_after: vec![coin(0u128, DENOM_1)],
+                send_after_mode: SendAfterMode::AnyOf,
+                send_type,
+                send_after_blocks: None,
+                send_after_seconds: None,
+                payout_mode: PayoutMode::Push,
+                kind: AllocationKind::Weighted,
+            };
+            execute(deps.as_mut(), env.clone(), mock_info(GOV_CONTRACT, &[]), msg)?;
+        }
+
+        let msg = ExecuteMsg::Deposit {
+            flush: false,
+            table: None,
+        };
+        let info = mock_info(USER_1, &[coin(900_000, DENOM_1)]);
+        let res = execute(deps.as_mut(), env.clone(), info, msg)?;
+        assert_eq!(res.messages.len(), 3);
+        assert_eq!(
+            res.messages[1].msg,
+            CosmosMsg::Bank(BankMsg::Burn {
+                amount: vec![coin(300_000, DENOM_1)],
+            })
+        );
+        assert_eq!(
+            res.messages[2].msg,
+            MsgFundCommunityPool {
+                amount: vec![coin(300_000, DENOM_1).into()],
+                depositor: env.contract.address.to_string(),
+            }
+            .into()
+        );
+
+        // CW20s get burnt, but stay put for the community pool
+        let msg = ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: GOV_CONTRACT.to_string(),
+            amount: Uint128::new(900_000),
+            msg: to_json_binary(&Cw20HookMsg::Deposit {
+                flush: true,
+                table: None,
+            })?,
+        });
+        let res = execute(deps.as_mut(), env.clone(), mock_info(TOKEN_1, &[]), msg)?;
+        assert_eq!(res.messages.len(), 2);
+        assert_eq!(
+            res.messages[1].msg,
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: TOKEN_1.to_string(),
+                msg: to_json_binary(&Cw20ExecuteMsg::Burn {
+                    amount: Uint128::new(300_000),
+                })?,
+                funds: vec![],
+            })
+        );
+        let community_pool =
+            query_allocation(deps.as_ref(), "community_pool".to_string())?.unwrap();
+        assert_eq!(community_pool.balance_cw20[0].amount, Uint128::new(300_000));
+
+        // and go to the rest when it is removed
+        let msg = ExecuteMsg::RemoveAllocationDetail {
+            name: "community_pool".to_string(),
+        };
+        let res = execute(deps.as_mut(), env, mock_info(GOV_CONTRACT, &[]), msg)?;
+        assert!(res.messages.is_empty());
+        for name in [ALLOCATION_1, "burn"] {
+            let allocation = query_allocation(deps.as_ref(), name.to_string())?.unwrap();
+            assert_eq!(allocation.balance_cw20.len(), 1);
+            assert_eq!(allocation.balance_cw20[0].amount, Uint128::new(150_000));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn add_liquid_stake_line() -> Result<(), ContractError> {
+        let mut deps = mock_dependencies();
+        let alloc = one_allocation(&deps.api);
+        let _res = do_instantiate(deps.as_mut(), CREATOR, alloc)?;
+        let env = mock_env();
+        let msg = ExecuteMsg::AddAllocationDetail {
+            name: "eris".to_string(),
+            allocation: Uint128::new(1),
+            send_after: vec![coin(0u128, DENOM_1)],
+            send_after_mode: SendAfterMode::AnyOf,
+            send_type: SendType::LiquidStake {
+                hub: deps.api.addr_validate("eris_hub")?,
+                receiver: env.contract.address.clone(),
+                protocol: LiquidStakeProtocol::Eris,
             },
+            send_after_blocks: None,
+            send_after_seconds: None,
+            payout_mode: PayoutMode::Push,
+            kind: AllocationKind::Weighted,
         };
         let err =
-            execute(deps.as_mut(), env.clone(), info.clone(), msg_duplicate.clone()).err().unwrap();
+            execute(deps.as_mut(), env.clone(), mock_info(GOV_CONTRACT, &[]), msg).err().unwrap();
         match err {
-            ContractError::FeeAlreadyThere {
+            ContractError::Std {
                 ..
             } => {},
             _ => panic!("wrong error {:?}", err),
         }
+        let msg = ExecuteMsg::AddAllocationDetail {
+            name: "eris".to_string(),
+            allocation: Uint128::new(1),
+            send_after: vec![coin(0u128, DENOM_1)],
+            send_after_mode: SendAfterMode::AnyOf,
+            send_type: SendType::LiquidStake {
+                hub: deps.api.addr_validate("eris_hub")?,
+                receiver: deps.api.addr_validate("receiver")?,
+                protocol: LiquidStakeProtocol::Eris,
+            },
+            send_after_blocks: None,
+            send_after_seconds: None,
+            payout_mode: PayoutMode::Push,
+            kind: AllocationKind::Weighted,
+        };
+        execute(deps.as_mut(), env.clone(), mock_info(GOV_CONTRACT, &[]), msg)?;
 
-        let res = execute(deps.as_mut(), env.clone(), info.clone(), msg.clone())?;
-        assert!(!res.attributes.is_empty(), "attributes should be present");
+        let msg = ExecuteMsg::Deposit {
+            flush: false,
+            table: None,
+        };
+        let info = mock_info(USER_1, &[coin(1_000_000, DENOM_1)]);
+        let res = execute(deps.as_mut(), env, info, msg)?;
+        assert_eq!(res.messages.len(), 2);
+        assert_eq!(
+            res.messages[1].msg,
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: "eris_hub".to_string(),
+                msg: Binary::from(br#"{"bond":{"receiver":"receiver"}}"#),
+                funds: vec![coin(500_000, DENOM_1)],
+            })
+        );
 
-        let allocations = query_allocations(deps.as_ref(), None, None)?;
-        assert_eq!(allocations.allocations.len(), 3);
-        assert_eq!(allocations.allocations[0].name, ALLOCATION_1);
-        assert_eq!(allocations.allocations[1].name, ALLOCATION_2);
-        assert_eq!(allocations.allocations[2].name, "line3");
+        Ok(())
+    }
 
+    /// two allocations holding 500 each, and a table using allocation_2
+    fn replace_setup() -> Result<OwnedDeps<MockStorage, MockApi, MockQuerier>, ContractError> {
+        let mut deps = mock_dependencies();
+        let alloc = two_allocation(&deps.api);
+        let _res = do_instantiate(deps.as_mut(), CREATOR, alloc)?;
         let msg = ExecuteMsg::Deposit {
             flush: false,
+            table: None,
         };
-        let info = mock_info(USER_1, &[coin(1_000_000, DENOM_1)]);
+        let info = mock_info(USER_1, &[coin(1_000, DENOM_1)]);
+        let res = execute(deps.as_mut(), mock_env(), info, msg)?;
+        assert!(res.messages.is_empty());
+        let msg = ExecuteMsg::SetTable {
+            table: "table".to_string(),
+            weights: vec![AllocationWeight {
+                name: ALLOCATION_2.to_string(),
+                allocation: Uint128::new(1),
+            }],
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(GOV_CONTRACT, &[]), msg)?;
+        Ok(deps)
+    }
+
+    /// keeps allocation_1, drops allocation_2 and adds allocation_3
+    fn replacement(api: &dyn Api) -> Vec<AllocationDetail> {
+        let mut allocations = one_allocation(api);
+        allocations.push(AllocationDetail {
+            name: "allocation_3".to_string(),
+            allocation: Uint128::new(3),
+            send_after_blocks: None,
+            send_after_seconds: None,
+            payout_mode: PayoutMode::Push,
+            kind: AllocationKind::Weighted,
+            send_after: vec![coin(1_000, DENOM_1)],
+            send_after_mode: SendAfterMode::AnyOf,
+            send_type: SendType::Wallet {
+                receiver: api.addr_validate("allocation_3_addr").unwrap(),
+            },
+        });
+        allocations
+    }
+
+    #[test]
+    fn replace_lines() -> Result<(), ContractError> {
+        let mut deps = replace_setup()?;
         let env = mock_env();
-        let res = execute(deps.as_mut(), env.clone(), info.clone(), msg.clone())?;
-        assert_eq!(res.attributes.len(), 2);
+        let replace = |allocations: Vec<AllocationDetail>, balance_policy: BalancePolicy| {
+            ExecuteMsg::ReplaceAllocations {
+                allocations,
+                balance_policy,
+            }
+        };
+
+        let msg = replace(replacement(&deps.api), BalancePolicy::CarryOver);
+        let err = execute(deps.as_mut(), env.clone(), mock_info(USER_1, &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::AdminError { .. }), "wrong error {:?}", err);
+
+        let info = mock_info(GOV_CONTRACT, &[]);
+        let msg = replace(vec![], BalancePolicy::CarryOver);
+        let err = execute(deps.as_mut(), env.clone(), info.clone(), msg).unwrap_err();
+        assert!(matches!(err, ContractError::NoFeesError {}), "wrong error {:?}", err);
+
+        let mut dupes = replacement(&deps.api);
+        dupes[1].name = ALLOCATION_1.to_string();
+        let msg = replace(dupes, BalancePolicy::CarryOver);
+        let err = execute(deps.as_mut(), env.clone(), info.clone(), msg).unwrap_err();
+        assert!(matches!(err, ContractError::FundAllocationNotUnique {}), "wrong error {:?}", err);
+
+        let mut zero = replacement(&deps.api);
+        zero[1].allocation = Uint128::zero();
+        let msg = replace(zero, BalancePolicy::CarryOver);
+        let err = execute(deps.as_mut(), env.clone(), info.clone(), msg).unwrap_err();
+        assert!(matches!(err, ContractError::AllocationZero {}), "wrong error {:?}", err);
+
+        let mut bad_memo = replacement(&deps.api);
+        bad_memo[1].send_type = SendType::IbcTransfer {
+            channel_id: "channel-1".to_string(),
+            receiver: "remote".to_string(),
+            timeout_seconds: 600,
+            memo: Some("not json".to_string()),
+        };
+        let msg = replace(bad_memo, BalancePolicy::CarryOver);
+        assert!(execute(deps.as_mut(), env.clone(), info.clone(), msg).is_err());
+        // nothing changed
+        assert_eq!(query_allocations(deps.as_ref(), None, None)?.allocations.len(), 2);
+
+        // a capped allocation's weight isn't used, so it isn't added up
+        let mut capped = replacement(&deps.api);
+        capped[0].allocation = Uint128::MAX;
+        capped[0].kind = AllocationKind::Capped {
+            cap: vec![coin(1_000, DENOM_1)],
+            priority: 0,
+        };
+        let mut deps_capped = replace_setup()?;
+        let msg = replace(capped, BalancePolicy::CarryOver);
+        execute(deps_capped.as_mut(), env.clone(), info.clone(), msg)?;
+
+        // allocation_1 keeps its 500, allocation_2 pays out its 500
+        let msg = replace(replacement(&deps.api), BalancePolicy::CarryOver);
+        let res = execute(deps.as_mut(), env.clone(), info.clone(), msg)?;
+        assert_eq!(res.messages.len(), 1);
+        match &res.messages[0].msg {
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr,
+                funds,
+                ..
+            }) => {
+                assert_eq!(contract_addr, "steak_contract");
+                assert_eq!(funds, &vec![coin(500, DENOM_1)]);
+            },
+            _ => unreachable!("Invalid MSG {:?}", res.messages[0].msg),
+        }
+        let allocations = query_allocations(deps.as_ref(), None, None)?.allocations;
+        assert_eq!(allocations.len(), 2);
+        assert_eq!(allocations[0].name, ALLOCATION_1);
+        assert_eq!(allocations[0].balance, vec![coin(500, DENOM_1)]);
+        assert_eq!(allocations[1].name, "allocation_3");
+        assert!(allocations[1].balance.is_empty());
+        assert_eq!(allocations[1].allocation, Uint128::new(3));
+        // the table only had allocation_2 in it
+        assert!(query_tables(deps.as_ref(), None, None)?.tables.is_empty());
+
+        // everything held pays out
+        let mut deps = replace_setup()?;
+        let msg = replace(replacement(&deps.api), BalancePolicy::PayOut);
+        let res = execute(deps.as_mut(), env.clone(), info.clone(), msg)?;
         assert_eq!(res.messages.len(), 2);
         assert_eq!(
             res.messages[0].msg,
             CosmosMsg::Bank(BankMsg::Send {
                 to_address: "allocation_1_addr".to_string(),
-                amount: vec![coin(333333, DENOM_1)],
+                amount: vec![coin(500, DENOM_1)],
             })
         );
-        assert_eq!(
-            &format!("{:?}", res.messages[1].msg),
-            "Wasm(Execute { contract_addr: \"steak-contract\", msg: \
-             {\"bond\":{\"receiver\":\"rewards\",\"exec_msg\":null}}, funds: [Coin { 333333 \
-             \"uxyz\" }] })"
+        let allocations = query_allocations(deps.as_ref(), None, None)?.allocations;
+        assert!(allocations.iter().all(|a| a.balance.is_empty()));
+
+        // the 1,000 held is split 1:3 over the new allocations
+        let mut deps = replace_setup()?;
+        let msg = replace(replacement(&deps.api), BalancePolicy::Redistribute);
+        let res = execute(deps.as_mut(), env, info, msg)?;
+        assert!(res.messages.is_empty());
+        let allocations = query_allocations(deps.as_ref(), None, None)?.allocations;
+        assert_eq!(allocations[0].balance, vec![coin(250, DENOM_1)]);
+        assert_eq!(allocations[1].balance, vec![coin(750, DENOM_1)]);
+        let stats = query_allocation_stats(deps.as_ref(), ALLOCATION_2.to_string())?;
+        assert!(stats.denoms[0].deposited.is_zero());
+        let stats = query_allocation_stats(deps.as_ref(), "allocation_3".to_string())?;
+        assert_eq!(stats.denoms[0].deposited, Uint128::new(750));
+
+        Ok(())
+    }
+
+    #[test]
+    fn replace_lines_keeps_settings() -> Result<(), ContractError> {
+        let mut deps = replace_setup()?;
+        let env = mock_env();
+        let info = mock_info(GOV_CONTRACT, &[]);
+        let msg = ExecuteMsg::SetAllocationStatus {
+            name: ALLOCATION_1.to_string(),
+            status: AllocationStatus::Paused,
+        };
+        execute(deps.as_mut(), env.clone(), info.clone(), msg)?;
+        let schedule = vec![WeightChange {
+            at: ScheduleAt::Height(env.block.height + 100),
+            weight: Uint128::new(5),
+        }];
+        let expires = Some(ScheduleAt::Height(env.block.height + 1_000));
+        let msg = ExecuteMsg::SetWeightSchedule {
+            name: ALLOCATION_1.to_string(),
+            schedule: schedule.clone(),
+            expires: expires.clone(),
+        };
+        execute(deps.as_mut(), env.clone(), info.clone(), msg)?;
+        let msg = ExecuteMsg::SetDenomWeights {
+            denom: DENOM_1.to_string(),
+            weights: vec![
+                AllocationWeight {
+                    name: ALLOCATION_1.to_string(),
+                    allocation: Uint128::new(1),
+                },
+                AllocationWeight {
+                    name: ALLOCATION_2.to_string(),
+                    allocation: Uint128::new(1),
+                },
+            ],
+        };
+        execute(deps.as_mut(), env.clone(), info.clone(), msg)?;
+
+        // DENOM_1's own weights only have allocation_1 left, so it gets the whole 1,000
+        let msg = ExecuteMsg::ReplaceAllocations {
+            allocations: replacement(&deps.api),
+            balance_policy: BalancePolicy::Redistribute,
+        };
+        execute(deps.as_mut(), env, info, msg)?;
+        let allocations = query_allocations(deps.as_ref(), None, None)?.allocations;
+        assert_eq!(allocations[0].balance, vec![coin(1_000, DENOM_1)]);
+        assert!(allocations[1].balance.is_empty());
+        // and keeps its status & schedule, while the new one starts out active
+        assert_eq!(allocations[0].status, AllocationStatus::Paused);
+        assert_eq!(allocations[0].schedule, schedule);
+        assert_eq!(allocations[0].expires, expires);
+        assert_eq!(allocations[1].status, AllocationStatus::Active);
+        assert!(allocations[1].schedule.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn last_active_weighted_line() -> Result<(), ContractError> {
+        let mut deps = mock_dependencies();
+        let alloc = two_allocation(&deps.api);
+        let _res = do_instantiate(deps.as_mut(), CREATOR, alloc)?;
+        let env = mock_env();
+        let info = mock_info(GOV_CONTRACT, &[]);
+        let msg = ExecuteMsg::SetAllocationStatus {
+            name: ALLOCATION_1.to_string(),
+            status: AllocationStatus::Paused,
+        };
+        execute(deps.as_mut(), env.clone(), info.clone(), msg)?;
+
+        // allocation_2 is the only weighted allocation still active
+        let msg = ExecuteMsg::ModifyAllocationDetail {
+            name: ALLOCATION_2.to_string(),
+            allocation: Uint128::new(1),
+            send_after_blocks: None,
+            send_after_seconds: None,
+            payout_mode: PayoutMode::Push,
+            kind: AllocationKind::Capped {
+                cap: vec![coin(1_000, DENOM_1)],
+                priority: 0,
+            },
+            send_after: vec![coin(1u128, DENOM_1)],
+            send_after_mode: SendAfterMode::AnyOf,
+            send_type: SendType::Wallet {
+                receiver: deps.api.addr_validate("new-contract").unwrap(),
+            },
+        };
+        let err = execute(deps.as_mut(), env.clone(), info.clone(), msg).unwrap_err();
+        assert!(
+            matches!(err, ContractError::NoActiveWeightedAllocation {}),
+            "wrong error {:?}",
+            err
         );
-        let allocations = query_allocation(deps.as_ref(), String::from(ALLOCATION_2))?.unwrap();
 
-        assert_eq!(allocations.balance.len(), 1);
-        assert_eq!(allocations.balance[0], coin(333_333, DENOM_1));
+        let msg = ExecuteMsg::RemoveAllocationDetail {
+            name: ALLOCATION_2.to_string(),
+        };
+        let err = execute(deps.as_mut(), env.clone(), info.clone(), msg).unwrap_err();
+        assert!(
+            matches!(err, ContractError::NoActiveWeightedAllocation {}),
+            "wrong error {:?}",
+            err
+        );
+
+        // allocation_1 stays paused
+        let msg = ExecuteMsg::ReplaceAllocations {
+            allocations: one_allocation(&deps.api),
+            balance_policy: BalancePolicy::CarryOver,
+        };
+        let err = execute(deps.as_mut(), env.clone(), info.clone(), msg).unwrap_err();
+        assert!(
+            matches!(err, ContractError::NoActiveWeightedAllocation {}),
+            "wrong error {:?}",
+            err
+        );
+
+        let allocations = query_allocations(deps.as_ref(), None, None)?.allocations;
+        assert_eq!(allocations.len(), 2);
+        assert_eq!(allocations[1].kind, AllocationKind::Weighted);
+
+        // with allocation_1 active again, allocation_2 can go
+        let msg = ExecuteMsg::SetAllocationStatus {
+            name: ALLOCATION_1.to_string(),
+            status: AllocationStatus::Active,
+        };
+        execute(deps.as_mut(), env.clone(), info.clone(), msg)?;
+        let msg = ExecuteMsg::RemoveAllocationDetail {
+            name: ALLOCATION_2.to_string(),
+        };
+        execute(deps.as_mut(), env, info, msg)?;
 
         Ok(())
     }
@@ -1052,6 +4612,7 @@ mod crud_allocations {
 
         let msg = ExecuteMsg::Deposit {
             flush: false,
+            table: None,
         };
         let info = mock_info(USER_1, &[coin(1_000_000, DENOM_1)]);
         let env = mock_env();
@@ -1083,8 +4644,13 @@ mod crud_allocations {
         assert_eq!(allocations.allocations[1].name, ALLOCATION_2);
         let msg = ExecuteMsg::ModifyAllocationDetail {
             name: ALLOCATION_2.to_string(),
-            allocation: 3,
-            send_after: coin(1u128, DENOM_1),
+            allocation: Uint128::new(3),
+            send_after_blocks: None,
+            send_after_seconds: None,
+            payout_mode: PayoutMode::Push,
+            kind: AllocationKind::Weighted,
+            send_after: vec![coin(1u128, DENOM_1)],
+            send_after_mode: SendAfterMode::AnyOf,
             send_type: SendType::Wallet {
                 receiver: deps.api.addr_validate("new-contract").unwrap(),
             },
@@ -1111,8 +4677,13 @@ mod crud_allocations {
         let info = mock_info(GOV_CONTRACT, &[]);
         let msg_does_not_exist = ExecuteMsg::ModifyAllocationDetail {
             name: "not-here".to_string(),
-            allocation: 3,
-            send_after: coin(1u128, DENOM_1),
+            allocation: Uint128::new(3),
+            send_after_blocks: None,
+            send_after_seconds: None,
+            payout_mode: PayoutMode::Push,
+            kind: AllocationKind::Weighted,
+            send_after: vec![coin(1u128, DENOM_1)],
+            send_after_mode: SendAfterMode::AnyOf,
             send_type: SendType::Wallet {
                 receiver: deps.api.addr_validate("new-contract").unwrap(),
             },
@@ -1129,6 +4700,7 @@ mod crud_allocations {
 
         let msg_deposit = ExecuteMsg::Deposit {
             flush: false,
+            table: None,
         };
         let info = mock_info(USER_1, &[coin(1_000_000, DENOM_1)]);
         let env = mock_env();
@@ -1161,17 +4733,34 @@ mod crud_allocations {
             AllocationHolding {
                 name: ALLOCATION_2.to_string(),
 
-                allocation: 3,
-                send_after: coin(1u128, DENOM_1),
+                allocation: Uint128::new(3),
+
+                send_after_blocks: None,
+
+                send_after_seconds: None,
+                payout_mode: PayoutMode::Push,
+                status: AllocationStatus::Active,
+                kind: AllocationKind::Weighted,
+                filled: vec![],
+                period_start: None,
+                schedule: vec![],
+                expires: None,
+                send_after: vec![coin(1u128, DENOM_1)],
+                send_after_mode: SendAfterMode::AnyOf,
                 send_type: SendType::Wallet {
                     receiver: deps.api.addr_validate("new-contract").unwrap()
                 },
                 balance: vec![coin(500_000, DENOM_1)],
+                balance_cw20: vec![],
+                last_payout_height: Some(env.block.height),
+                last_payout_time: Some(env.block.time),
+                payout_error: None,
             }
         );
 
         let msg_deposit = ExecuteMsg::Deposit {
             flush: false,
+            table: None,
         };
         let info = mock_info(USER_1, &[coin(1_000_000, DENOM_1)]);
         let env = mock_env();
@@ -1315,9 +4904,11 @@ mod flush_whitelist {
         let info_with_funds = mock_info(USER_1, &[coin(1_000_000u128, String::from(DENOM_1))]);
         let msg_no_flush = ExecuteMsg::Deposit {
             flush: false,
+            table: None,
         };
         let msg_flush = ExecuteMsg::Deposit {
             flush: true,
+            table: None,
         };
         let res = execute(deps.as_mut(), env.clone(), info_with_funds.clone(), msg_no_flush)?;
         assert_eq!(res.messages.len(), 1);
@@ -1376,6 +4967,7 @@ mod ownership_changes {
         let _res = execute(deps.as_mut(), env.clone(), info.clone(), msg_gov_transfer.clone())?;
         let msg_flush = ExecuteMsg::Deposit {
             flush: true,
+            table: None,
         };
 
         //  not admin yet
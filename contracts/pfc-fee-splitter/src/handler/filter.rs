@@ -0,0 +1,445 @@
+use std::collections::{HashMap, HashSet};
+
+use cosmwasm_std::{
+    to_json_binary, Addr, BankMsg, Coin, DepsMut, MessageInfo, Response, StdResult, Storage,
+    Uint128, WasmMsg,
+};
+use cw20::Cw20ExecuteMsg;
+use pfc_fee_split::fee_split_msg::{
+    DenomFilter, DenomFilterMode, FilteredDenomAction, MAX_QUARANTINE_DENOMS,
+};
+
+use crate::{
+    error::ContractError,
+    handler::exec::{add_to_balance, coins_to_string},
+    state::{ADMIN, DENOM_FILTER, QUARANTINE},
+};
+
+/// what the denom filter took out of a deposit or sweep
+#[derive(Default)]
+pub(crate) struct Filtered {
+    pub refund: Vec<Coin>,
+    pub quarantined: Vec<Coin>,
+}
+impl Filtered {
+    /// sends the refund (if any) back to the depositor, and notes what was taken out.
+    /// 'cw20' refunds are keyed by token address
+    pub(crate) fn add_to(
+        self,
+        mut res: Response,
+        depositor: &Addr,
+        cw20: bool,
+    ) -> StdResult<Response> {
+        if !self.refund.is_empty() {
+            res = res.add_attribute("refunded", coins_to_string(&self.refund));
+            if cw20 {
+                for token in &self.refund {
+                    res = res.add_message(WasmMsg::Execute {
+                        contract_addr: token.denom.clone(),
+                        msg: to_json_binary(&Cw20ExecuteMsg::Transfer {
+                            recipient: depositor.to_string(),
+                            amount: token.amount,
+                        })?,
+                        funds: vec![],
+                    });
+                }
+            } else {
+                res = res.add_message(BankMsg::Send {
+                    to_address: depositor.to_string(),
+                    amount: self.refund,
+                });
+            }
+        }
+        if !self.quarantined.is_empty() {
+            res = res.add_attribute("quarantined", coins_to_string(&self.quarantined));
+        }
+        Ok(res)
+    }
+}
+
+/// errors if a deposit sends more denoms than the filter allows
+pub(crate) fn assert_denom_count(storage: &dyn Storage, count: usize) -> Result<(), ContractError> {
+    match DENOM_FILTER.may_load(storage)?.and_then(|f| f.max_denoms) {
+        Some(max) if count > max as usize => Err(ContractError::TooManyDenoms {
+            count,
+            max,
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// takes the denoms the filter doesn't allow out of 'funds', and quarantines them (see
+/// check_funds). 'cw20' funds are keyed by token address
+pub(crate) fn filter_funds(
+    storage: &mut dyn Storage,
+    funds: &mut HashMap<String, Uint128>,
+    cw20: bool,
+    refundable: bool,
+) -> Result<Filtered, ContractError> {
+    let filtered = check_funds(storage, funds, cw20, refundable)?;
+    if filtered.quarantined.is_empty() {
+        return Ok(filtered);
+    }
+    let mut quarantine = QUARANTINE.may_load(storage)?.unwrap_or_default();
+    for coin in &filtered.quarantined {
+        add_to_balance(&mut quarantine.balance, coin.clone());
+    }
+    QUARANTINE.save(storage, &quarantine)?;
+    Ok(filtered)
+}
+
+/// takes the denoms the filter doesn't allow out of 'funds', without changing anything. they are
+/// to be quarantined, or refunded when there is a depositor to refund ('refundable') and the
+/// filter says so.
+/// CW20 tokens are always refunded, as a token can refuse to be swept out of the quarantine.
+/// once the quarantine holds MAX_QUARANTINE_DENOMS denoms, new ones are refunded too (or, with
+/// no one to refund, left untracked)
+pub(crate) fn check_funds(
+    storage: &dyn Storage,
+    funds: &mut HashMap<String, Uint128>,
+    cw20: bool,
+    refundable: bool,
+) -> Result<Filtered, ContractError> {
+    let mut filtered = Filtered::default();
+    let Some(filter) = DENOM_FILTER.may_load(storage)? else {
+        return Ok(filtered);
+    };
+    let mut denied = funds.keys().filter(|d| !filter.allows(d)).cloned().collect::<Vec<_>>();
+    if denied.is_empty() {
+        return Ok(filtered);
+    }
+    denied.sort();
+    let mut held = QUARANTINE
+        .may_load(storage)?
+        .unwrap_or_default()
+        .balance
+        .into_iter()
+        .map(|c| c.denom)
+        .collect::<HashSet<_>>();
+    for denom in denied {
+        let amount = funds.remove(&denom).unwrap_or_default();
+        if amount.is_zero() {
+            continue;
+        }
+        let refund = cw20 || refundable && filter.on_filtered == FilteredDenomAction::Refund;
+        let quarantine = !refund && (held.contains(&denom) || held.len() < MAX_QUARANTINE_DENOMS);
+        let coin = Coin::new(amount.u128(), denom);
+        if quarantine {
+            held.insert(coin.denom.clone());
+            filtered.quarantined.push(coin);
+        } else if refundable {
+            filtered.refund.push(coin);
+        }
+    }
+    Ok(filtered)
+}
+
+/// checks a denom filter before it is set
+pub(crate) fn validate_denom_filter(
+    mode: DenomFilterMode,
+    denoms: &[String],
+    max_denoms: Option<u32>,
+) -> Result<(), ContractError> {
+    let invalid = |reason: &str| ContractError::InvalidDenomFilter {
+        reason: reason.to_string(),
+    };
+    if denoms.iter().any(|d| d.trim().is_empty()) {
+        return Err(invalid("denoms can't be blank"));
+    }
+    if denoms.iter().collect::<HashSet<_>>().len() != denoms.len() {
+        return Err(invalid("each denom can only be listed once"));
+    }
+    // it would turn every deposit away
+    if mode == DenomFilterMode::Allow && denoms.is_empty() {
+        return Err(invalid("an allowlist needs at least one denom"));
+    }
+    if max_denoms == Some(0) {
+        return Err(invalid("max_denoms must be more than 0"));
+    }
+    Ok(())
+}
+
+pub(crate) fn execute_set_denom_filter(
+    deps: DepsMut,
+    info: MessageInfo,
+    mode: DenomFilterMode,
+    denoms: Vec<String>,
+    on_filtered: FilteredDenomAction,
+    max_denoms: Option<u32>,
+) -> Result<Response, ContractError> {
+    validate_denom_filter(mode, &denoms, max_denoms)?;
+    let filter = DenomFilter {
+        mode,
+        denoms,
+        on_filtered,
+        max_denoms,
+    };
+    DENOM_FILTER.save(deps.storage, &filter)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "set_denom_filter")
+        .add_attribute("from", info.sender)
+        .add_attribute("mode", mode.to_string())
+        .add_attribute("denoms", filter.denoms.join(","))
+        .add_attribute("on_filtered", on_filtered.to_string())
+        .add_attribute("max_denoms", max_denoms.map(|m| m.to_string()).unwrap_or_default()))
+}
+
+/// sends what the quarantine holds of 'denom' into 'to'. a denom at a time, so one that can't be
+/// sent doesn't hold up the rest
+pub fn execute_sweep_quarantine(
+    deps: DepsMut,
+    info: MessageInfo,
+    to: String,
+    denom: String,
+) -> Result<Response, ContractError> {
+    ADMIN.assert_admin(deps.as_ref(), &info.sender)?;
+    let to = deps.api.addr_validate(&to)?;
+    let mut quarantine = QUARANTINE.may_load(deps.storage)?.unwrap_or_default();
+
+    let res = Response::new()
+        .add_attribute("action", "sweep_quarantine")
+        .add_attribute("from", info.sender)
+        .add_attribute("to", &to)
+        .add_attribute("denom", &denom);
+    let Some(index) = quarantine.balance.iter().position(|c| c.denom == denom) else {
+        return Ok(res.add_attribute("no-action", "denom not in quarantine"));
+    };
+    let swept = quarantine.balance.remove(index);
+    QUARANTINE.save(deps.storage, &quarantine)?;
+
+    Ok(res.add_attribute("amount", swept.to_string()).add_message(BankMsg::Send {
+        to_address: to.to_string(),
+        amount: vec![swept],
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use cosmwasm_std::{
+        coin,
+        testing::{mock_dependencies, mock_env, mock_info},
+        CosmosMsg,
+    };
+    use cw20::Cw20ReceiveMsg;
+    use pfc_fee_split::fee_split_msg::{Cw20HookMsg, ExecuteMsg};
+
+    use super::*;
+    use crate::{
+        contract::execute,
+        handler::query::{query_allocation, query_quarantine},
+        test_helpers::{
+            do_instantiate, two_allocation, ALLOCATION_1, CREATOR, DENOM_1, DENOM_2, DENOM_3,
+            GOV_CONTRACT, TOKEN_1, USER_1,
+        },
+    };
+
+    fn set_filter(
+        mode: DenomFilterMode,
+        denoms: &[&str],
+        on_filtered: FilteredDenomAction,
+        max_denoms: Option<u32>,
+    ) -> ExecuteMsg {
+        ExecuteMsg::SetDenomFilter {
+            mode,
+            denoms: denoms.iter().map(|d| d.to_string()).collect(),
+            on_filtered,
+            max_denoms,
+        }
+    }
+
+    fn deposit() -> ExecuteMsg {
+        ExecuteMsg::Deposit {
+            flush: false,
+            table: None,
+        }
+    }
+
+    #[test]
+    fn denom_filter() -> Result<(), ContractError> {
+        let mut deps = mock_dependencies();
+        let alloc = two_allocation(&deps.api);
+        let _res = do_instantiate(deps.as_mut(), CREATOR, alloc)?;
+        let env = mock_env();
+        let gov = mock_info(GOV_CONTRACT, &[]);
+
+        let allow =
+            set_filter(DenomFilterMode::Allow, &[DENOM_1], FilteredDenomAction::Refund, Some(2));
+        let err =
+            execute(deps.as_mut(), env.clone(), mock_info(USER_1, &[]), allow.clone()).unwrap_err();
+        assert!(matches!(err, ContractError::AdminError(_)), "wrong error {:?}", err);
+        for bad in [
+            set_filter(DenomFilterMode::Allow, &[], FilteredDenomAction::Refund, None),
+            set_filter(
+                DenomFilterMode::Deny,
+                &[DENOM_2, DENOM_2],
+                FilteredDenomAction::Refund,
+                None,
+            ),
+            set_filter(DenomFilterMode::Deny, &[DENOM_2], FilteredDenomAction::Refund, Some(0)),
+        ] {
+            let err = execute(deps.as_mut(), env.clone(), gov.clone(), bad).unwrap_err();
+            assert!(
+                matches!(err, ContractError::InvalidDenomFilter { .. }),
+                "wrong error {:?}",
+                err
+            );
+        }
+        execute(deps.as_mut(), env.clone(), gov.clone(), allow)?;
+
+        // the rest goes back to the depositor
+        let info = mock_info(USER_1, &[coin(100, DENOM_1), coin(50, DENOM_2)]);
+        let res = execute(deps.as_mut(), env.clone(), info, deposit())?;
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: USER_1.to_string(),
+                amount: vec![coin(50, DENOM_2)],
+            })
+        );
+        let allocation = query_allocation(deps.as_ref(), ALLOCATION_1.into())?.unwrap();
+        assert_eq!(allocation.balance, vec![coin(50, DENOM_1)]);
+
+        let info = mock_info(USER_1, &[coin(1, DENOM_1), coin(1, DENOM_2), coin(1, DENOM_3)]);
+        let err = execute(deps.as_mut(), env.clone(), info, deposit()).unwrap_err();
+        assert!(matches!(err, ContractError::TooManyDenoms { .. }), "wrong error {:?}", err);
+        let info = mock_info(USER_1, &[coin(50, DENOM_2)]);
+        let res = execute(deps.as_mut(), env.clone(), info, deposit())?;
+        assert!(res.attributes.iter().any(|a| a.key == "no-action"));
+        assert_eq!(res.messages.len(), 1);
+
+        // a denylist, with the rest held apart
+        let deny = set_filter(
+            DenomFilterMode::Deny,
+            &[DENOM_2, TOKEN_1],
+            FilteredDenomAction::Quarantine,
+            None,
+        );
+        execute(deps.as_mut(), env.clone(), gov.clone(), deny)?;
+        let info = mock_info(USER_1, &[coin(100, DENOM_1), coin(50, DENOM_2)]);
+        let res = execute(deps.as_mut(), env.clone(), info, deposit())?;
+        assert!(res.messages.is_empty());
+        let allocation = query_allocation(deps.as_ref(), ALLOCATION_1.into())?.unwrap();
+        assert_eq!(allocation.balance, vec![coin(100, DENOM_1)]);
+        let receive = ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: USER_1.to_string(),
+            amount: Uint128::new(30),
+            msg: to_json_binary(&Cw20HookMsg::Deposit {
+                flush: false,
+                table: None,
+            })?,
+        });
+        // tokens go back, as they could refuse to be swept out
+        let res = execute(deps.as_mut(), env.clone(), mock_info(TOKEN_1, &[]), receive)?;
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: TOKEN_1.to_string(),
+                msg: to_json_binary(&Cw20ExecuteMsg::Transfer {
+                    recipient: USER_1.to_string(),
+                    amount: Uint128::new(30),
+                })?,
+                funds: vec![],
+            })
+        );
+        let allocation = query_allocation(deps.as_ref(), ALLOCATION_1.into())?.unwrap();
+        assert!(allocation.balance_cw20.is_empty());
+
+        // untracked funds of a denied denom join the quarantine too
+        deps.querier.update_balance(
+            env.contract.address.clone(),
+            vec![coin(220, DENOM_1), coin(60, DENOM_2)],
+        );
+        let res =
+            execute(deps.as_mut(), env.clone(), mock_info(USER_1, &[]), ExecuteMsg::Sweep {})?;
+        assert!(res.attributes.iter().any(|a| a.key == "quarantined" && a.value == "10uabc"));
+        let allocation = query_allocation(deps.as_ref(), ALLOCATION_1.into())?.unwrap();
+        assert_eq!(allocation.balance, vec![coin(110, DENOM_1)]);
+        let quarantine = query_quarantine(deps.as_ref())?;
+        assert_eq!(quarantine.balance, vec![coin(60, DENOM_2)]);
+
+        let sweep = |denom: &str| ExecuteMsg::SweepQuarantine {
+            to: "treasury".to_string(),
+            denom: denom.to_string(),
+        };
+        let err = execute(deps.as_mut(), env.clone(), mock_info(USER_1, &[]), sweep(DENOM_2))
+            .unwrap_err();
+        assert!(matches!(err, ContractError::AdminError(_)), "wrong error {:?}", err);
+        let res = execute(deps.as_mut(), env.clone(), gov.clone(), sweep(TOKEN_1))?;
+        assert!(res.messages.is_empty());
+        let res = execute(deps.as_mut(), env.clone(), gov, sweep(DENOM_2))?;
+        assert_eq!(res.messages.len(), 1);
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "treasury".to_string(),
+                amount: vec![coin(60, DENOM_2)],
+            })
+        );
+        assert_eq!(query_quarantine(deps.as_ref())?, Default::default());
+
+        Ok(())
+    }
+
+    #[test]
+    fn quarantine_limit() -> Result<(), ContractError> {
+        let mut deps = mock_dependencies();
+        let alloc = two_allocation(&deps.api);
+        let _res = do_instantiate(deps.as_mut(), CREATOR, alloc)?;
+        let env = mock_env();
+        let gov = mock_info(GOV_CONTRACT, &[]);
+
+        let denied = (0..=MAX_QUARANTINE_DENOMS).map(|i| format!("udenied{:02}", i)).collect();
+        let deny = ExecuteMsg::SetDenomFilter {
+            mode: DenomFilterMode::Deny,
+            denoms: denied,
+            on_filtered: FilteredDenomAction::Quarantine,
+            max_denoms: None,
+        };
+        execute(deps.as_mut(), env.clone(), gov.clone(), deny)?;
+
+        // the quarantine fills up, and what doesn't fit is left untracked
+        let untracked = (0..=MAX_QUARANTINE_DENOMS)
+            .map(|i| coin(10, format!("udenied{:02}", i)))
+            .collect::<Vec<_>>();
+        deps.querier.update_balance(env.contract.address.clone(), untracked);
+        let res =
+            execute(deps.as_mut(), env.clone(), mock_info(USER_1, &[]), ExecuteMsg::Sweep {})?;
+        assert!(res.messages.is_empty());
+        let quarantine = query_quarantine(deps.as_ref())?;
+        assert_eq!(quarantine.balance.len(), MAX_QUARANTINE_DENOMS);
+        assert!(!quarantine.balance.iter().any(|c| c.denom == "udenied20"));
+
+        // a full quarantine still takes the denoms it holds, and refunds the rest
+        let info = mock_info(USER_1, &[coin(5, "udenied00"), coin(5, "udenied20")]);
+        let res = execute(deps.as_mut(), env.clone(), info, deposit())?;
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: USER_1.to_string(),
+                amount: vec![coin(5, "udenied20")],
+            })
+        );
+        let quarantine = query_quarantine(deps.as_ref())?;
+        assert_eq!(quarantine.balance[0], coin(15, "udenied00"));
+
+        // a denom at a time
+        let sweep = ExecuteMsg::SweepQuarantine {
+            to: "treasury".to_string(),
+            denom: "udenied00".to_string(),
+        };
+        let res = execute(deps.as_mut(), env, gov, sweep)?;
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "treasury".to_string(),
+                amount: vec![coin(15, "udenied00")],
+            })
+        );
+        let quarantine = query_quarantine(deps.as_ref())?;
+        assert_eq!(quarantine.balance.len(), MAX_QUARANTINE_DENOMS - 1);
+        assert_eq!(quarantine.balance[0], coin(10, "udenied01"));
+
+        Ok(())
+    }
+}
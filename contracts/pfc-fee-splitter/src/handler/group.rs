@@ -0,0 +1,524 @@
+use std::collections::BTreeMap;
+
+use cosmwasm_std::{
+    to_json_binary, Addr, BankMsg, Coin, CosmosMsg, Deps, DepsMut, Env, MessageInfo, Order,
+    QuerierWrapper, Response, StdResult, SubMsg, Uint128, WasmMsg,
+};
+use cw20::{Cw20CoinVerified, Cw20ExecuteMsg};
+use pfc_fee_split::{
+    cw4::{Cw4QueryMsg, Member, MemberChangedHookMsg, MemberListResponse, TotalWeightResponse},
+    fee_split_msg::{AllocationHolding, AllocationStatus, SendType},
+};
+
+use crate::{
+    error::ContractError,
+    handler::{
+        exec::{cw20_as_coins, PayoutMsg},
+        payout::track_payouts,
+        stats,
+    },
+    state::ALLOCATION_HOLDINGS,
+};
+
+/// the most a cw4 group returns in one page
+const MEMBER_PAGE: u32 = 30;
+
+/// all the group's members, in address order
+pub(crate) fn list_members(querier: &QuerierWrapper, group: &Addr) -> StdResult<Vec<Member>> {
+    let mut members: Vec<Member> = vec![];
+    loop {
+        let page: MemberListResponse = querier.query_wasm_smart(
+            group,
+            &Cw4QueryMsg::ListMembers {
+                start_after: members.last().map(|m| m.addr.clone()),
+                limit: Some(MEMBER_PAGE),
+            },
+        )?;
+        if page.members.is_empty() {
+            break;
+        }
+        members.extend(page.members);
+    }
+    Ok(members)
+}
+
+/// false for a cw4 group with no weight, as there is no one to pay. true for other send types
+pub(crate) fn has_weight(querier: &QuerierWrapper, send_type: &SendType) -> StdResult<bool> {
+    let SendType::Cw4Group {
+        group,
+    } = send_type
+    else {
+        return Ok(true);
+    };
+    let total: TotalWeightResponse = querier.query_wasm_smart(
+        group,
+        &Cw4QueryMsg::TotalWeight {
+            at_height: None,
+        },
+    )?;
+    Ok(total.weight > 0)
+}
+
+/// splits the coins & tokens over the members by weight, with a bank send (and a transfer per
+/// token) for each. the largest member gets the rounding remainder.
+/// no messages if no member has weight, so callers keep the balance
+pub(crate) fn group_payout_msgs(
+    members: &[Member],
+    coins: Vec<Coin>,
+    tokens: Vec<Cw20CoinVerified>,
+) -> Result<Vec<PayoutMsg>, ContractError> {
+    let coins = coins.into_iter().filter(|c| !c.amount.is_zero()).collect::<Vec<_>>();
+    let tokens = tokens.into_iter().filter(|t| !t.amount.is_zero()).collect::<Vec<_>>();
+    if coins.is_empty() && tokens.is_empty() {
+        return Ok(vec![]);
+    }
+    let members = members.iter().filter(|m| m.weight > 0).collect::<Vec<_>>();
+    let total_weight = members.iter().map(|m| Uint128::from(m.weight)).sum::<Uint128>();
+    let Some(largest) =
+        members.iter().enumerate().max_by_key(|(i, m)| (m.weight, usize::MAX - i)).map(|(i, _)| i)
+    else {
+        return Ok(vec![]);
+    };
+
+    let split = |amount: Uint128| -> Vec<Uint128> {
+        let mut shares = members
+            .iter()
+            .map(|m| amount.multiply_ratio(m.weight, total_weight))
+            .collect::<Vec<_>>();
+        let given = shares.iter().sum::<Uint128>();
+        shares[largest] += amount - given;
+        shares
+    };
+    let coin_shares = coins.iter().map(|c| split(c.amount)).collect::<Vec<_>>();
+    let token_shares = tokens.iter().map(|t| split(t.amount)).collect::<Vec<_>>();
+
+    let mut msgs = vec![];
+    for (i, member) in members.iter().enumerate() {
+        let amount = coins
+            .iter()
+            .zip(&coin_shares)
+            .filter(|(_, shares)| !shares[i].is_zero())
+            .map(|(c, shares)| Coin::new(shares[i].u128(), c.denom.clone()))
+            .collect::<Vec<_>>();
+        if !amount.is_empty() {
+            msgs.push(PayoutMsg {
+                msg: SubMsg::new(CosmosMsg::Bank(BankMsg::Send {
+                    to_address: member.addr.clone(),
+                    amount: amount.clone(),
+                })),
+                coins: amount,
+                tokens: vec![],
+            });
+        }
+        for (token, shares) in tokens.iter().zip(&token_shares) {
+            if shares[i].is_zero() {
+                continue;
+            }
+            msgs.push(PayoutMsg {
+                msg: SubMsg::new(WasmMsg::Execute {
+                    contract_addr: token.address.to_string(),
+                    msg: to_json_binary(&Cw20ExecuteMsg::Transfer {
+                        recipient: member.addr.clone(),
+                        amount: shares[i],
+                    })?,
+                    funds: vec![],
+                }),
+                coins: vec![],
+                tokens: vec![Cw20CoinVerified {
+                    address: token.address.clone(),
+                    amount: shares[i],
+                }],
+            });
+        }
+    }
+    Ok(msgs)
+}
+
+/// an allocation paying the group, with what it pays out
+struct Settlement {
+    name: String,
+    holding: AllocationHolding,
+    paid: Vec<Coin>,
+    payouts: Vec<PayoutMsg>,
+}
+
+/// the group has changed its members. active allocations paying it are paid out with the weights
+/// from before the change, so what they built up goes to who earned it. paused & frozen ones keep
+/// their balance, as they do on deposits.
+/// an error before anything is written (eg. a sender that isn't a group) doesn't fail, as it would
+/// stop the group from changing its members
+pub fn execute_member_changed_hook(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    msg: MemberChangedHookMsg,
+) -> Result<Response, ContractError> {
+    let res = Response::new()
+        .add_attribute("action", "member_changed_hook")
+        .add_attribute("from", &info.sender)
+        .add_attribute("diffs", msg.diffs.len().to_string());
+    let settlements = match plan_settlements(deps.as_ref(), &info.sender, &msg) {
+        Ok(settlements) => settlements,
+        Err(err) => return Ok(res.add_attribute("no-action", err.to_string())),
+    };
+
+    let mut msgs = vec![];
+    let mut settled = vec![];
+    for Settlement {
+        name,
+        mut holding,
+        paid,
+        payouts,
+    } in settlements
+    {
+        stats::record_payout(deps.storage, &name, &paid, env.block.height)?;
+        msgs.extend(track_payouts(deps.storage, &env, &name, payouts)?);
+        holding.last_payout_height = Some(env.block.height);
+        holding.last_payout_time = Some(env.block.time);
+        holding.payout_error = None;
+        ALLOCATION_HOLDINGS.save(deps.storage, name.clone(), &holding)?;
+        settled.push(name);
+    }
+    Ok(res.add_attribute("settled", settled.join(",")).add_submessages(msgs))
+}
+
+/// works out what the active allocations paying 'group' pay out, with the weights from before the
+/// change. nothing is written
+fn plan_settlements(
+    deps: Deps,
+    group: &Addr,
+    msg: &MemberChangedHookMsg,
+) -> Result<Vec<Settlement>, ContractError> {
+    let holdings = ALLOCATION_HOLDINGS
+        .range(deps.storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<_>>>()?
+        .into_iter()
+        .filter(|(_, holding)| {
+            matches!(&holding.send_type, SendType::Cw4Group { group: g } if g == group)
+        })
+        .collect::<Vec<_>>();
+    if holdings.is_empty() {
+        return Err(ContractError::Unauthorized {
+            action: "member_changed_hook".to_string(),
+            expected: "a cw4 group an allocation pays".to_string(),
+            actual: group.to_string(),
+        });
+    }
+
+    // the group has already made the change, so put the old weights back
+    let mut members = list_members(&deps.querier, group)?
+        .into_iter()
+        .map(|m| (m.addr, m.weight))
+        .collect::<BTreeMap<_, _>>();
+    for diff in &msg.diffs {
+        match diff.old {
+            Some(weight) => members.insert(diff.key.clone(), weight),
+            None => members.remove(&diff.key),
+        };
+    }
+    let members = members
+        .into_iter()
+        .map(|(addr, weight)| Member {
+            addr,
+            weight,
+        })
+        .collect::<Vec<_>>();
+
+    let mut settlements = vec![];
+    for (name, mut holding) in holdings {
+        if holding.status != AllocationStatus::Active {
+            continue;
+        }
+        let coins = std::mem::take(&mut holding.balance);
+        let tokens = std::mem::take(&mut holding.balance_cw20);
+        let paid = coins.iter().cloned().chain(cw20_as_coins(&tokens)).collect::<Vec<_>>();
+        let payouts = group_payout_msgs(&members, coins, tokens)?;
+        // no one to settle with. it waits for the new members
+        if payouts.is_empty() {
+            continue;
+        }
+        settlements.push(Settlement {
+            name,
+            holding,
+            paid,
+            payouts,
+        });
+    }
+    Ok(settlements)
+}
+
+#[cfg(test)]
+mod test {
+    use cosmwasm_std::{
+        coin,
+        testing::{mock_dependencies, mock_env, mock_info},
+        Uint128,
+    };
+    use pfc_fee_split::{
+        cw4::MemberDiff,
+        fee_split_msg::{
+            AllocationKind, AllocationStatus, BalancePolicy, ExecuteMsg, PayoutMode, SendAfterMode,
+        },
+    };
+
+    use super::*;
+    use crate::{
+        contract::execute,
+        handler::query::{query_allocation, query_allocation_stats},
+        test_helpers::{
+            do_instantiate, mock_cw4_group, two_allocation, ALLOCATION_1, ALLOCATION_2, CREATOR,
+            DENOM_1, GOV_CONTRACT, USER_1,
+        },
+    };
+
+    fn bank_sends(res: &Response) -> Vec<(String, Vec<Coin>)> {
+        res.messages
+            .iter()
+            .filter_map(|m| match &m.msg {
+                CosmosMsg::Bank(BankMsg::Send {
+                    to_address,
+                    amount,
+                }) => Some((to_address.clone(), amount.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn to_group(name: &str, group: &str) -> ExecuteMsg {
+        ExecuteMsg::ModifyAllocationDetail {
+            name: name.to_string(),
+            allocation: Uint128::new(1),
+            send_after: vec![coin(1_000, DENOM_1)],
+            send_after_mode: SendAfterMode::AnyOf,
+            send_type: SendType::Cw4Group {
+                group: Addr::unchecked(group),
+            },
+            send_after_blocks: None,
+            send_after_seconds: None,
+            payout_mode: PayoutMode::Push,
+            kind: AllocationKind::Weighted,
+        }
+    }
+
+    #[test]
+    fn cw4_group() -> Result<(), ContractError> {
+        let mut deps = mock_dependencies();
+        let alloc = two_allocation(&deps.api);
+        let _res = do_instantiate(deps.as_mut(), CREATOR, alloc)?;
+        let env = mock_env();
+        let gov = mock_info(GOV_CONTRACT, &[]);
+        let deposit = |amount: u128| {
+            (
+                mock_info(USER_1, &[coin(amount, DENOM_1)]),
+                ExecuteMsg::Deposit {
+                    flush: false,
+                    table: None,
+                },
+            )
+        };
+
+        mock_cw4_group(
+            &mut deps.querier,
+            "group_contract",
+            &[("member_a", 1), ("member_b", 3), ("member_c", 0)],
+        );
+        let err = execute(deps.as_mut(), env.clone(), gov.clone(), to_group(ALLOCATION_2, "other"))
+            .unwrap_err();
+        assert!(matches!(err, ContractError::Std(_)), "wrong error {:?}", err);
+        execute(deps.as_mut(), env.clone(), gov.clone(), to_group(ALLOCATION_1, "group_contract"))?;
+
+        // split by weight, over both pages of members
+        let (info, msg) = deposit(2_400);
+        let res = execute(deps.as_mut(), env.clone(), info, msg)?;
+        assert_eq!(
+            bank_sends(&res),
+            vec![
+                ("member_a".to_string(), vec![coin(300, DENOM_1)]),
+                ("member_b".to_string(), vec![coin(900, DENOM_1)]),
+            ]
+        );
+        let (info, msg) = deposit(998);
+        let res = execute(deps.as_mut(), env.clone(), info, msg)?;
+        assert!(bank_sends(&res).is_empty());
+
+        let hook = ExecuteMsg::MemberChangedHook(MemberChangedHookMsg {
+            diffs: vec![
+                MemberDiff {
+                    key: "member_b".to_string(),
+                    old: Some(3),
+                    new: None,
+                },
+                MemberDiff {
+                    key: "member_c".to_string(),
+                    old: Some(0),
+                    new: Some(2),
+                },
+            ],
+        });
+        let res = execute(deps.as_mut(), env.clone(), mock_info(USER_1, &[]), hook.clone())?;
+        assert!(res.messages.is_empty());
+        assert_eq!(res.attributes[3].key, "no-action");
+
+        // the group has changed by the time the hook arrives. what was built up uses the old
+        // weights, with the remainder to the largest member
+        mock_cw4_group(&mut deps.querier, "group_contract", &[("member_a", 1), ("member_c", 2)]);
+        let res = execute(deps.as_mut(), env.clone(), mock_info("group_contract", &[]), hook)?;
+        assert_eq!(
+            bank_sends(&res),
+            vec![
+                ("member_a".to_string(), vec![coin(124, DENOM_1)]),
+                ("member_b".to_string(), vec![coin(375, DENOM_1)]),
+            ]
+        );
+        assert_eq!(res.attributes[3].value, ALLOCATION_1);
+        let allocation = query_allocation(deps.as_ref(), ALLOCATION_1.into())?.unwrap();
+        assert!(allocation.balance.is_empty());
+
+        let (info, msg) = deposit(3_000);
+        let res = execute(deps.as_mut(), env.clone(), info, msg)?;
+        assert_eq!(
+            bank_sends(&res),
+            vec![
+                ("member_a".to_string(), vec![coin(500, DENOM_1)]),
+                ("member_c".to_string(), vec![coin(1_000, DENOM_1)]),
+            ]
+        );
+
+        // no one to pay, so it holds on to it
+        mock_cw4_group(&mut deps.querier, "group_contract", &[]);
+        let (info, msg) = deposit(4_000);
+        let res = execute(deps.as_mut(), env, info, msg)?;
+        assert!(bank_sends(&res).is_empty());
+        let allocation = query_allocation(deps.as_ref(), ALLOCATION_1.into())?.unwrap();
+        assert_eq!(allocation.balance, vec![coin(2_000, DENOM_1)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn empty_group() -> Result<(), ContractError> {
+        let mut deps = mock_dependencies();
+        let alloc = two_allocation(&deps.api);
+        let _res = do_instantiate(deps.as_mut(), CREATOR, alloc)?;
+        let env = mock_env();
+        let gov = mock_info(GOV_CONTRACT, &[]);
+        let deposit = |amount: u128| {
+            (
+                mock_info(USER_1, &[coin(amount, DENOM_1)]),
+                ExecuteMsg::Deposit {
+                    flush: false,
+                    table: None,
+                },
+            )
+        };
+        let set_status = |status: AllocationStatus| ExecuteMsg::SetAllocationStatus {
+            name: ALLOCATION_1.to_string(),
+            status,
+        };
+
+        // a paused allocation keeps what it holds
+        mock_cw4_group(&mut deps.querier, "group_contract", &[("member_a", 1), ("member_b", 3)]);
+        execute(deps.as_mut(), env.clone(), gov.clone(), to_group(ALLOCATION_1, "group_contract"))?;
+        execute(deps.as_mut(), env.clone(), gov.clone(), set_status(AllocationStatus::Paused))?;
+        let (info, msg) = deposit(2_000);
+        let res = execute(deps.as_mut(), env.clone(), info, msg)?;
+        assert!(bank_sends(&res).is_empty());
+        mock_cw4_group(&mut deps.querier, "group_contract", &[]);
+        let hook = ExecuteMsg::MemberChangedHook(MemberChangedHookMsg {
+            diffs: vec![
+                MemberDiff {
+                    key: "member_a".to_string(),
+                    old: Some(1),
+                    new: None,
+                },
+                MemberDiff {
+                    key: "member_b".to_string(),
+                    old: Some(3),
+                    new: None,
+                },
+            ],
+        });
+        let group = mock_info("group_contract", &[]);
+        let res = execute(deps.as_mut(), env.clone(), group.clone(), hook.clone())?;
+        assert!(res.messages.is_empty());
+        assert_eq!(res.attributes[3].value, "");
+        let allocation = query_allocation(deps.as_ref(), ALLOCATION_1.into())?.unwrap();
+        assert_eq!(allocation.balance, vec![coin(1_000, DENOM_1)]);
+        assert_eq!(allocation.status, AllocationStatus::Paused);
+        let stats = query_allocation_stats(deps.as_ref(), ALLOCATION_1.into())?;
+        assert!(stats.denoms[0].paid_out.is_zero());
+
+        // once active, it is settled with the old weights
+        execute(deps.as_mut(), env.clone(), gov.clone(), set_status(AllocationStatus::Active))?;
+        let res = execute(deps.as_mut(), env.clone(), group, hook)?;
+        assert_eq!(
+            bank_sends(&res),
+            vec![
+                ("member_a".to_string(), vec![coin(250, DENOM_1)]),
+                ("member_b".to_string(), vec![coin(750, DENOM_1)]),
+            ]
+        );
+        assert_eq!(res.attributes[3].value, ALLOCATION_1);
+        let allocation = query_allocation(deps.as_ref(), ALLOCATION_1.into())?.unwrap();
+        assert!(allocation.balance.is_empty());
+        let stats = query_allocation_stats(deps.as_ref(), ALLOCATION_1.into())?;
+        assert_eq!(stats.denoms[0].paid_out, Uint128::new(1_000));
+
+        // with no one in the group, the hook (from the group) settles nothing, and doesn't fail
+        let (info, msg) = deposit(4_000);
+        let res = execute(deps.as_mut(), env.clone(), info, msg)?;
+        assert!(bank_sends(&res).is_empty());
+        let hook = ExecuteMsg::MemberChangedHook(MemberChangedHookMsg {
+            diffs: vec![],
+        });
+        let res = execute(deps.as_mut(), env.clone(), mock_info("group_contract", &[]), hook)?;
+        assert!(res.messages.is_empty());
+        assert_eq!(res.attributes[3].value, "");
+
+        // a retry holds on to it
+        ALLOCATION_HOLDINGS.update(deps.as_mut().storage, ALLOCATION_1.to_string(), |h| {
+            let mut holding = h.unwrap();
+            holding.payout_error = Some("cannot receive funds".to_string());
+            StdResult::Ok(holding)
+        })?;
+        let retry = ExecuteMsg::RetryPayout {
+            name: ALLOCATION_1.to_string(),
+        };
+        let res = execute(deps.as_mut(), env.clone(), gov.clone(), retry)?;
+        assert!(res.messages.is_empty());
+        let allocation = query_allocation(deps.as_ref(), ALLOCATION_1.into())?.unwrap();
+        assert_eq!(allocation.balance, vec![coin(2_000, DENOM_1)]);
+
+        // replacing it pools what it holds with the new set
+        let mut allocations = two_allocation(&deps.api);
+        allocations[0].send_type = SendType::Cw4Group {
+            group: Addr::unchecked("group_contract"),
+        };
+        allocations[1].send_type = SendType::Wallet {
+            receiver: Addr::unchecked("allocation_2_addr"),
+        };
+        let replace = ExecuteMsg::ReplaceAllocations {
+            allocations,
+            balance_policy: BalancePolicy::PayOut,
+        };
+        // (the steak allocation pays out as usual)
+        let res = execute(deps.as_mut(), env.clone(), gov.clone(), replace)?;
+        assert_eq!(res.messages.len(), 1);
+        assert!(bank_sends(&res).is_empty());
+        let allocation = query_allocation(deps.as_ref(), ALLOCATION_1.into())?.unwrap();
+        assert_eq!(allocation.balance, vec![coin(1_000, DENOM_1)]);
+        let allocation = query_allocation(deps.as_ref(), ALLOCATION_2.into())?.unwrap();
+        assert_eq!(allocation.balance, vec![coin(1_000, DENOM_1)]);
+
+        // and removing it gives it to the rest
+        let remove = ExecuteMsg::RemoveAllocationDetail {
+            name: ALLOCATION_1.to_string(),
+        };
+        let res = execute(deps.as_mut(), env, gov, remove)?;
+        assert!(res.messages.is_empty());
+        let allocation = query_allocation(deps.as_ref(), ALLOCATION_2.into())?.unwrap();
+        assert_eq!(allocation.balance, vec![coin(2_000, DENOM_1)]);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,395 @@
+use std::convert::TryFrom;
+
+use cosmwasm_std::{DepsMut, Reply, Response, SubMsgResult};
+use pfc_fee_split::fee_split_msg::{IbcLifecycleComplete, PendingIbcTransfer};
+
+use crate::{
+    error::ContractError,
+    handler::{exec::add_to_balance, payout, stats},
+    proto::MsgTransferResponse,
+    state::{ALLOCATION_HOLDINGS, IBC_TRANSFER_QUEUE, PENDING_IBC_TRANSFERS},
+};
+
+/// the transfer was accepted by the chain: remember it by sequence until we hear back.
+/// if it wasn't, it goes back into the allocation's balance (like any other failed payout)
+pub fn reply_ibc_transfer(deps: DepsMut, msg: Reply) -> Result<Response, ContractError> {
+    let transfer = IBC_TRANSFER_QUEUE
+        .pop_front(deps.storage)?
+        .ok_or(ContractError::IbcTransferNotQueued {})?;
+    let response = match msg.result {
+        SubMsgResult::Ok(response) => response,
+        SubMsgResult::Err(error) => {
+            return payout::credit_failed_payout(
+                deps.storage,
+                transfer.name,
+                vec![transfer.coin],
+                vec![],
+                error,
+            );
+        },
+    };
+    // the transfer has gone, but without its sequence the ack or timeout can't be matched to it.
+    // it counts as paid, and a refund arrives untracked (for sweep to split)
+    let Some(sequence) =
+        response.data.and_then(|data| MsgTransferResponse::try_from(data).ok()).map(|r| r.sequence)
+    else {
+        return Ok(Response::new()
+            .add_attribute("action", "ibc_transfer")
+            .add_attribute("name", transfer.name)
+            .add_attribute("channel", transfer.channel_id)
+            .add_attribute("amount", transfer.coin.to_string())
+            .add_attribute("untracked", "no sequence in the reply"));
+    };
+
+    let transfer = PendingIbcTransfer {
+        sequence,
+        ..transfer
+    };
+    PENDING_IBC_TRANSFERS.save(deps.storage, (&transfer.channel_id, sequence), &transfer)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "ibc_transfer")
+        .add_attribute("name", transfer.name)
+        .add_attribute("channel", transfer.channel_id)
+        .add_attribute("sequence", sequence.to_string())
+        .add_attribute("amount", transfer.coin.to_string()))
+}
+
+/// ibc-hooks callback. failed & timed out transfers go back into the allocation's balance
+pub fn sudo_ibc_lifecycle_complete(
+    deps: DepsMut,
+    msg: IbcLifecycleComplete,
+) -> Result<Response, ContractError> {
+    match msg {
+        IbcLifecycleComplete::IbcAck {
+            channel,
+            sequence,
+            success: true,
+            ..
+        } => {
+            PENDING_IBC_TRANSFERS.remove(deps.storage, (&channel, sequence));
+            Ok(Response::new()
+                .add_attribute("action", "ibc_ack")
+                .add_attribute("channel", channel)
+                .add_attribute("sequence", sequence.to_string()))
+        },
+        IbcLifecycleComplete::IbcAck {
+            channel,
+            sequence,
+            ack,
+            success: false,
+        } => credit_back(deps, "ibc_ack_failed", channel, sequence)
+            .map(|res| res.add_attribute("ack", ack)),
+        IbcLifecycleComplete::IbcTimeout {
+            channel,
+            sequence,
+        } => credit_back(deps, "ibc_timeout", channel, sequence),
+    }
+}
+
+fn credit_back(
+    deps: DepsMut,
+    action: &str,
+    channel: String,
+    sequence: u64,
+) -> Result<Response, ContractError> {
+    let res = Response::new()
+        .add_attribute("action", action)
+        .add_attribute("channel", &channel)
+        .add_attribute("sequence", sequence.to_string());
+    let Some(transfer) = PENDING_IBC_TRANSFERS.may_load(deps.storage, (&channel, sequence))? else {
+        return Ok(res.add_attribute("no-action", "unknown transfer"));
+    };
+    PENDING_IBC_TRANSFERS.remove(deps.storage, (&channel, sequence));
+    stats::unrecord_payout(deps.storage, &transfer.name, &transfer.coin)?;
+
+    let res = res
+        .add_attribute("name", &transfer.name)
+        .add_attribute("amount", transfer.coin.to_string());
+    match ALLOCATION_HOLDINGS.may_load(deps.storage, transfer.name.clone())? {
+        Some(mut allocation_holding) => {
+            add_to_balance(&mut allocation_holding.balance, transfer.coin);
+            ALLOCATION_HOLDINGS.save(deps.storage, transfer.name, &allocation_holding)?;
+            Ok(res)
+        },
+        // the allocation was removed while the transfer was in flight. reconcile will pick it up
+        None => Ok(res.add_attribute("no-action", "allocation removed")),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use cosmwasm_std::{
+        coin,
+        testing::{mock_dependencies, mock_env, mock_info},
+        Addr, CosmosMsg, ReplyOn, SubMsgResponse, Uint128,
+    };
+    use pfc_fee_split::fee_split_msg::{
+        AllocationDetail, AllocationKind, ExecuteMsg, PayoutMode, SendAfterMode, SendType, SudoMsg,
+    };
+
+    use super::*;
+    use crate::{
+        contract::{execute, reply, sudo},
+        handler::{
+            exec::ibc_memo,
+            query::{query_allocation, query_allocation_stats, query_pending_ibc_transfers},
+        },
+        proto::MsgTransfer,
+        state::REPLY_IBC_TRANSFER,
+        test_helpers::{do_instantiate, CREATOR, DENOM_1, USER_1},
+    };
+
+    #[test]
+    fn memo() -> Result<(), ContractError> {
+        let this = cosmwasm_std::Addr::unchecked("splitter");
+        assert_eq!(ibc_memo(None, &this)?, r#"{"ibc_callback":"splitter"}"#);
+        assert_eq!(ibc_memo(Some(" {} "), &this)?, r#"{"ibc_callback":"splitter"}"#);
+        assert_eq!(
+            ibc_memo(Some(r#"{"forward":{"port":"transfer"}}"#), &this)?,
+            r#"{"ibc_callback":"splitter","forward":{"port":"transfer"}}"#
+        );
+        // rather than sending it without
+        for bad in [r#"["forward"]"#, r#"{"ibc_callback":"elsewhere"}"#] {
+            assert!(ibc_memo(Some(bad), &this).is_err(), "{}", bad);
+        }
+
+        let mut deps = mock_dependencies();
+        let this = mock_env().contract.address;
+        let send_type = |memo: &str| SendType::IbcTransfer {
+            channel_id: "channel-1".to_string(),
+            receiver: "osmo1receiver".to_string(),
+            timeout_seconds: 600,
+            memo: Some(memo.to_string()),
+        };
+        send_type(r#" {"forward":{"port":"transfer"}} "#).verify_details(&deps.as_mut(), &this)?;
+        for bad in [
+            "not json",
+            "{not json}",
+            r#"{"forward":}"#,
+            r#"["forward"]"#,
+            r#"{"ibc_callback":"elsewhere"}"#,
+        ] {
+            assert!(send_type(bad).verify_details(&deps.as_mut(), &this).is_err(), "{}", bad);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn transfer_timeout() -> Result<(), ContractError> {
+        let mut deps = mock_dependencies();
+        let allocation = vec![AllocationDetail {
+            name: "remote".to_string(),
+            allocation: Uint128::new(1),
+            send_after: vec![coin(0, DENOM_1)],
+            send_after_mode: SendAfterMode::AnyOf,
+            send_type: SendType::IbcTransfer {
+                channel_id: "channel-1".to_string(),
+                receiver: "osmo1receiver".to_string(),
+                timeout_seconds: 600,
+                memo: None,
+            },
+            send_after_blocks: None,
+            send_after_seconds: None,
+            payout_mode: PayoutMode::Push,
+            kind: AllocationKind::Weighted,
+        }];
+        do_instantiate(deps.as_mut(), CREATOR, allocation)?;
+
+        let env = mock_env();
+        let msg = ExecuteMsg::Deposit {
+            flush: false,
+            table: None,
+        };
+        let info = mock_info(USER_1, &[coin(1_000, DENOM_1)]);
+        let res = execute(deps.as_mut(), env.clone(), info, msg)?;
+        assert_eq!(res.messages.len(), 1);
+        assert_eq!(res.messages[0].id, REPLY_IBC_TRANSFER);
+        match &res.messages[0].msg {
+            CosmosMsg::Stargate {
+                type_url,
+                value,
+            } => {
+                assert_eq!(type_url, "/ibc.applications.transfer.v1.MsgTransfer");
+                let transfer = MsgTransfer::try_from(value.clone())?;
+                assert_eq!(transfer.source_channel, "channel-1");
+                assert_eq!(transfer.receiver, "osmo1receiver");
+                assert_eq!(transfer.token, Some(coin(1_000, DENOM_1).into()));
+                assert_eq!(transfer.timeout_timestamp, env.block.time.plus_seconds(600).nanos());
+                assert_eq!(transfer.memo, ibc_memo(None, &env.contract.address)?);
+            },
+            _ => panic!("wrong message {:?}", res.messages[0].msg),
+        }
+
+        reply(
+            deps.as_mut(),
+            env.clone(),
+            Reply {
+                id: REPLY_IBC_TRANSFER,
+                result: SubMsgResult::Ok(SubMsgResponse {
+                    events: vec![],
+                    data: Some(
+                        MsgTransferResponse {
+                            sequence: 7,
+                        }
+                        .into(),
+                    ),
+                }),
+            },
+        )?;
+        assert!(IBC_TRANSFER_QUEUE.is_empty(&deps.storage)?);
+        let pending = query_pending_ibc_transfers(deps.as_ref(), None, None)?;
+        assert_eq!(
+            pending.transfers,
+            vec![PendingIbcTransfer {
+                name: "remote".to_string(),
+                channel_id: "channel-1".to_string(),
+                sequence: 7,
+                coin: coin(1_000, DENOM_1),
+            }]
+        );
+        let holding = query_allocation(deps.as_ref(), "remote".to_string())?.unwrap();
+        assert!(holding.balance.is_empty());
+
+        // a callback for someone else's transfer is ignored
+        let res = sudo(
+            deps.as_mut(),
+            env.clone(),
+            SudoMsg::IbcLifecycleComplete(IbcLifecycleComplete::IbcTimeout {
+                channel: "channel-1".to_string(),
+                sequence: 8,
+            }),
+        )?;
+        assert!(res.attributes.iter().any(|a| a.key == "no-action"));
+
+        sudo(
+            deps.as_mut(),
+            env,
+            SudoMsg::IbcLifecycleComplete(IbcLifecycleComplete::IbcTimeout {
+                channel: "channel-1".to_string(),
+                sequence: 7,
+            }),
+        )?;
+        let holding = query_allocation(deps.as_ref(), "remote".to_string())?.unwrap();
+        assert_eq!(holding.balance, vec![coin(1_000, DENOM_1)]);
+        // it wasn't paid out after all
+        let stats = query_allocation_stats(deps.as_ref(), "remote".to_string())?;
+        assert_eq!(stats.denoms[0].deposited, Uint128::new(1_000));
+        assert!(stats.denoms[0].paid_out.is_zero());
+        let pending = query_pending_ibc_transfers(deps.as_ref(), None, None)?;
+        assert!(pending.transfers.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn transfer_fails() -> Result<(), ContractError> {
+        let mut deps = mock_dependencies();
+        let allocation = |name: &str, send_type: SendType| AllocationDetail {
+            name: name.to_string(),
+            allocation: Uint128::new(1),
+            send_after: vec![coin(0, DENOM_1)],
+            send_after_mode: SendAfterMode::AnyOf,
+            send_type,
+            send_after_blocks: None,
+            send_after_seconds: None,
+            payout_mode: PayoutMode::Push,
+            kind: AllocationKind::Weighted,
+        };
+        let allocations = vec![
+            allocation(
+                "local",
+                SendType::Wallet {
+                    receiver: Addr::unchecked("local_addr"),
+                },
+            ),
+            allocation(
+                "remote",
+                SendType::IbcTransfer {
+                    channel_id: "channel-1".to_string(),
+                    receiver: "osmo1receiver".to_string(),
+                    timeout_seconds: 600,
+                    memo: None,
+                },
+            ),
+        ];
+        do_instantiate(deps.as_mut(), CREATOR, allocations)?;
+
+        let env = mock_env();
+        let msg = ExecuteMsg::Deposit {
+            flush: false,
+            table: None,
+        };
+        let info = mock_info(USER_1, &[coin(1_000, DENOM_1)]);
+        let res = execute(deps.as_mut(), env.clone(), info, msg.clone())?;
+        assert_eq!(res.messages.len(), 2);
+        // the transfer failing comes back to us, rather than failing the deposit
+        assert_eq!(res.messages[1].id, REPLY_IBC_TRANSFER);
+        assert_eq!(res.messages[1].reply_on, ReplyOn::Always);
+
+        let res = reply(
+            deps.as_mut(),
+            env.clone(),
+            Reply {
+                id: REPLY_IBC_TRANSFER,
+                result: SubMsgResult::Err("channel closed".to_string()),
+            },
+        )?;
+        assert_eq!(res.attributes[0].value, "payout_failed");
+        assert!(IBC_TRANSFER_QUEUE.is_empty(&deps.storage)?);
+        assert!(query_pending_ibc_transfers(deps.as_ref(), None, None)?.transfers.is_empty());
+        let holding = query_allocation(deps.as_ref(), "remote".to_string())?.unwrap();
+        assert_eq!(holding.balance, vec![coin(500, DENOM_1)]);
+        assert_eq!(holding.payout_error, Some("channel closed".to_string()));
+        let stats = query_allocation_stats(deps.as_ref(), "remote".to_string())?;
+        assert!(stats.denoms[0].paid_out.is_zero());
+        // the other allocation was paid
+        let stats = query_allocation_stats(deps.as_ref(), "local".to_string())?;
+        assert_eq!(stats.denoms[0].paid_out, Uint128::new(500));
+
+        // a reply without the sequence doesn't fail the deposit. it just can't be tracked
+        let info = mock_info(USER_1, &[coin(1_000, DENOM_1)]);
+        execute(deps.as_mut(), env.clone(), info, msg)?;
+        let res = reply(
+            deps.as_mut(),
+            env,
+            Reply {
+                id: REPLY_IBC_TRANSFER,
+                result: SubMsgResult::Ok(SubMsgResponse {
+                    events: vec![],
+                    data: None,
+                }),
+            },
+        )?;
+        assert!(res.attributes.iter().any(|a| a.key == "untracked"));
+        assert!(IBC_TRANSFER_QUEUE.is_empty(&deps.storage)?);
+        assert!(query_pending_ibc_transfers(deps.as_ref(), None, None)?.transfers.is_empty());
+        let holding = query_allocation(deps.as_ref(), "remote".to_string())?.unwrap();
+        assert!(holding.balance.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn instantiate_memo() {
+        let mut deps = mock_dependencies();
+        let allocation = vec![AllocationDetail {
+            name: "remote".to_string(),
+            allocation: Uint128::new(1),
+            send_after: vec![coin(0, DENOM_1)],
+            send_after_mode: SendAfterMode::AnyOf,
+            send_type: SendType::IbcTransfer {
+                channel_id: "channel-1".to_string(),
+                receiver: "osmo1receiver".to_string(),
+                timeout_seconds: 600,
+                memo: Some(r#"{"ibc_callback":"elsewhere"}"#.to_string()),
+            },
+            send_after_blocks: None,
+            send_after_seconds: None,
+            payout_mode: PayoutMode::Push,
+            kind: AllocationKind::Weighted,
+        }];
+        let err = do_instantiate(deps.as_mut(), CREATOR, allocation).unwrap_err();
+        assert!(matches!(err, ContractError::Std(_)), "wrong error {:?}", err);
+    }
+}
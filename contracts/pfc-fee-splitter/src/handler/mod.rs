@@ -1,2 +1,9 @@
 pub mod exec;
+pub mod filter;
+pub mod group;
+pub mod ibc;
+pub mod payout;
 pub mod query;
+pub mod receiver;
+pub mod stats;
+pub mod timelock;
@@ -0,0 +1,484 @@
+use cosmwasm_std::{
+    Addr, Coin, DepsMut, Env, MessageInfo, Order, QuerierWrapper, Reply, ReplyOn, Response,
+    StdResult, Storage, SubMsg, SubMsgResult,
+};
+use cw20::Cw20CoinVerified;
+use pfc_fee_split::fee_split_msg::{AllocationHolding, AllocationStatus};
+
+use crate::{
+    error::ContractError,
+    handler::{
+        exec::{
+            add_to_balance, assert_flush_allowed, coins_to_string, cw20_as_coins,
+            generate_payout_msgs, record_ibc_transfers, sends_cw20, PayoutMsg,
+        },
+        group, stats,
+    },
+    state::{
+        PayoutReply, ALLOCATION_HOLDINGS, CLAIM_DELEGATES, PAYOUT_REPLIES, PAYOUT_REPLY_ID,
+        REPLY_PAYOUT,
+    },
+};
+
+/// sends payouts with reply_on_error, so one failing doesn't take the rest of the transaction
+/// with it. IBC transfers already have their own reply (which handles failures the same way),
+/// and are left as they are
+pub(crate) fn track_payouts(
+    storage: &mut dyn Storage,
+    env: &Env,
+    name: &str,
+    msgs: Vec<PayoutMsg>,
+) -> Result<Vec<SubMsg>, ContractError> {
+    if msgs.is_empty() {
+        return Ok(vec![]);
+    }
+    let stale = PAYOUT_REPLIES
+        .range(storage, None, None, Order::Ascending)
+        .filter(|item| item.as_ref().map(|(_, r)| r.height < env.block.height).unwrap_or(true))
+        .map(|item| item.map(|(id, _)| id))
+        .collect::<StdResult<Vec<_>>>()?;
+    for id in stale {
+        PAYOUT_REPLIES.remove(storage, id);
+    }
+
+    let mut last_id = PAYOUT_REPLY_ID.may_load(storage)?.unwrap_or_default();
+    let mut sub_msgs = Vec::with_capacity(msgs.len());
+    for payout in msgs {
+        if payout.msg.reply_on != ReplyOn::Never {
+            sub_msgs.push(payout.msg);
+            continue;
+        }
+        last_id += 1;
+        let id = REPLY_PAYOUT + last_id;
+        PAYOUT_REPLIES.save(
+            storage,
+            id,
+            &PayoutReply {
+                name: name.to_string(),
+                coins: payout.coins,
+                tokens: payout.tokens,
+                height: env.block.height,
+            },
+        )?;
+        sub_msgs.push(SubMsg::reply_on_error(payout.msg.msg, id));
+    }
+    PAYOUT_REPLY_ID.save(storage, &last_id)?;
+    Ok(sub_msgs)
+}
+
+/// a payout failed. see credit_failed_payout
+pub fn reply_payout(deps: DepsMut, msg: Reply) -> Result<Response, ContractError> {
+    let payout =
+        PAYOUT_REPLIES.may_load(deps.storage, msg.id)?.ok_or(ContractError::UnknownReplyId {
+            id: msg.id,
+        })?;
+    PAYOUT_REPLIES.remove(deps.storage, msg.id);
+    let SubMsgResult::Err(error) = msg.result else {
+        return Ok(Response::new());
+    };
+    credit_failed_payout(deps.storage, payout.name, payout.coins, payout.tokens, error)
+}
+
+/// puts what a failed payout carried back into the allocation's balance, and keeps the error on
+/// the allocation until it pays out again
+pub(crate) fn credit_failed_payout(
+    storage: &mut dyn Storage,
+    name: String,
+    coins: Vec<Coin>,
+    tokens: Vec<Cw20CoinVerified>,
+    error: String,
+) -> Result<Response, ContractError> {
+    let returned = coins.iter().cloned().chain(cw20_as_coins(&tokens)).collect::<Vec<Coin>>();
+    for coin in &returned {
+        stats::unrecord_payout(storage, &name, coin)?;
+    }
+    let res = Response::new()
+        .add_attribute("action", "payout_failed")
+        .add_attribute("name", &name)
+        .add_attribute("amount", coins_to_string(&returned))
+        .add_attribute("error", &error);
+
+    match ALLOCATION_HOLDINGS.may_load(storage, name.clone())? {
+        Some(mut allocation_holding) => {
+            for coin in coins {
+                add_to_balance(&mut allocation_holding.balance, coin);
+            }
+            for token in tokens {
+                match allocation_holding
+                    .balance_cw20
+                    .iter_mut()
+                    .find(|t| t.address == token.address)
+                {
+                    Some(existing) => existing.amount += token.amount,
+                    None => allocation_holding.balance_cw20.push(token),
+                }
+            }
+            allocation_holding.payout_error = Some(error);
+            ALLOCATION_HOLDINGS.save(storage, name, &allocation_holding)?;
+            Ok(res)
+        },
+        // it was being paid out as it was removed. sweep will pick the native coins up
+        None => Ok(res.add_attribute("no-action", "allocation removed")),
+    }
+}
+
+/// pays out everything an allocation holds, after a failed payout
+pub fn execute_retry_payout(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    name: String,
+) -> Result<Response, ContractError> {
+    assert_flush_allowed(deps.as_ref(), &info.sender, true)?;
+    let mut allocation_holding = ALLOCATION_HOLDINGS
+        .may_load(deps.storage, name.clone())?
+        .ok_or_else(|| ContractError::AllocationNotFound {
+            name: name.clone(),
+        })?;
+    if allocation_holding.payout_error.is_none() {
+        return Err(ContractError::NoFailedPayout {
+            name,
+        });
+    }
+
+    let (paid, msgs) =
+        pay_out_all(deps.storage, &deps.querier, &env, &name, &mut allocation_holding)?;
+    allocation_holding.payout_error = None;
+    ALLOCATION_HOLDINGS.save(deps.storage, name.clone(), &allocation_holding)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "retry_payout")
+        .add_attribute("from", info.sender)
+        .add_attribute("name", name)
+        .add_attribute("amount", coins_to_string(&paid))
+        .add_submessages(msgs))
+}
+
+/// pays out everything an allocation holds, to its receiver. for the receiver, or their delegate
+pub fn execute_claim(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    name: String,
+) -> Result<Response, ContractError> {
+    let mut allocation_holding = ALLOCATION_HOLDINGS
+        .may_load(deps.storage, name.clone())?
+        .ok_or_else(|| ContractError::AllocationNotFound {
+            name: name.clone(),
+        })?;
+    let receiver = assert_receiver(&allocation_holding, &info.sender, "claim")?;
+    if info.sender != receiver
+        && CLAIM_DELEGATES.may_load(deps.storage, (&name, &receiver))?.as_ref()
+            != Some(&info.sender)
+    {
+        return Err(ContractError::Unauthorized {
+            action: "claim".to_string(),
+            expected: receiver.to_string(),
+            actual: info.sender.to_string(),
+        });
+    }
+
+    let res = Response::new()
+        .add_attribute("action", "claim")
+        .add_attribute("from", &info.sender)
+        .add_attribute("name", &name);
+    if allocation_holding.balance.is_empty() && allocation_holding.balance_cw20.is_empty() {
+        return Ok(res.add_attribute("no-action", "nothing to claim"));
+    }
+    let (paid, msgs) =
+        pay_out_all(deps.storage, &deps.querier, &env, &name, &mut allocation_holding)?;
+    allocation_holding.payout_error = None;
+    ALLOCATION_HOLDINGS.save(deps.storage, name, &allocation_holding)?;
+
+    Ok(res.add_attribute("amount", coins_to_string(&paid)).add_submessages(msgs))
+}
+
+/// lets someone else claim for an allocation. only its receiver can set this
+pub fn execute_set_claim_delegate(
+    deps: DepsMut,
+    info: MessageInfo,
+    name: String,
+    delegate: Option<String>,
+) -> Result<Response, ContractError> {
+    let allocation_holding =
+        ALLOCATION_HOLDINGS.may_load(deps.storage, name.clone())?.ok_or_else(|| {
+            ContractError::AllocationNotFound {
+                name: name.clone(),
+            }
+        })?;
+    let receiver = assert_receiver(&allocation_holding, &info.sender, "set_claim_delegate")?;
+    if info.sender != receiver {
+        return Err(ContractError::Unauthorized {
+            action: "set_claim_delegate".to_string(),
+            expected: receiver.to_string(),
+            actual: info.sender.to_string(),
+        });
+    }
+
+    let res = Response::new()
+        .add_attribute("action", "set_claim_delegate")
+        .add_attribute("from", &info.sender)
+        .add_attribute("name", &name);
+    match delegate {
+        Some(delegate) => {
+            let delegate = deps.api.addr_validate(&delegate)?;
+            CLAIM_DELEGATES.save(deps.storage, (&name, &receiver), &delegate)?;
+            Ok(res.add_attribute("delegate", delegate))
+        },
+        None => {
+            CLAIM_DELEGATES.remove(deps.storage, (&name, &receiver));
+            Ok(res.add_attribute("delegate", "none"))
+        },
+    }
+}
+
+/// the allocation's receiver on this chain. send types without one can't be claimed
+pub(crate) fn assert_receiver(
+    allocation_holding: &AllocationHolding,
+    sender: &Addr,
+    action: &str,
+) -> Result<Addr, ContractError> {
+    allocation_holding.send_type.local_receiver().cloned().ok_or_else(|| {
+        ContractError::Unauthorized {
+            action: action.to_string(),
+            expected: format!("receiver of {}", allocation_holding.send_type),
+            actual: sender.to_string(),
+        }
+    })
+}
+
+/// takes the whole balance out of the allocation, and returns what was paid & the messages
+/// paying it. cw20 balances stay put for send types that can't take them.
+/// paused & frozen allocations don't pay out, and a cw4 group with no weight holds on to it
+fn pay_out_all(
+    storage: &mut dyn Storage,
+    querier: &QuerierWrapper,
+    env: &Env,
+    name: &str,
+    allocation_holding: &mut AllocationHolding,
+) -> Result<(Vec<Coin>, Vec<SubMsg>), ContractError> {
+    if allocation_holding.status != AllocationStatus::Active {
+        return Err(ContractError::AllocationNotActive {
+            name: name.to_string(),
+            status: allocation_holding.status.to_string(),
+        });
+    }
+    if !group::has_weight(querier, &allocation_holding.send_type)? {
+        return Ok((vec![], vec![]));
+    }
+    let coins = std::mem::take(&mut allocation_holding.balance);
+    let (tokens, held_cw20) = if sends_cw20(&allocation_holding.send_type) {
+        (std::mem::take(&mut allocation_holding.balance_cw20), vec![])
+    } else {
+        (vec![], std::mem::take(&mut allocation_holding.balance_cw20))
+    };
+    record_ibc_transfers(storage, name, &allocation_holding.send_type, &coins)?;
+    let paid = coins.iter().cloned().chain(cw20_as_coins(&tokens)).collect::<Vec<_>>();
+    stats::record_payout(storage, name, &paid, env.block.height)?;
+    let msgs = generate_payout_msgs(querier, env, &allocation_holding.send_type, coins, tokens)?;
+    let msgs = track_payouts(storage, env, name, msgs)?;
+    if !msgs.is_empty() {
+        allocation_holding.last_payout_height = Some(env.block.height);
+        allocation_holding.last_payout_time = Some(env.block.time);
+    }
+    allocation_holding.balance_cw20 = held_cw20;
+    Ok((paid, msgs))
+}
+
+#[cfg(test)]
+mod test {
+    use cosmwasm_std::{
+        coin,
+        testing::{mock_dependencies, mock_env, mock_info},
+        Addr, BankMsg, CosmosMsg, Uint128,
+    };
+    use pfc_fee_split::fee_split_msg::{
+        AllocationKind, ExecuteMsg, PayoutMode, SendAfterMode, SendType,
+    };
+
+    use super::*;
+    use crate::{
+        contract::{execute, reply},
+        handler::query::{query_allocation, query_allocation_stats, query_claim_delegate},
+        test_helpers::{
+            do_instantiate, two_allocation, ALLOCATION_1, CREATOR, DENOM_1, GOV_CONTRACT, USER_1,
+        },
+    };
+
+    #[test]
+    fn failed_payout() -> Result<(), ContractError> {
+        let mut deps = mock_dependencies();
+        let alloc = two_allocation(&deps.api);
+        let _res = do_instantiate(deps.as_mut(), CREATOR, alloc)?;
+        let mut env = mock_env();
+
+        let msg = ExecuteMsg::Deposit {
+            flush: false,
+            table: None,
+        };
+        let info = mock_info(USER_1, &[coin(5_000, DENOM_1)]);
+        let res = execute(deps.as_mut(), env.clone(), info, msg.clone())?;
+        assert_eq!(res.messages.len(), 1);
+        assert_eq!(res.messages[0].reply_on, ReplyOn::Error);
+        let id = res.messages[0].id;
+
+        // the bank send fails
+        let failure = Reply {
+            id,
+            result: SubMsgResult::Err("cannot receive funds".to_string()),
+        };
+        let res = reply(deps.as_mut(), env.clone(), failure.clone())?;
+        assert_eq!(res.attributes[0].value, "payout_failed");
+        let allocation = query_allocation(deps.as_ref(), ALLOCATION_1.into())?.unwrap();
+        assert_eq!(allocation.balance, vec![coin(2_500, DENOM_1)]);
+        assert_eq!(allocation.payout_error, Some("cannot receive funds".to_string()));
+        let stats = query_allocation_stats(deps.as_ref(), ALLOCATION_1.into())?;
+        assert!(stats.denoms[0].paid_out.is_zero());
+        // each reply is only handled once
+        assert!(reply(deps.as_mut(), env.clone(), failure).is_err());
+
+        let retry = ExecuteMsg::RetryPayout {
+            name: ALLOCATION_1.to_string(),
+        };
+        let err =
+            execute(deps.as_mut(), env.clone(), mock_info(USER_1, &[]), retry.clone()).unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized { .. }), "wrong error {:?}", err);
+        let gov = mock_info(GOV_CONTRACT, &[]);
+        let res = execute(deps.as_mut(), env.clone(), gov.clone(), retry.clone())?;
+        assert_eq!(res.messages.len(), 1);
+        assert_eq!(res.messages[0].reply_on, ReplyOn::Error);
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "allocation_1_addr".to_string(),
+                amount: vec![coin(2_500, DENOM_1)],
+            })
+        );
+        let allocation = query_allocation(deps.as_ref(), ALLOCATION_1.into())?.unwrap();
+        assert!(allocation.balance.is_empty());
+        assert!(allocation.payout_error.is_none());
+        let stats = query_allocation_stats(deps.as_ref(), ALLOCATION_1.into())?;
+        assert_eq!(stats.denoms[0].paid_out, Uint128::new(2_500));
+
+        let err = execute(deps.as_mut(), env.clone(), gov, retry).unwrap_err();
+        assert!(matches!(err, ContractError::NoFailedPayout { .. }), "wrong error {:?}", err);
+
+        // replies from earlier blocks can't come back, so they are cleared out
+        env.block.height += 1;
+        let info = mock_info(USER_1, &[coin(5_000, DENOM_1)]);
+        let res = execute(deps.as_mut(), env, info, msg)?;
+        assert_eq!(PAYOUT_REPLIES.keys(&deps.storage, None, None, Order::Ascending).count(), 1);
+        assert!(PAYOUT_REPLIES.has(&deps.storage, res.messages[0].id));
+
+        Ok(())
+    }
+
+    #[test]
+    fn pull_claim() -> Result<(), ContractError> {
+        let mut deps = mock_dependencies();
+        let mut alloc = two_allocation(&deps.api);
+        alloc[0].payout_mode = PayoutMode::Pull;
+        let _res = do_instantiate(deps.as_mut(), CREATOR, alloc)?;
+        let env = mock_env();
+
+        // over the threshold, but it waits to be claimed
+        let deposit = ExecuteMsg::Deposit {
+            flush: false,
+            table: None,
+        };
+        let info = mock_info(USER_1, &[coin(5_000, DENOM_1)]);
+        let res = execute(deps.as_mut(), env.clone(), info.clone(), deposit.clone())?;
+        assert!(res.messages.is_empty());
+        let allocation = query_allocation(deps.as_ref(), ALLOCATION_1.into())?.unwrap();
+        assert_eq!(allocation.balance, vec![coin(2_500, DENOM_1)]);
+
+        let claim = ExecuteMsg::Claim {
+            name: ALLOCATION_1.to_string(),
+        };
+        let err =
+            execute(deps.as_mut(), env.clone(), mock_info(USER_1, &[]), claim.clone()).unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized { .. }), "wrong error {:?}", err);
+        let receiver = mock_info("allocation_1_addr", &[]);
+        let res = execute(deps.as_mut(), env.clone(), receiver.clone(), claim.clone())?;
+        assert_eq!(res.messages.len(), 1);
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "allocation_1_addr".to_string(),
+                amount: vec![coin(2_500, DENOM_1)],
+            })
+        );
+        let allocation = query_allocation(deps.as_ref(), ALLOCATION_1.into())?.unwrap();
+        assert!(allocation.balance.is_empty());
+        let res = execute(deps.as_mut(), env.clone(), receiver.clone(), claim.clone())?;
+        assert!(res.messages.is_empty());
+        assert_eq!(res.attributes[3].key, "no-action");
+
+        // a delegate claims, but the funds still go to the receiver
+        let set_delegate = ExecuteMsg::SetClaimDelegate {
+            name: ALLOCATION_1.to_string(),
+            delegate: Some(USER_1.to_string()),
+        };
+        let err = execute(deps.as_mut(), env.clone(), mock_info(USER_1, &[]), set_delegate.clone())
+            .unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized { .. }), "wrong error {:?}", err);
+        execute(deps.as_mut(), env.clone(), receiver, set_delegate)?;
+        let delegate = query_claim_delegate(deps.as_ref(), ALLOCATION_1.into())?;
+        assert_eq!(delegate.receiver, Some("allocation_1_addr".to_string()));
+        assert_eq!(delegate.delegate, Some(USER_1.to_string()));
+
+        execute(deps.as_mut(), env.clone(), info.clone(), deposit.clone())?;
+        let res = execute(deps.as_mut(), env.clone(), mock_info(USER_1, &[]), claim.clone())?;
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "allocation_1_addr".to_string(),
+                amount: vec![coin(2_500, DENOM_1)],
+            })
+        );
+
+        // a new receiver doesn't inherit the delegate
+        let modify = |send_type: SendType| ExecuteMsg::ModifyAllocationDetail {
+            name: ALLOCATION_1.to_string(),
+            allocation: Uint128::new(1),
+            send_after: vec![coin(1_000, DENOM_1)],
+            send_after_mode: SendAfterMode::AnyOf,
+            send_type,
+            send_after_blocks: None,
+            send_after_seconds: None,
+            payout_mode: PayoutMode::Pull,
+            kind: AllocationKind::Weighted,
+        };
+        let gov = mock_info(GOV_CONTRACT, &[]);
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            gov.clone(),
+            modify(SendType::Wallet {
+                receiver: Addr::unchecked("new_receiver"),
+            }),
+        )?;
+        execute(deps.as_mut(), env.clone(), info, deposit)?;
+        let err =
+            execute(deps.as_mut(), env.clone(), mock_info(USER_1, &[]), claim.clone()).unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized { .. }), "wrong error {:?}", err);
+        let delegate = query_claim_delegate(deps.as_ref(), ALLOCATION_1.into())?;
+        assert_eq!(delegate.delegate, None);
+        let res = execute(deps.as_mut(), env.clone(), mock_info("new_receiver", &[]), claim)?;
+        assert_eq!(res.messages.len(), 1);
+
+        // nobody could claim a burn
+        let err = execute(deps.as_mut(), env, gov, modify(SendType::Burn {})).unwrap_err();
+        assert!(matches!(err, ContractError::PullWithoutReceiver { .. }), "wrong error {:?}", err);
+
+        // or start out with one
+        let mut deps = mock_dependencies();
+        let mut alloc = two_allocation(&deps.api);
+        alloc[0].payout_mode = PayoutMode::Pull;
+        alloc[0].send_type = SendType::CommunityPool {};
+        let err = do_instantiate(deps.as_mut(), CREATOR, alloc).unwrap_err();
+        assert!(matches!(err, ContractError::PullWithoutReceiver { .. }), "wrong error {:?}", err);
+
+        Ok(())
+    }
+}
@@ -1,10 +1,31 @@
-use cosmwasm_std::{Deps, Order, StdResult};
-use cw_storage_plus::Bound;
+use std::collections::HashMap;
+
+use cosmwasm_std::{Coin, Deps, Env, Order, Response, StdError, StdResult, Uint128};
+use cw_storage_plus::{Bound, PrefixBound};
 use pfc_fee_split::fee_split_msg::{
-    AllocationHolding, AllocationResponse, OwnershipResponse, WhitelistResponse,
+    AllocationHolding, AllocationKind, AllocationResponse, AllocationStatsResponse,
+    AllocationWeight, ClaimDelegateResponse, DenomFilter, DenomSplitResponse, DenomStats,
+    DenomWeightsResponse, OwnershipResponse, PendingChangesResponse, PendingIbcTransfer,
+    PendingIbcTransfersResponse, PendingReceiverResponse, Quarantine, SenderTableResponse,
+    SenderTablesResponse, SimulateDepositResponse, StatsResponse, TableResponse, TablesResponse,
+    TrancheResponse, WeightSchedule, WeightSchedulesResponse, WhitelistResponse,
 };
 
-use crate::state::{ALLOCATION_HOLDINGS, CONFIG, FLUSH_WHITELIST};
+use crate::{
+    error::ContractError,
+    handler::{
+        exec::{
+            apply_schedule, apply_schedules, assert_flush_allowed, current_fill, default_weights,
+            deposit_table, plan_deposit, split_weights, tranche_room,
+        },
+        filter::{assert_denom_count, check_funds, Filtered},
+    },
+    state::{
+        ALLOCATION_HOLDINGS, ALLOCATION_STATS, CLAIM_DELEGATES, CONFIG, DENOM_FILTER,
+        DENOM_WEIGHTS, FLUSH_WHITELIST, PENDING_CHANGES, PENDING_IBC_TRANSFERS, PENDING_RECEIVERS,
+        QUARANTINE, SENDER_TABLES, TABLES,
+    },
+};
 
 const DEFAULT_LIMIT: u32 = 10;
 const MAX_LIMIT: u32 = 30;
@@ -48,3 +69,393 @@ pub(crate) fn query_allocations(
             .collect::<StdResult<Vec<AllocationHolding>>>()?,
     })
 }
+
+pub(crate) fn query_pending_ibc_transfers(
+    deps: Deps,
+    start_after: Option<(String, u64)>,
+    limit: Option<u32>,
+) -> StdResult<PendingIbcTransfersResponse> {
+    let limit_amt = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after
+        .as_ref()
+        .map(|(channel, sequence)| Bound::exclusive((channel.as_str(), *sequence)));
+    Ok(PendingIbcTransfersResponse {
+        transfers: PENDING_IBC_TRANSFERS
+            .range(deps.storage, start, None, Order::Ascending)
+            .take(limit_amt)
+            .map(|item| item.map(|(_, v)| v))
+            .collect::<StdResult<Vec<PendingIbcTransfer>>>()?,
+    })
+}
+
+/// the weights a deposit of 'denom' would be split with now, once any fixed & capped
+/// allocations have been filled. the same weights the deposit itself uses
+pub(crate) fn query_denom_split(
+    deps: Deps,
+    env: &Env,
+    denom: String,
+) -> StdResult<DenomSplitResponse> {
+    let mut allocation_holdings = ALLOCATION_HOLDINGS
+        .range(deps.storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<_>>>()?;
+    let expired = apply_schedules(&mut allocation_holdings, env);
+    let (excluded, weights) = default_weights(&allocation_holdings, &expired);
+    let (weights, is_override) = split_weights(deps.storage, &weights, &excluded, None, &denom)
+        .map_err(|e| StdError::generic_err(e.to_string()))?;
+    Ok(DenomSplitResponse {
+        denom,
+        is_override,
+        weights: weights
+            .into_iter()
+            .map(|(name, allocation)| AllocationWeight {
+                name,
+                allocation,
+            })
+            .collect(),
+    })
+}
+
+pub(crate) fn query_denom_weights(
+    deps: Deps,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> StdResult<DenomWeightsResponse> {
+    let limit_amt = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.as_deref().map(Bound::exclusive);
+    Ok(DenomWeightsResponse {
+        denoms: DENOM_WEIGHTS
+            .range(deps.storage, start, None, Order::Ascending)
+            .take(limit_amt)
+            .map(|item| {
+                item.map(|(denom, weights)| DenomSplitResponse {
+                    denom,
+                    is_override: true,
+                    weights,
+                })
+            })
+            .collect::<StdResult<Vec<DenomSplitResponse>>>()?,
+    })
+}
+
+/// the allocation, with its weight in 'table'
+pub(crate) fn query_table_allocation(
+    deps: Deps,
+    table: String,
+    name: String,
+) -> StdResult<Option<AllocationHolding>> {
+    let weights = load_table(deps, &table)?;
+    let Some(weight) = weights.into_iter().find(|w| w.name == name) else {
+        return Ok(None);
+    };
+    Ok(ALLOCATION_HOLDINGS.may_load(deps.storage, name)?.map(|holding| AllocationHolding {
+        allocation: weight.allocation,
+        ..holding
+    }))
+}
+
+pub(crate) fn query_table_allocations(
+    deps: Deps,
+    table: String,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> StdResult<AllocationResponse> {
+    let limit_amt = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let mut weights = load_table(deps, &table)?;
+    weights.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(AllocationResponse {
+        allocations: weights
+            .into_iter()
+            .filter(|w| start_after.as_ref().map(|start| &w.name > start).unwrap_or(true))
+            .take(limit_amt)
+            .map(|w| {
+                ALLOCATION_HOLDINGS.load(deps.storage, w.name).map(|holding| AllocationHolding {
+                    allocation: w.allocation,
+                    ..holding
+                })
+            })
+            .collect::<StdResult<Vec<AllocationHolding>>>()?,
+    })
+}
+
+fn load_table(deps: Deps, table: &str) -> StdResult<Vec<AllocationWeight>> {
+    TABLES
+        .may_load(deps.storage, table)?
+        .ok_or_else(|| StdError::not_found(format!("table {}", table)))
+}
+
+pub(crate) fn query_tables(
+    deps: Deps,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> StdResult<TablesResponse> {
+    let limit_amt = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.as_deref().map(Bound::exclusive);
+    Ok(TablesResponse {
+        tables: TABLES
+            .range(deps.storage, start, None, Order::Ascending)
+            .take(limit_amt)
+            .map(|item| {
+                item.map(|(table, weights)| TableResponse {
+                    table,
+                    weights,
+                })
+            })
+            .collect::<StdResult<Vec<TableResponse>>>()?,
+    })
+}
+
+pub(crate) fn query_sender_tables(
+    deps: Deps,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> StdResult<SenderTablesResponse> {
+    let limit_amt = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start_addr = start_after.map(|s| deps.api.addr_validate(&s)).transpose()?;
+    let start = start_addr.as_ref().map(Bound::exclusive);
+    Ok(SenderTablesResponse {
+        senders: SENDER_TABLES
+            .range(deps.storage, start, None, Order::Ascending)
+            .take(limit_amt)
+            .map(|item| {
+                item.map(|(sender, table)| SenderTableResponse {
+                    sender: sender.to_string(),
+                    table,
+                })
+            })
+            .collect::<StdResult<Vec<SenderTableResponse>>>()?,
+    })
+}
+
+pub(crate) fn query_allocation_stats(
+    deps: Deps,
+    name: String,
+) -> StdResult<AllocationStatsResponse> {
+    let denoms = ALLOCATION_STATS
+        .prefix(&name)
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(_, v)| v))
+        .collect::<StdResult<Vec<DenomStats>>>()?;
+    Ok(AllocationStatsResponse {
+        name,
+        denoms,
+    })
+}
+
+pub(crate) fn query_stats(
+    deps: Deps,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> StdResult<StatsResponse> {
+    let limit_amt = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.as_deref().map(PrefixBound::exclusive);
+    let mut allocations: Vec<AllocationStatsResponse> = vec![];
+    for item in ALLOCATION_STATS.prefix_range(deps.storage, start, None, Order::Ascending) {
+        let ((name, _), stats) = item?;
+        match allocations.last_mut() {
+            Some(last) if last.name == name => last.denoms.push(stats),
+            _ => {
+                if allocations.len() == limit_amt {
+                    break;
+                }
+                allocations.push(AllocationStatsResponse {
+                    name,
+                    denoms: vec![stats],
+                })
+            },
+        }
+    }
+    Ok(StatsResponse {
+        allocations,
+    })
+}
+
+/// allocations with weight changes or an expiry to come, as a deposit now would see them
+pub(crate) fn query_weight_schedules(
+    deps: Deps,
+    env: &Env,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> StdResult<WeightSchedulesResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(Bound::exclusive);
+    let schedules = ALLOCATION_HOLDINGS
+        .range(deps.storage, start, None, Order::Ascending)
+        .filter_map(|item| {
+            item.map(|(name, mut holding)| {
+                if holding.schedule.is_empty() && holding.expires.is_none() {
+                    return None;
+                }
+                apply_schedule(&mut holding, env);
+                Some(WeightSchedule {
+                    name,
+                    weight: holding.allocation,
+                    schedule: holding.schedule,
+                    expires: holding.expires,
+                })
+            })
+            .transpose()
+        })
+        .take(limit)
+        .collect::<StdResult<Vec<_>>>()?;
+
+    Ok(WeightSchedulesResponse {
+        schedules,
+    })
+}
+
+pub(crate) fn query_tranche(deps: Deps, env: &Env, name: String) -> StdResult<TrancheResponse> {
+    let allocation_holding = ALLOCATION_HOLDINGS.load(deps.storage, name.clone())?;
+    let (period_start, filled) = current_fill(&allocation_holding, env);
+    let remaining = tranche_room(&allocation_holding.kind, &filled);
+    let period_ends = match allocation_holding.kind {
+        AllocationKind::Fixed {
+            period,
+            ..
+        } => period_start.map(|start| start.plus_seconds(period)),
+        _ => None,
+    };
+
+    Ok(TrancheResponse {
+        name,
+        kind: allocation_holding.kind,
+        filled,
+        remaining,
+        period_ends,
+    })
+}
+
+pub(crate) fn query_claim_delegate(deps: Deps, name: String) -> StdResult<ClaimDelegateResponse> {
+    let allocation_holding = ALLOCATION_HOLDINGS.load(deps.storage, name.clone())?;
+    let Some(receiver) = allocation_holding.send_type.local_receiver() else {
+        return Ok(ClaimDelegateResponse {
+            name,
+            receiver: None,
+            delegate: None,
+        });
+    };
+    let delegate = CLAIM_DELEGATES.may_load(deps.storage, (&name, receiver))?;
+
+    Ok(ClaimDelegateResponse {
+        name,
+        receiver: Some(receiver.to_string()),
+        delegate: delegate.map(|d| d.to_string()),
+    })
+}
+
+pub(crate) fn query_pending_receiver(
+    deps: Deps,
+    name: String,
+) -> StdResult<PendingReceiverResponse> {
+    let pending = PENDING_RECEIVERS.may_load(deps.storage, &name)?;
+
+    Ok(PendingReceiverResponse {
+        name,
+        receiver: pending.as_ref().map(|p| p.receiver.to_string()),
+        new_receiver: pending.map(|p| p.new_receiver.to_string()),
+    })
+}
+
+pub(crate) fn query_pending_changes(
+    deps: Deps,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<PendingChangesResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(Bound::exclusive);
+    let changes = PENDING_CHANGES
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|item| item.map(|(_, change)| change))
+        .collect::<StdResult<Vec<_>>>()?;
+
+    Ok(PendingChangesResponse {
+        change_delay: CONFIG.load(deps.storage)?.change_delay,
+        changes,
+    })
+}
+
+pub(crate) fn query_denom_filter(deps: Deps) -> StdResult<DenomFilter> {
+    Ok(DENOM_FILTER.may_load(deps.storage)?.unwrap_or_default())
+}
+
+pub(crate) fn query_quarantine(deps: Deps) -> StdResult<Quarantine> {
+    Ok(QUARANTINE.may_load(deps.storage)?.unwrap_or_default())
+}
+
+/// runs a deposit through the same checks, denom filter and split as execute_deposit &
+/// execute_receive, without saving anything
+pub(crate) fn query_simulate_deposit(
+    deps: Deps,
+    env: &Env,
+    funds: Vec<Coin>,
+    cw20: Option<Coin>,
+    flush: bool,
+    sender: Option<String>,
+    table: Option<String>,
+) -> StdResult<SimulateDepositResponse> {
+    let to_std = |e: ContractError| StdError::generic_err(e.to_string());
+    let mut funds_in: HashMap<String, Uint128> = HashMap::new();
+    for coin in funds {
+        *funds_in.entry(coin.denom).or_default() += coin.amount;
+    }
+    let mut cw20_in: HashMap<String, Uint128> = HashMap::new();
+    if let Some(token) = cw20 {
+        let address = deps.api.addr_validate(&token.denom)?;
+        *cw20_in.entry(address.to_string()).or_default() += token.amount;
+    }
+    let sender = sender.map(|s| deps.api.addr_validate(&s)).transpose()?;
+    let table = match &sender {
+        Some(sender) => {
+            assert_flush_allowed(deps, sender, flush).map_err(to_std)?;
+            deposit_table(deps, sender, table).map_err(to_std)?
+        },
+        None => table,
+    };
+    assert_denom_count(deps.storage, funds_in.len() + cw20_in.len()).map_err(to_std)?;
+    let filtered = check_funds(deps.storage, &mut funds_in, false, true).map_err(to_std)?;
+    let filtered_cw20 = check_funds(deps.storage, &mut cw20_in, true, true).map_err(to_std)?;
+
+    // refunds can only be sent to a sender we know
+    let mut messages = vec![];
+    if let Some(sender) = &sender {
+        for (filtered, cw20) in [(&filtered, false), (&filtered_cw20, true)] {
+            let refund = Filtered {
+                refund: filtered.refund.clone(),
+                quarantined: vec![],
+            };
+            let res = refund.add_to(Response::new(), sender, cw20)?;
+            messages.extend(res.messages.into_iter().map(|m| m.msg));
+        }
+    }
+    let (mut refunded, mut quarantined) = (filtered.refund, filtered.quarantined);
+    refunded.extend(filtered_cw20.refund);
+    quarantined.extend(filtered_cw20.quarantined);
+
+    // with nothing left to split, a deposit leaves the allocations as they are
+    let allocations = if funds_in.is_empty() && cw20_in.is_empty() && !flush {
+        ALLOCATION_HOLDINGS
+            .range(deps.storage, None, None, Order::Ascending)
+            .map(|item| item.map(|(_, holding)| holding))
+            .collect::<StdResult<Vec<_>>>()?
+    } else {
+        let updates = plan_deposit(
+            deps.storage,
+            &deps.querier,
+            env,
+            &funds_in,
+            &cw20_in,
+            flush,
+            table.as_deref(),
+        )
+        .map_err(to_std)?;
+        messages.extend(updates.iter().flat_map(|u| u.msgs.iter().map(|m| m.msg.msg.clone())));
+        updates.into_iter().map(|u| u.holding).collect()
+    };
+
+    Ok(SimulateDepositResponse {
+        messages,
+        allocations,
+        refunded,
+        quarantined,
+    })
+}
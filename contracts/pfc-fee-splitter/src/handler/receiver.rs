@@ -0,0 +1,239 @@
+use cosmwasm_std::{DepsMut, Env, MessageInfo, Response};
+
+use crate::{
+    error::ContractError,
+    state::{PendingReceiver, ALLOCATION_HOLDINGS, PENDING_RECEIVERS},
+};
+
+/// the allocation's receiver proposes a new one. the weights, thresholds and the rest of the
+/// send type stay as the gov contract set them
+pub fn execute_update_my_receiver(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    name: String,
+    new_receiver: String,
+) -> Result<Response, ContractError> {
+    let allocation_holding =
+        ALLOCATION_HOLDINGS.may_load(deps.storage, name.clone())?.ok_or_else(|| {
+            ContractError::AllocationNotFound {
+                name: name.clone(),
+            }
+        })?;
+    let mut send_type = allocation_holding.send_type;
+    let Some(receiver) = send_type.receiver_mut() else {
+        return Err(ContractError::Unauthorized {
+            action: "update_my_receiver".to_string(),
+            expected: format!("receiver of {}", send_type),
+            actual: info.sender.to_string(),
+        });
+    };
+    if *receiver != info.sender {
+        return Err(ContractError::Unauthorized {
+            action: "update_my_receiver".to_string(),
+            expected: receiver.to_string(),
+            actual: info.sender.to_string(),
+        });
+    }
+    let new_receiver = deps.api.addr_validate(&new_receiver)?;
+    *receiver = new_receiver.clone();
+    send_type.verify_details(&deps, &env.contract.address)?;
+
+    PENDING_RECEIVERS.save(
+        deps.storage,
+        &name,
+        &PendingReceiver {
+            receiver: info.sender.clone(),
+            new_receiver: new_receiver.clone(),
+        },
+    )?;
+
+    Ok(Response::new()
+        .add_attribute("action", "update_my_receiver")
+        .add_attribute("from", info.sender)
+        .add_attribute("name", name)
+        .add_attribute("new_receiver", new_receiver))
+}
+
+/// the proposed receiver accepts, and takes over the allocation
+pub fn execute_accept_my_receiver(
+    deps: DepsMut,
+    info: MessageInfo,
+    name: String,
+) -> Result<Response, ContractError> {
+    let pending = PENDING_RECEIVERS.may_load(deps.storage, &name)?.ok_or_else(|| {
+        ContractError::NoPendingReceiver {
+            name: name.clone(),
+        }
+    })?;
+    if pending.new_receiver != info.sender {
+        return Err(ContractError::Unauthorized {
+            action: "accept_my_receiver".to_string(),
+            expected: pending.new_receiver.to_string(),
+            actual: info.sender.to_string(),
+        });
+    }
+    let mut allocation_holding = ALLOCATION_HOLDINGS
+        .may_load(deps.storage, name.clone())?
+        .ok_or_else(|| ContractError::AllocationNotFound {
+            name: name.clone(),
+        })?;
+    // the gov contract changed the receiver since it was proposed
+    match allocation_holding.send_type.receiver_mut() {
+        Some(receiver) if *receiver == pending.receiver => *receiver = pending.new_receiver,
+        _ => {
+            return Err(ContractError::NoPendingReceiver {
+                name,
+            })
+        },
+    }
+    ALLOCATION_HOLDINGS.save(deps.storage, name.clone(), &allocation_holding)?;
+    PENDING_RECEIVERS.remove(deps.storage, &name);
+
+    Ok(Response::new()
+        .add_attribute("action", "accept_my_receiver")
+        .add_attribute("from", info.sender)
+        .add_attribute("name", name)
+        .add_attribute("old_receiver", pending.receiver))
+}
+
+#[cfg(test)]
+mod test {
+    use cosmwasm_std::{
+        coin,
+        testing::{mock_dependencies, mock_env, mock_info},
+        Addr, Uint128,
+    };
+    use pfc_fee_split::fee_split_msg::{
+        AllocationKind, ExecuteMsg, PayoutMode, SendAfterMode, SendType,
+    };
+
+    use super::*;
+    use crate::{
+        contract::execute,
+        handler::query::{query_allocation, query_pending_receiver},
+        test_helpers::{
+            do_instantiate, two_allocation, ALLOCATION_1, ALLOCATION_2, CREATOR, DENOM_1,
+            GOV_CONTRACT, USER_1,
+        },
+    };
+
+    #[test]
+    fn update_my_receiver() -> Result<(), ContractError> {
+        let mut deps = mock_dependencies();
+        let alloc = two_allocation(&deps.api);
+        let _res = do_instantiate(deps.as_mut(), CREATOR, alloc)?;
+        let env = mock_env();
+
+        let update = |new_receiver: &str| ExecuteMsg::UpdateMyReceiver {
+            name: ALLOCATION_1.to_string(),
+            new_receiver: new_receiver.to_string(),
+        };
+        let accept = ExecuteMsg::AcceptMyReceiver {
+            name: ALLOCATION_1.to_string(),
+        };
+        let err = execute(deps.as_mut(), env.clone(), mock_info(USER_1, &[]), update("new_addr"))
+            .unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized { .. }), "wrong error {:?}", err);
+        let err = execute(deps.as_mut(), env.clone(), mock_info(USER_1, &[]), accept.clone())
+            .unwrap_err();
+        assert!(matches!(err, ContractError::NoPendingReceiver { .. }), "wrong error {:?}", err);
+
+        let receiver = mock_info("allocation_1_addr", &[]);
+        execute(deps.as_mut(), env.clone(), receiver.clone(), update("new_addr"))?;
+        let pending = query_pending_receiver(deps.as_ref(), ALLOCATION_1.into())?;
+        assert_eq!(pending.receiver, Some("allocation_1_addr".to_string()));
+        assert_eq!(pending.new_receiver, Some("new_addr".to_string()));
+        // nothing changes until it is accepted
+        let allocation = query_allocation(deps.as_ref(), ALLOCATION_1.into())?.unwrap();
+        assert_eq!(
+            allocation.send_type,
+            SendType::Wallet {
+                receiver: Addr::unchecked("allocation_1_addr")
+            }
+        );
+
+        let err =
+            execute(deps.as_mut(), env.clone(), receiver.clone(), accept.clone()).unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized { .. }), "wrong error {:?}", err);
+        let res = execute(deps.as_mut(), env.clone(), mock_info("new_addr", &[]), accept.clone())?;
+        assert_eq!(res.attributes[0].value, "accept_my_receiver");
+        let allocation = query_allocation(deps.as_ref(), ALLOCATION_1.into())?.unwrap();
+        assert_eq!(
+            allocation.send_type,
+            SendType::Wallet {
+                receiver: Addr::unchecked("new_addr")
+            }
+        );
+        assert_eq!(allocation.allocation, Uint128::new(1));
+        let pending = query_pending_receiver(deps.as_ref(), ALLOCATION_1.into())?;
+        assert_eq!(pending.new_receiver, None);
+
+        // the old receiver has no say any more
+        let err = execute(deps.as_mut(), env.clone(), receiver, update("other_addr")).unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized { .. }), "wrong error {:?}", err);
+
+        // a proposal lapses if the gov contract changes the receiver first
+        execute(deps.as_mut(), env.clone(), mock_info("new_addr", &[]), update("third_addr"))?;
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(GOV_CONTRACT, &[]),
+            ExecuteMsg::ModifyAllocationDetail {
+                name: ALLOCATION_1.to_string(),
+                allocation: Uint128::new(1),
+                send_after: vec![coin(1_000, DENOM_1)],
+                send_after_mode: SendAfterMode::AnyOf,
+                send_type: SendType::Wallet {
+                    receiver: Addr::unchecked("gov_pick"),
+                },
+                send_after_blocks: None,
+                send_after_seconds: None,
+                payout_mode: PayoutMode::Push,
+                kind: AllocationKind::Weighted,
+            },
+        )?;
+        let err =
+            execute(deps.as_mut(), env.clone(), mock_info("third_addr", &[]), accept).unwrap_err();
+        assert!(matches!(err, ContractError::NoPendingReceiver { .. }), "wrong error {:?}", err);
+
+        // the receiver can't point it back at the contract
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("gov_pick", &[]),
+            update(env.contract.address.as_str()),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::Std(_)), "wrong error {:?}", err);
+
+        // steak rewards keep the steak contract, only the receiver moves
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("receiver", &[]),
+            ExecuteMsg::UpdateMyReceiver {
+                name: ALLOCATION_2.to_string(),
+                new_receiver: "new_receiver".to_string(),
+            },
+        )?;
+        execute(
+            deps.as_mut(),
+            env,
+            mock_info("new_receiver", &[]),
+            ExecuteMsg::AcceptMyReceiver {
+                name: ALLOCATION_2.to_string(),
+            },
+        )?;
+        let allocation = query_allocation(deps.as_ref(), ALLOCATION_2.into())?.unwrap();
+        assert_eq!(
+            allocation.send_type,
+            SendType::SteakRewards {
+                steak: Addr::unchecked("steak_contract"),
+                receiver: Addr::unchecked("new_receiver"),
+            }
+        );
+
+        Ok(())
+    }
+}
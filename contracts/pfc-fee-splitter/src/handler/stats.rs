@@ -0,0 +1,73 @@
+use std::collections::HashMap;
+
+use cosmwasm_std::{Coin, StdResult, Storage, Uint128};
+use pfc_fee_split::fee_split_msg::DenomStats;
+
+use crate::state::ALLOCATION_STATS;
+
+fn update(
+    storage: &mut dyn Storage,
+    name: &str,
+    denom: &str,
+    action: impl FnOnce(&mut DenomStats),
+) -> StdResult<()> {
+    let mut stats =
+        ALLOCATION_STATS.may_load(storage, (name, denom))?.unwrap_or_else(|| DenomStats {
+            denom: denom.to_string(),
+            deposited: Uint128::zero(),
+            paid_out: Uint128::zero(),
+            payouts: 0,
+            last_payout_height: None,
+        });
+    action(&mut stats);
+    ALLOCATION_STATS.save(storage, (name, denom), &stats)
+}
+
+/// an allocation's share of a deposit
+pub(crate) fn record_deposit(
+    storage: &mut dyn Storage,
+    name: &str,
+    share: &HashMap<String, Uint128>,
+) -> StdResult<()> {
+    for (denom, amount) in share.iter().filter(|(_, amount)| !amount.is_zero()) {
+        update(storage, name, denom, |stats| stats.deposited += *amount)?;
+    }
+    Ok(())
+}
+
+/// reconcile re-deposits what allocations were holding, so it comes off first
+pub(crate) fn unrecord_deposit(
+    storage: &mut dyn Storage,
+    name: &str,
+    coins: &[Coin],
+) -> StdResult<()> {
+    for coin in coins.iter().filter(|c| !c.amount.is_zero()) {
+        update(storage, name, &coin.denom, |stats| {
+            stats.deposited = stats.deposited.saturating_sub(coin.amount)
+        })?;
+    }
+    Ok(())
+}
+
+pub(crate) fn record_payout(
+    storage: &mut dyn Storage,
+    name: &str,
+    coins: &[Coin],
+    height: u64,
+) -> StdResult<()> {
+    for coin in coins.iter().filter(|c| !c.amount.is_zero()) {
+        update(storage, name, &coin.denom, |stats| {
+            stats.paid_out += coin.amount;
+            stats.payouts += 1;
+            stats.last_payout_height = Some(height);
+        })?;
+    }
+    Ok(())
+}
+
+/// a payout that came back (eg. a failed IBC transfer) wasn't paid out after all
+pub(crate) fn unrecord_payout(storage: &mut dyn Storage, name: &str, coin: &Coin) -> StdResult<()> {
+    update(storage, name, &coin.denom, |stats| {
+        stats.paid_out = stats.paid_out.saturating_sub(coin.amount)
+    })
+}
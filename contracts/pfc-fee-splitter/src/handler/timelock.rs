@@ -0,0 +1,542 @@
+use cosmwasm_std::{DepsMut, Env, MessageInfo, Response, Uint64};
+use pfc_fee_split::fee_split_msg::{AllocationChange, PendingChange, MAX_CHANGE_DELAY};
+
+use crate::{
+    error::ContractError,
+    handler::{exec, filter},
+    state::{ADMIN, CONFIG, PENDING_CHANGES, PENDING_CHANGE_ID},
+};
+
+/// allocation (and weight, table & filter) changes from the gov contract. with a change delay
+/// they are checked & queued, otherwise they are applied straight away
+pub fn execute_allocation_change(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    change: AllocationChange,
+) -> Result<Response, ContractError> {
+    ADMIN.assert_admin(deps.as_ref(), &info.sender)?;
+    let config = CONFIG.load(deps.storage)?;
+    if config.change_delay == 0 {
+        return apply_change(deps, env, info, change);
+    }
+    // catch what we can now, rather than when it is applied
+    match &change {
+        AllocationChange::AddAllocationDetail {
+            allocation,
+        }
+        | AllocationChange::ModifyAllocationDetail {
+            allocation,
+        } => exec::validate_allocation(&deps, &env, allocation)?,
+        AllocationChange::ReplaceAllocations {
+            allocations,
+            ..
+        } => exec::validate_allocations(&deps, &env, allocations)?,
+        AllocationChange::SetWeightSchedule {
+            schedule,
+            ..
+        } => exec::validate_schedule(schedule)?,
+        AllocationChange::SetChangeDelay {
+            blocks,
+        } => validate_change_delay(*blocks)?,
+        AllocationChange::SetAllocationStatus {
+            name,
+            status,
+        } => {
+            exec::validate_allocation_status(&deps, name, *status)?;
+        },
+        AllocationChange::SetDenomWeights {
+            denom,
+            weights,
+        } => exec::validate_denom_weights(&deps, denom, weights)?,
+        AllocationChange::ClearDenomWeights {
+            denom,
+        } => exec::assert_denom_weights_exist(deps.storage, denom)?,
+        AllocationChange::SetTable {
+            table,
+            weights,
+        } => exec::validate_table(&deps, table, weights)?,
+        AllocationChange::RemoveTable {
+            table,
+        } => exec::assert_table_exists(deps.storage, table)?,
+        AllocationChange::SetSenderTable {
+            sender,
+            table,
+        } => {
+            exec::validate_sender_table(&deps, sender, table.as_deref())?;
+        },
+        AllocationChange::SetDenomFilter {
+            mode,
+            denoms,
+            max_denoms,
+            ..
+        } => filter::validate_denom_filter(*mode, denoms, *max_denoms)?,
+        AllocationChange::RemoveAllocationDetail {
+            ..
+        } => {},
+    }
+
+    let execute_after =
+        Uint64::new(env.block.height).checked_add(Uint64::new(config.change_delay))?.u64();
+    let id = PENDING_CHANGE_ID.may_load(deps.storage)?.unwrap_or_default() + 1;
+    PENDING_CHANGE_ID.save(deps.storage, &id)?;
+    PENDING_CHANGES.save(
+        deps.storage,
+        id,
+        &PendingChange {
+            id,
+            change,
+            queued_height: env.block.height,
+            execute_after,
+        },
+    )?;
+
+    Ok(Response::new()
+        .add_attribute("action", "queue_change")
+        .add_attribute("from", info.sender)
+        .add_attribute("id", id.to_string())
+        .add_attribute("execute_after", execute_after.to_string()))
+}
+
+/// anyone can apply a change once its delay has passed
+pub fn execute_apply_change(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    id: u64,
+) -> Result<Response, ContractError> {
+    let pending =
+        PENDING_CHANGES.may_load(deps.storage, id)?.ok_or(ContractError::ChangeNotFound {
+            id,
+        })?;
+    if env.block.height < pending.execute_after {
+        return Err(ContractError::ChangeTimelocked {
+            id,
+            execute_after: pending.execute_after,
+        });
+    }
+    PENDING_CHANGES.remove(deps.storage, id);
+
+    let res = apply_change(deps, env, info, pending.change)?;
+    Ok(res.add_attribute("change_id", id.to_string()))
+}
+
+pub fn execute_cancel_change(
+    deps: DepsMut,
+    info: MessageInfo,
+    id: u64,
+) -> Result<Response, ContractError> {
+    ADMIN.assert_admin(deps.as_ref(), &info.sender)?;
+    if !PENDING_CHANGES.has(deps.storage, id) {
+        return Err(ContractError::ChangeNotFound {
+            id,
+        });
+    }
+    PENDING_CHANGES.remove(deps.storage, id);
+
+    Ok(Response::new()
+        .add_attribute("action", "cancel_change")
+        .add_attribute("from", info.sender)
+        .add_attribute("id", id.to_string()))
+}
+
+fn apply_change(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    change: AllocationChange,
+) -> Result<Response, ContractError> {
+    match change {
+        AllocationChange::AddAllocationDetail {
+            allocation,
+        } => exec::execute_add_allocation_detail(
+            deps,
+            env,
+            info,
+            allocation.name,
+            allocation.allocation,
+            allocation.send_after,
+            allocation.send_after_mode,
+            allocation.send_type,
+            allocation.send_after_blocks,
+            allocation.send_after_seconds,
+            allocation.payout_mode,
+            allocation.kind,
+        ),
+        AllocationChange::ModifyAllocationDetail {
+            allocation,
+        } => exec::execute_modify_allocation_detail(
+            deps,
+            env,
+            info,
+            allocation.name,
+            allocation.allocation,
+            allocation.send_after,
+            allocation.send_after_mode,
+            allocation.send_type,
+            allocation.send_after_blocks,
+            allocation.send_after_seconds,
+            allocation.payout_mode,
+            allocation.kind,
+        ),
+        AllocationChange::RemoveAllocationDetail {
+            name,
+        } => exec::execute_remove_allocation_detail(deps, env, info, name),
+        AllocationChange::ReplaceAllocations {
+            allocations,
+            balance_policy,
+        } => exec::execute_replace_allocations(deps, env, info, allocations, balance_policy),
+        AllocationChange::SetChangeDelay {
+            blocks,
+        } => {
+            validate_change_delay(blocks)?;
+            let mut config = CONFIG.load(deps.storage)?;
+            config.change_delay = blocks;
+            CONFIG.save(deps.storage, &config)?;
+            Ok(Response::new()
+                .add_attribute("action", "set_change_delay")
+                .add_attribute("from", info.sender)
+                .add_attribute("blocks", blocks.to_string()))
+        },
+        AllocationChange::SetWeightSchedule {
+            name,
+            schedule,
+            expires,
+        } => exec::execute_set_weight_schedule(deps, info, name, schedule, expires),
+        AllocationChange::SetAllocationStatus {
+            name,
+            status,
+        } => exec::execute_set_allocation_status(deps, info, name, status),
+        AllocationChange::SetDenomWeights {
+            denom,
+            weights,
+        } => exec::execute_set_denom_weights(deps, info, denom, weights),
+        AllocationChange::ClearDenomWeights {
+            denom,
+        } => exec::execute_clear_denom_weights(deps, info, denom),
+        AllocationChange::SetTable {
+            table,
+            weights,
+        } => exec::execute_set_table(deps, info, table, weights),
+        AllocationChange::RemoveTable {
+            table,
+        } => exec::execute_remove_table(deps, info, table),
+        AllocationChange::SetSenderTable {
+            sender,
+            table,
+        } => exec::execute_set_sender_table(deps, info, sender, table),
+        AllocationChange::SetDenomFilter {
+            mode,
+            denoms,
+            on_filtered,
+            max_denoms,
+        } => filter::execute_set_denom_filter(deps, info, mode, denoms, on_filtered, max_denoms),
+    }
+}
+
+fn validate_change_delay(blocks: u64) -> Result<(), ContractError> {
+    if blocks > MAX_CHANGE_DELAY {
+        return Err(ContractError::ChangeDelayTooLong {
+            blocks,
+            max: MAX_CHANGE_DELAY,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use cosmwasm_std::{
+        coin,
+        testing::{mock_dependencies, mock_env, mock_info},
+        Api, Uint128,
+    };
+    use pfc_fee_split::fee_split_msg::{
+        AllocationDetail, AllocationKind, AllocationStatus, AllocationWeight, DenomFilterMode,
+        ExecuteMsg, FilteredDenomAction, PayoutMode, SendAfterMode, SendType,
+    };
+
+    use super::*;
+    use crate::{
+        contract::execute,
+        handler::query::{
+            query_allocation, query_allocations, query_denom_filter, query_denom_split,
+            query_pending_changes, query_tables,
+        },
+        test_helpers::{
+            do_instantiate, two_allocation, ALLOCATION_1, ALLOCATION_2, CREATOR, DENOM_1,
+            GOV_CONTRACT, USER_1,
+        },
+    };
+
+    #[test]
+    fn change_delay() -> Result<(), ContractError> {
+        let mut deps = mock_dependencies();
+        let alloc = two_allocation(&deps.api);
+        let _res = do_instantiate(deps.as_mut(), CREATOR, alloc)?;
+        let mut env = mock_env();
+        let gov = mock_info(GOV_CONTRACT, &[]);
+
+        // no delay yet, so this applies straight away
+        let msg = ExecuteMsg::SetChangeDelay {
+            blocks: 100,
+        };
+        execute(deps.as_mut(), env.clone(), gov.clone(), msg)?;
+        assert_eq!(query_pending_changes(deps.as_ref(), None, None)?.change_delay, 100);
+
+        let detail = AllocationDetail {
+            name: "allocation_3".to_string(),
+            allocation: Uint128::new(1),
+            send_after: vec![coin(1_000, DENOM_1)],
+            send_after_mode: SendAfterMode::AnyOf,
+            send_type: SendType::Wallet {
+                receiver: deps.api.addr_validate("allocation_3_addr")?,
+            },
+            send_after_blocks: None,
+            send_after_seconds: None,
+            payout_mode: PayoutMode::Push,
+            kind: AllocationKind::Weighted,
+        };
+        let msg = ExecuteMsg::AddAllocationDetail {
+            name: detail.name.clone(),
+            allocation: detail.allocation,
+            send_after: detail.send_after.clone(),
+            send_after_mode: detail.send_after_mode,
+            send_type: detail.send_type.clone(),
+            send_after_blocks: None,
+            send_after_seconds: None,
+            payout_mode: PayoutMode::Push,
+            kind: AllocationKind::Weighted,
+        };
+        let err =
+            execute(deps.as_mut(), env.clone(), mock_info(USER_1, &[]), msg.clone()).unwrap_err();
+        assert!(matches!(err, ContractError::AdminError { .. }), "wrong error {:?}", err);
+        let res = execute(deps.as_mut(), env.clone(), gov.clone(), msg)?;
+        assert_eq!(res.attributes[0].value, "queue_change");
+        assert!(query_allocation(deps.as_ref(), "allocation_3".to_string())?.is_none());
+
+        // bad changes are caught when they are queued
+        let msg = ExecuteMsg::ModifyAllocationDetail {
+            name: ALLOCATION_1.to_string(),
+            allocation: Uint128::zero(),
+            send_after: vec![],
+            send_after_mode: SendAfterMode::AnyOf,
+            send_type: detail.send_type.clone(),
+            send_after_blocks: None,
+            send_after_seconds: None,
+            payout_mode: PayoutMode::Push,
+            kind: AllocationKind::Weighted,
+        };
+        let err = execute(deps.as_mut(), env.clone(), gov.clone(), msg).unwrap_err();
+        assert!(matches!(err, ContractError::AllocationZero {}), "wrong error {:?}", err);
+
+        let msg = ExecuteMsg::RemoveAllocationDetail {
+            name: ALLOCATION_2.to_string(),
+        };
+        execute(deps.as_mut(), env.clone(), gov.clone(), msg)?;
+
+        let pending = query_pending_changes(deps.as_ref(), None, None)?;
+        assert_eq!(pending.changes.len(), 2);
+        assert_eq!(pending.changes[0].id, 1);
+        assert_eq!(
+            pending.changes[0].change,
+            AllocationChange::AddAllocationDetail {
+                allocation: detail
+            }
+        );
+        assert_eq!(pending.changes[0].execute_after, env.block.height + 100);
+        assert_eq!(query_pending_changes(deps.as_ref(), Some(1), None)?.changes[0].id, 2);
+
+        // the gov contract can cancel during the window
+        let msg = ExecuteMsg::CancelChange {
+            id: 2,
+        };
+        let err =
+            execute(deps.as_mut(), env.clone(), mock_info(USER_1, &[]), msg.clone()).unwrap_err();
+        assert!(matches!(err, ContractError::AdminError { .. }), "wrong error {:?}", err);
+        execute(deps.as_mut(), env.clone(), gov.clone(), msg.clone())?;
+        let err = execute(deps.as_mut(), env.clone(), gov.clone(), msg).unwrap_err();
+        assert!(
+            matches!(
+                err,
+                ContractError::ChangeNotFound {
+                    id: 2
+                }
+            ),
+            "wrong error {:?}",
+            err
+        );
+
+        // anyone can apply, once the window has passed
+        let msg = ExecuteMsg::ApplyChange {
+            id: 1,
+        };
+        env.block.height += 99;
+        let err =
+            execute(deps.as_mut(), env.clone(), mock_info(USER_1, &[]), msg.clone()).unwrap_err();
+        assert!(
+            matches!(
+                err,
+                ContractError::ChangeTimelocked {
+                    id: 1,
+                    ..
+                }
+            ),
+            "wrong error {:?}",
+            err
+        );
+        env.block.height += 1;
+        let res = execute(deps.as_mut(), env.clone(), mock_info(USER_1, &[]), msg)?;
+        assert_eq!(res.attributes[0].value, "add_fee_detail");
+        assert_eq!(query_allocations(deps.as_ref(), None, None)?.allocations.len(), 3);
+        assert!(query_pending_changes(deps.as_ref(), None, None)?.changes.is_empty());
+
+        // turning the delay off waits too
+        let msg = ExecuteMsg::SetChangeDelay {
+            blocks: 0,
+        };
+        execute(deps.as_mut(), env.clone(), gov.clone(), msg)?;
+        assert_eq!(query_pending_changes(deps.as_ref(), None, None)?.change_delay, 100);
+        env.block.height += 100;
+        let msg = ExecuteMsg::ApplyChange {
+            id: 3,
+        };
+        execute(deps.as_mut(), env.clone(), mock_info(USER_1, &[]), msg)?;
+        assert_eq!(query_pending_changes(deps.as_ref(), None, None)?.change_delay, 0);
+
+        let msg = ExecuteMsg::RemoveAllocationDetail {
+            name: ALLOCATION_2.to_string(),
+        };
+        let res = execute(deps.as_mut(), env, gov, msg)?;
+        assert_eq!(res.attributes[0].value, "remove_fee_detail");
+
+        Ok(())
+    }
+
+    #[test]
+    fn weight_changes_wait() -> Result<(), ContractError> {
+        let mut deps = mock_dependencies();
+        let alloc = two_allocation(&deps.api);
+        let _res = do_instantiate(deps.as_mut(), CREATOR, alloc)?;
+        let mut env = mock_env();
+        let gov = mock_info(GOV_CONTRACT, &[]);
+        let msg = ExecuteMsg::SetChangeDelay {
+            blocks: 100,
+        };
+        execute(deps.as_mut(), env.clone(), gov.clone(), msg)?;
+        let weights = vec![AllocationWeight {
+            name: ALLOCATION_1.to_string(),
+            allocation: Uint128::new(1),
+        }];
+
+        let msgs = vec![
+            ExecuteMsg::SetTable {
+                table: "table".to_string(),
+                weights: weights.clone(),
+            },
+            ExecuteMsg::SetDenomWeights {
+                denom: DENOM_1.to_string(),
+                weights: weights.clone(),
+            },
+            ExecuteMsg::SetAllocationStatus {
+                name: ALLOCATION_2.to_string(),
+                status: AllocationStatus::Frozen,
+            },
+            ExecuteMsg::SetDenomFilter {
+                mode: DenomFilterMode::Deny,
+                denoms: vec![DENOM_1.to_string()],
+                on_filtered: FilteredDenomAction::Refund,
+                max_denoms: None,
+            },
+        ];
+        for msg in msgs {
+            let err = execute(deps.as_mut(), env.clone(), mock_info(USER_1, &[]), msg.clone())
+                .unwrap_err();
+            assert!(matches!(err, ContractError::AdminError { .. }), "wrong error {:?}", err);
+            let res = execute(deps.as_mut(), env.clone(), gov.clone(), msg)?;
+            assert_eq!(res.attributes[0].value, "queue_change");
+        }
+        // the table doesn't exist yet
+        let msg = ExecuteMsg::SetSenderTable {
+            sender: USER_1.to_string(),
+            table: Some("table".to_string()),
+        };
+        let err = execute(deps.as_mut(), env.clone(), gov.clone(), msg).unwrap_err();
+        assert!(matches!(err, ContractError::TableNotFound { .. }), "wrong error {:?}", err);
+        let msg = ExecuteMsg::SetTable {
+            table: "other".to_string(),
+            weights: vec![AllocationWeight {
+                name: "not-there".to_string(),
+                allocation: Uint128::new(1),
+            }],
+        };
+        let err = execute(deps.as_mut(), env.clone(), gov, msg).unwrap_err();
+        assert!(matches!(err, ContractError::AllocationNotFound { .. }), "wrong error {:?}", err);
+
+        // nothing has changed until they are applied
+        assert!(query_tables(deps.as_ref(), None, None)?.tables.is_empty());
+        assert_eq!(query_denom_filter(deps.as_ref())?.mode, DenomFilterMode::Off);
+        let allocation = query_allocation(deps.as_ref(), ALLOCATION_2.to_string())?.unwrap();
+        assert_eq!(allocation.status, AllocationStatus::Active);
+        env.block.height += 100;
+        for id in 1..=4 {
+            let msg = ExecuteMsg::ApplyChange {
+                id,
+            };
+            execute(deps.as_mut(), env.clone(), mock_info(USER_1, &[]), msg)?;
+        }
+        assert_eq!(query_tables(deps.as_ref(), None, None)?.tables.len(), 1);
+        assert!(query_denom_split(deps.as_ref(), &env, DENOM_1.to_string())?.is_override);
+        let allocation = query_allocation(deps.as_ref(), ALLOCATION_2.to_string())?.unwrap();
+        assert_eq!(allocation.status, AllocationStatus::Frozen);
+        assert_eq!(query_denom_filter(deps.as_ref())?.mode, DenomFilterMode::Deny);
+
+        Ok(())
+    }
+
+    #[test]
+    fn change_delay_too_long() -> Result<(), ContractError> {
+        let mut deps = mock_dependencies();
+        let alloc = two_allocation(&deps.api);
+        let _res = do_instantiate(deps.as_mut(), CREATOR, alloc)?;
+        let env = mock_env();
+        let gov = mock_info(GOV_CONTRACT, &[]);
+
+        for blocks in [MAX_CHANGE_DELAY + 1, u64::MAX] {
+            let msg = ExecuteMsg::SetChangeDelay {
+                blocks,
+            };
+            let err = execute(deps.as_mut(), env.clone(), gov.clone(), msg).unwrap_err();
+            assert!(
+                matches!(err, ContractError::ChangeDelayTooLong { .. }),
+                "wrong error {:?}",
+                err
+            );
+        }
+        let msg = ExecuteMsg::SetChangeDelay {
+            blocks: MAX_CHANGE_DELAY,
+        };
+        execute(deps.as_mut(), env.clone(), gov.clone(), msg)?;
+        // and can't be queued either
+        let msg = ExecuteMsg::SetChangeDelay {
+            blocks: u64::MAX,
+        };
+        let err = execute(deps.as_mut(), env.clone(), gov.clone(), msg).unwrap_err();
+        assert!(matches!(err, ContractError::ChangeDelayTooLong { .. }), "wrong error {:?}", err);
+
+        // a change that would be applied past the last block height errors, rather than panicking
+        let mut env = env;
+        env.block.height = u64::MAX - MAX_CHANGE_DELAY + 1;
+        let msg = ExecuteMsg::RemoveAllocationDetail {
+            name: ALLOCATION_2.to_string(),
+        };
+        let err = execute(deps.as_mut(), env.clone(), gov.clone(), msg.clone()).unwrap_err();
+        assert!(matches!(err, ContractError::Overflow(_)), "wrong error {:?}", err);
+        env.block.height -= 1;
+        execute(deps.as_mut(), env, gov, msg)?;
+        let pending = query_pending_changes(deps.as_ref(), None, None)?;
+        assert_eq!(pending.changes[0].execute_after, u64::MAX);
+
+        Ok(())
+    }
+}
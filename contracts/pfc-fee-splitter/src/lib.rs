@@ -2,6 +2,7 @@ pub mod contract;
 
 mod error;
 mod handler;
+mod lst;
 mod querier;
 //mod response;
 
@@ -10,6 +11,7 @@ mod querier;
 
 //mod core;
 mod migrations;
+mod proto;
 
 pub mod state;
 #[cfg(test)]
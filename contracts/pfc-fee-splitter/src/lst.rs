@@ -0,0 +1,30 @@
+// 'bond' messages for the liquid staking hubs SendType::LiquidStake can deposit into.
+// only the parts of each hub's ExecuteMsg we use.
+use cosmwasm_std::{to_json_binary, Addr, Binary, StdResult};
+use pfc_fee_split::fee_split_msg::LiquidStakeProtocol;
+use serde::Serialize;
+
+/// Eris' and Backbone's hubs both take a steak style bond, without the exec_msg.
+/// see https://github.com/erisprotocol/contracts-terra/blob/main/packages/eris/src/hub.rs
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+enum BondExecuteMsg {
+    Bond {
+        receiver: Option<String>,
+    },
+}
+
+pub(crate) fn bond_msg(protocol: LiquidStakeProtocol, receiver: &Addr) -> StdResult<Binary> {
+    let receiver = Some(receiver.to_string());
+    match protocol {
+        LiquidStakeProtocol::Steak => to_json_binary(&pfc_steak::hub::ExecuteMsg::Bond {
+            receiver,
+            exec_msg: None,
+        }),
+        LiquidStakeProtocol::Eris | LiquidStakeProtocol::Backbone => {
+            to_json_binary(&BondExecuteMsg::Bond {
+                receiver,
+            })
+        },
+    }
+}
@@ -1,15 +1,20 @@
-use cosmwasm_std::{Addr, Api, Coin, DepsMut, Order, StdError, StdResult, Storage};
+use cosmwasm_std::{Addr, Api, Coin, DepsMut, Order, StdError, StdResult, Storage, Uint128};
+use cw20::Cw20CoinVerified;
 use cw_storage_plus::{Item, Map};
-use pfc_fee_split::fee_split_msg::{AllocationHolding, SendType};
+use pfc_fee_split::fee_split_msg::{
+    AllocationHolding, AllocationKind, AllocationStatus, PayoutMode, SendAfterMode, SendType,
+};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use crate::state::{Config, ALLOCATION_HOLDINGS, CONFIG_KEY};
+use crate::state::{Config, ALLOCATION_HOLDINGS, CONFIG_KEY, FEE_KEY};
 
 const CONFIG_V100_KEY: &str = "config_001";
 const FEE_KEY_V100: &str = "fees_001";
+const FEE_KEY_V152: &str = "fees_002";
 pub const CONFIG_V100: Item<ConfigV100> = Item::new(CONFIG_V100_KEY);
 pub const ALLOCATION_HOLDINGSV100: Map<String, AllocationHoldingV100> = Map::new(FEE_KEY_V100);
+pub const ALLOCATION_HOLDINGSV152: Map<String, AllocationHoldingV152> = Map::new(FEE_KEY_V152);
 #[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
 pub struct AllocationHoldingV100 {
     pub name: String,            // user-friendly name of wallet
@@ -40,6 +45,7 @@ impl ConfigV100 {
             gov_contract: self.gov_contract.clone(),
             new_gov_contract: None,
             change_gov_contract_by_height: None,
+            change_delay: 0,
         }
     }
 }
@@ -76,13 +82,144 @@ impl SendTypeV100 {
             let new = AllocationHolding {
                 name: old.1.name,
 
-                allocation: old.1.allocation,
-                send_after: old.1.send_after,
+                allocation: Uint128::from(old.1.allocation),
+                send_after: vec![old.1.send_after],
+                send_after_mode: SendAfterMode::AnyOf,
                 send_type: old.1.send_type.convert(deps.api, old.1.contract)?,
                 balance: old.1.balance,
+                balance_cw20: vec![],
+                send_after_blocks: None,
+                send_after_seconds: None,
+                payout_mode: PayoutMode::Push,
+                status: AllocationStatus::Active,
+                kind: AllocationKind::Weighted,
+                filled: vec![],
+                period_start: None,
+                schedule: vec![],
+                expires: None,
+                last_payout_height: None,
+                last_payout_time: None,
+                payout_error: None,
             };
             ALLOCATION_HOLDINGS.save(deps.storage, old.0, &new)?
         }
         Ok(())
     }
 }
+
+/// allocation weights were a u8 up to v1.5.2
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct AllocationHoldingV152 {
+    pub name: String,
+    pub allocation: u8,
+    pub send_after: Coin,
+    pub send_type: SendType,
+    pub balance: Vec<Coin>,
+    #[serde(default)]
+    pub balance_cw20: Vec<Cw20CoinVerified>,
+}
+impl AllocationHoldingV152 {
+    /// moves the rows from fees_002 to fees_003. As the weights are relative to each other,
+    /// the u8 weight is carried over as-is, so nobody's split changes.
+    /// this is a no-op if there is nothing under the old key
+    pub fn migrate_allocation_v152(deps: DepsMut) -> StdResult<()> {
+        if FEE_KEY_V152 == FEE_KEY {
+            return Err(StdError::generic_err(
+                "PFC-Fee-Split: Migration Failed. Allocation keys are the same",
+            ));
+        }
+        let old_vec = ALLOCATION_HOLDINGSV152
+            .range(deps.storage, None, None, Order::Ascending)
+            .collect::<StdResult<Vec<_>>>()?;
+        for (key, old) in old_vec {
+            let new = AllocationHolding {
+                name: old.name,
+                allocation: Uint128::from(old.allocation),
+                send_after: vec![old.send_after],
+                send_after_mode: SendAfterMode::AnyOf,
+                send_type: old.send_type,
+                balance: old.balance,
+                balance_cw20: old.balance_cw20,
+                send_after_blocks: None,
+                send_after_seconds: None,
+                payout_mode: PayoutMode::Push,
+                status: AllocationStatus::Active,
+                kind: AllocationKind::Weighted,
+                filled: vec![],
+                period_start: None,
+                schedule: vec![],
+                expires: None,
+                last_payout_height: None,
+                last_payout_time: None,
+                payout_error: None,
+            };
+            ALLOCATION_HOLDINGS.save(deps.storage, key.clone(), &new)?;
+            ALLOCATION_HOLDINGSV152.remove(deps.storage, key);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use cosmwasm_std::{coin, testing::mock_dependencies, Addr, Uint128};
+    use pfc_fee_split::fee_split_msg::SendType;
+
+    use super::*;
+
+    #[test]
+    fn migrate_v152_weights() -> StdResult<()> {
+        let mut deps = mock_dependencies();
+        for (name, weight) in [("a", 1u8), ("b", 2u8), ("c", 200u8)] {
+            ALLOCATION_HOLDINGSV152.save(
+                deps.as_mut().storage,
+                name.to_string(),
+                &AllocationHoldingV152 {
+                    name: name.to_string(),
+                    allocation: weight,
+                    send_after: coin(1_000, "uxyz"),
+                    send_type: SendType::Wallet {
+                        receiver: Addr::unchecked(name),
+                    },
+                    balance: vec![coin(10, "uxyz")],
+                    balance_cw20: vec![],
+                },
+            )?;
+        }
+        AllocationHoldingV152::migrate_allocation_v152(deps.as_mut())?;
+
+        let migrated = ALLOCATION_HOLDINGS
+            .range(deps.as_ref().storage, None, None, Order::Ascending)
+            .map(|x| x.map(|(_, v)| (v.name, v.allocation, v.balance)))
+            .collect::<StdResult<Vec<_>>>()?;
+        assert_eq!(
+            migrated,
+            vec![
+                ("a".to_string(), Uint128::new(1), vec![coin(10, "uxyz")]),
+                ("b".to_string(), Uint128::new(2), vec![coin(10, "uxyz")]),
+                ("c".to_string(), Uint128::new(200), vec![coin(10, "uxyz")]),
+            ]
+        );
+        assert!(ALLOCATION_HOLDINGSV152.is_empty(deps.as_ref().storage));
+        // running it again is harmless
+        AllocationHoldingV152::migrate_allocation_v152(deps.as_mut())?;
+        assert_eq!(
+            ALLOCATION_HOLDINGS.keys(deps.as_ref().storage, None, None, Order::Ascending).count(),
+            3
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn single_send_after_still_loads() -> StdResult<()> {
+        let mut deps = mock_dependencies();
+        let old_json = r#"{"name":"a","allocation":"1","send_after":{"denom":"uxyz","amount":"1000"},"send_type":{"Wallet":{"receiver":"a"}},"balance":[]}"#;
+        deps.storage.set(&ALLOCATION_HOLDINGS.key("a".to_string()), old_json.as_bytes());
+
+        let holding = ALLOCATION_HOLDINGS.load(deps.as_ref().storage, "a".to_string())?;
+        assert_eq!(holding.send_after, vec![coin(1_000, "uxyz")]);
+        assert_eq!(holding.send_after_mode, SendAfterMode::AnyOf);
+        assert!(holding.balance_cw20.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,126 @@
+// messages the SDK doesn't have a CosmosMsg for (yet).
+use std::convert::{TryFrom, TryInto};
+
+use osmosis_std_derive::CosmwasmExt;
+
+// see https://github.com/cosmos/cosmos-sdk/blob/v0.47.5/proto/cosmos/base/v1beta1/coin.proto
+
+#[derive(
+    Clone,
+    PartialEq,
+    Eq,
+    ::prost::Message,
+    serde::Serialize,
+    serde::Deserialize,
+    schemars::JsonSchema,
+    CosmwasmExt,
+)]
+#[proto_message(type_url = "/cosmos.base.v1beta1.Coin")]
+pub struct Coin {
+    #[prost(string, tag = "1")]
+    pub denom: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub amount: ::prost::alloc::string::String,
+}
+
+impl From<cosmwasm_std::Coin> for Coin {
+    fn from(coin: cosmwasm_std::Coin) -> Self {
+        Coin {
+            denom: coin.denom,
+            amount: coin.amount.to_string(),
+        }
+    }
+}
+
+// see https://github.com/cosmos/ibc-go/blob/v7.3.0/proto/ibc/applications/transfer/v1/tx.proto
+
+/// Height is a monotonically increasing data type. a zero height disables the height timeout
+#[derive(
+    Clone,
+    PartialEq,
+    Eq,
+    ::prost::Message,
+    serde::Serialize,
+    serde::Deserialize,
+    schemars::JsonSchema,
+    CosmwasmExt,
+)]
+#[proto_message(type_url = "/ibc.core.client.v1.Height")]
+pub struct Height {
+    #[prost(uint64, tag = "1")]
+    pub revision_number: u64,
+    #[prost(uint64, tag = "2")]
+    pub revision_height: u64,
+}
+
+/// MsgTransfer defines a msg to transfer fungible tokens (i.e Coins) between
+/// ICS20 enabled chains. IbcMsg::Transfer doesn't have a memo field until cosmwasm 2.0
+#[derive(
+    Clone,
+    PartialEq,
+    Eq,
+    ::prost::Message,
+    serde::Serialize,
+    serde::Deserialize,
+    schemars::JsonSchema,
+    CosmwasmExt,
+)]
+#[proto_message(type_url = "/ibc.applications.transfer.v1.MsgTransfer")]
+pub struct MsgTransfer {
+    #[prost(string, tag = "1")]
+    pub source_port: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub source_channel: ::prost::alloc::string::String,
+    #[prost(message, optional, tag = "3")]
+    pub token: ::core::option::Option<Coin>,
+    #[prost(string, tag = "4")]
+    pub sender: ::prost::alloc::string::String,
+    #[prost(string, tag = "5")]
+    pub receiver: ::prost::alloc::string::String,
+    #[prost(message, optional, tag = "6")]
+    pub timeout_height: ::core::option::Option<Height>,
+    /// in nanoseconds since the unix epoch
+    #[prost(uint64, tag = "7")]
+    pub timeout_timestamp: u64,
+    #[prost(string, tag = "8")]
+    pub memo: ::prost::alloc::string::String,
+}
+
+/// MsgTransferResponse defines the Msg/Transfer response type.
+#[derive(
+    Clone,
+    PartialEq,
+    Eq,
+    ::prost::Message,
+    serde::Serialize,
+    serde::Deserialize,
+    schemars::JsonSchema,
+    CosmwasmExt,
+)]
+#[proto_message(type_url = "/ibc.applications.transfer.v1.MsgTransferResponse")]
+pub struct MsgTransferResponse {
+    #[prost(uint64, tag = "1")]
+    pub sequence: u64,
+}
+
+// see https://github.com/cosmos/cosmos-sdk/blob/v0.47.5/proto/cosmos/distribution/v1beta1/tx.proto
+
+/// MsgFundCommunityPool allows an account to directly fund the community pool.
+/// DistributionMsg::FundCommunityPool needs cosmwasm_1_3, which we don't enable for Kujira
+#[derive(
+    Clone,
+    PartialEq,
+    Eq,
+    ::prost::Message,
+    serde::Serialize,
+    serde::Deserialize,
+    schemars::JsonSchema,
+    CosmwasmExt,
+)]
+#[proto_message(type_url = "/cosmos.distribution.v1beta1.MsgFundCommunityPool")]
+pub struct MsgFundCommunityPool {
+    #[prost(message, repeated, tag = "1")]
+    pub amount: ::prost::alloc::vec::Vec<Coin>,
+    #[prost(string, tag = "2")]
+    pub depositor: ::prost::alloc::string::String,
+}
@@ -1,22 +1,74 @@
-use cosmwasm_std::Addr;
+use cosmwasm_std::{Addr, Coin};
+use cw20::Cw20CoinVerified;
 use cw_controllers::Admin;
 use cw_item_set::Set;
-use cw_storage_plus::{Item, Map};
-use pfc_fee_split::fee_split_msg::AllocationHolding;
+use cw_storage_plus::{Deque, Item, Map};
+use pfc_fee_split::fee_split_msg::{
+    AllocationHolding, AllocationWeight, DenomFilter, DenomStats, PendingChange,
+    PendingIbcTransfer, Quarantine,
+};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 pub(crate) const CONFIG_KEY: &str = "config_002";
-pub(crate) const FEE_KEY: &str = "fees_002";
+pub(crate) const FEE_KEY: &str = "fees_003";
 
 pub(crate) const FLUSH_WHITELIST_KEY: &str = "flush_001";
 pub(crate) const FLUSH_WHITELIST_COUNTER_KEY: &str = "flush_001";
+pub(crate) const IBC_TRANSFER_QUEUE_KEY: &str = "ibc_queue_001";
+pub(crate) const PENDING_IBC_TRANSFERS_KEY: &str = "ibc_pending_001";
+pub(crate) const DENOM_WEIGHTS_KEY: &str = "denom_weights_001";
+pub(crate) const TABLES_KEY: &str = "tables_001";
+pub(crate) const SENDER_TABLES_KEY: &str = "sender_tables_001";
+pub(crate) const ALLOCATION_STATS_KEY: &str = "stats_001";
+pub(crate) const PAYOUT_REPLIES_KEY: &str = "payout_replies_001";
+pub(crate) const PAYOUT_REPLY_ID_KEY: &str = "payout_reply_id_001";
+pub(crate) const PENDING_CHANGES_KEY: &str = "pending_changes_001";
+pub(crate) const PENDING_CHANGE_ID_KEY: &str = "pending_change_id_001";
+pub(crate) const CLAIM_DELEGATES_KEY: &str = "claim_delegates_001";
+pub(crate) const PENDING_RECEIVERS_KEY: &str = "pending_receivers_001";
+pub(crate) const DENOM_FILTER_KEY: &str = "denom_filter_001";
+pub(crate) const QUARANTINE_KEY: &str = "quarantine_001";
+
+pub(crate) const REPLY_IBC_TRANSFER: u64 = 1;
+/// payouts get their own reply id, from here up
+pub(crate) const REPLY_PAYOUT: u64 = 1_000;
 
 pub const ADMIN: Admin = Admin::new("admin");
 
 pub const CONFIG: Item<Config> = Item::new(CONFIG_KEY);
 pub const ALLOCATION_HOLDINGS: Map<String, AllocationHolding> = Map::new(FEE_KEY);
 pub const FLUSH_WHITELIST: Set<Addr> = Set::new(FLUSH_WHITELIST_KEY, FLUSH_WHITELIST_COUNTER_KEY);
+/// IBC transfers sent in this transaction, waiting on their reply for a sequence number
+pub const IBC_TRANSFER_QUEUE: Deque<PendingIbcTransfer> = Deque::new(IBC_TRANSFER_QUEUE_KEY);
+/// (channel, sequence) -> IBC transfer waiting on an ack or timeout
+pub const PENDING_IBC_TRANSFERS: Map<(&str, u64), PendingIbcTransfer> =
+    Map::new(PENDING_IBC_TRANSFERS_KEY);
+/// denom -> weights to split it with, instead of the allocations' own
+pub const DENOM_WEIGHTS: Map<&str, Vec<AllocationWeight>> = Map::new(DENOM_WEIGHTS_KEY);
+/// named tables of weights, that deposits can be split with instead of the allocations' own
+pub const TABLES: Map<&str, Vec<AllocationWeight>> = Map::new(TABLES_KEY);
+/// sender -> the table their deposits are split with
+pub const SENDER_TABLES: Map<&Addr, String> = Map::new(SENDER_TABLES_KEY);
+/// (allocation name, denom) -> lifetime totals. kept when an allocation is removed
+pub const ALLOCATION_STATS: Map<(&str, &str), DenomStats> = Map::new(ALLOCATION_STATS_KEY);
+/// reply id -> what a payout sent, so it can be credited back if it fails
+pub const PAYOUT_REPLIES: Map<u64, PayoutReply> = Map::new(PAYOUT_REPLIES_KEY);
+/// the last payout reply id used (less REPLY_PAYOUT)
+pub const PAYOUT_REPLY_ID: Item<u64> = Item::new(PAYOUT_REPLY_ID_KEY);
+/// id -> allocation change waiting on the change delay
+pub const PENDING_CHANGES: Map<u64, PendingChange> = Map::new(PENDING_CHANGES_KEY);
+/// the last id given to a pending change
+pub const PENDING_CHANGE_ID: Item<u64> = Item::new(PENDING_CHANGE_ID_KEY);
+/// (allocation, receiver) -> who else can claim for it. keyed by receiver so a new receiver
+/// starts without one
+pub const CLAIM_DELEGATES: Map<(&str, &Addr), Addr> = Map::new(CLAIM_DELEGATES_KEY);
+/// allocation -> receiver change waiting on the new receiver to accept
+pub const PENDING_RECEIVERS: Map<&str, PendingReceiver> = Map::new(PENDING_RECEIVERS_KEY);
+/// which denoms deposits take. missing = all of them
+pub const DENOM_FILTER: Item<DenomFilter> = Item::new(DENOM_FILTER_KEY);
+/// what the denom filter has held back, outside of any allocation
+pub const QUARANTINE: Item<Quarantine> = Item::new(QUARANTINE_KEY);
 
 #[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
 pub struct Config {
@@ -24,4 +76,24 @@ pub struct Config {
     pub gov_contract: Addr,
     pub new_gov_contract: Option<Addr>,
     pub change_gov_contract_by_height: Option<u64>,
+    /// blocks allocation changes are queued for before they can be applied. 0 = no delay
+    #[serde(default)]
+    pub change_delay: u64,
+}
+
+/// a payout message sent with reply_on_error
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PayoutReply {
+    pub name: String,
+    pub coins: Vec<Coin>,
+    pub tokens: Vec<Cw20CoinVerified>,
+    /// replies only come back in the same transaction, so older ones can be dropped
+    pub height: u64,
+}
+
+/// a receiver change proposed by 'receiver'. it lapses if the receiver changes some other way
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+pub struct PendingReceiver {
+    pub receiver: Addr,
+    pub new_receiver: Addr,
 }
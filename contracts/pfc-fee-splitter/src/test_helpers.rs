@@ -1,9 +1,16 @@
 use cosmwasm_std::{
-    coin,
-    testing::{mock_env, mock_info},
-    Api, DepsMut, Response,
+    coin, from_json,
+    testing::{mock_env, mock_info, MockQuerier},
+    to_json_binary, Addr, Api, Binary, ContractInfoResponse, ContractResult, DepsMut, Response,
+    SystemError, SystemResult, Uint128, WasmQuery,
+};
+use pfc_fee_split::{
+    cw4::{Cw4QueryMsg, Member, MemberListResponse, TotalWeightResponse},
+    fee_split_msg::{
+        AllocationDetail, AllocationHolding, AllocationKind, AllocationResponse, AllocationStatus,
+        InstantiateMsg, PayoutMode, QueryMsg, SendAfterMode, SendType,
+    },
 };
-use pfc_fee_split::fee_split_msg::{AllocationDetail, InstantiateMsg, SendType};
 
 use crate::{contract::instantiate, error::ContractError};
 
@@ -15,6 +22,7 @@ pub const ALLOCATION_2: &str = "allocation_2";
 pub const DENOM_1: &str = "uxyz";
 pub const DENOM_2: &str = "uabc";
 pub const DENOM_3: &str = "udef";
+pub const TOKEN_1: &str = "cw20_token";
 
 const NAME: &str = "pfc-fee-split";
 pub(crate) fn do_instantiate(
@@ -36,8 +44,13 @@ pub(crate) fn do_instantiate(
 pub(crate) fn one_allocation(api: &dyn Api) -> Vec<AllocationDetail> {
     vec![AllocationDetail {
         name: ALLOCATION_1.to_string(),
-        allocation: 1,
-        send_after: coin(1_000u128, DENOM_1),
+        allocation: Uint128::new(1),
+        send_after_blocks: None,
+        send_after_seconds: None,
+        payout_mode: PayoutMode::Push,
+        kind: AllocationKind::Weighted,
+        send_after: vec![coin(1_000u128, DENOM_1)],
+        send_after_mode: SendAfterMode::AnyOf,
         send_type: SendType::Wallet {
             receiver: api.addr_validate("allocation_1_addr").unwrap(),
         },
@@ -48,8 +61,13 @@ pub(crate) fn two_allocation(api: &dyn Api) -> Vec<AllocationDetail> {
     vec![
         AllocationDetail {
             name: ALLOCATION_1.to_string(),
-            allocation: 1,
-            send_after: coin(1_000u128, DENOM_1),
+            allocation: Uint128::new(1),
+            send_after_blocks: None,
+            send_after_seconds: None,
+            payout_mode: PayoutMode::Push,
+            kind: AllocationKind::Weighted,
+            send_after: vec![coin(1_000u128, DENOM_1)],
+            send_after_mode: SendAfterMode::AnyOf,
             send_type: SendType::Wallet {
                 receiver: api.addr_validate("allocation_1_addr").unwrap(),
             },
@@ -57,8 +75,15 @@ pub(crate) fn two_allocation(api: &dyn Api) -> Vec<AllocationDetail> {
         AllocationDetail {
             name: ALLOCATION_2.to_string(),
 
-            allocation: 1,
-            send_after: coin(10_000_000u128, DENOM_1),
+            allocation: Uint128::new(1),
+
+            send_after_blocks: None,
+
+            send_after_seconds: None,
+            payout_mode: PayoutMode::Push,
+            kind: AllocationKind::Weighted,
+            send_after: vec![coin(10_000_000u128, DENOM_1)],
+            send_after_mode: SendAfterMode::AnyOf,
             send_type: SendType::SteakRewards {
                 steak: api.addr_validate("steak_contract").unwrap(),
                 receiver: api.addr_validate("receiver").unwrap(),
@@ -66,3 +91,216 @@ pub(crate) fn two_allocation(api: &dyn Api) -> Vec<AllocationDetail> {
         },
     ]
 }
+
+/// makes the mock querier treat these addresses as contracts
+pub(crate) fn mock_contracts(querier: &mut MockQuerier, contracts: &[&str]) {
+    let contracts: Vec<String> = contracts.iter().map(|c| c.to_string()).collect();
+    querier.update_wasm(move |query| match query {
+        WasmQuery::ContractInfo {
+            contract_addr,
+        } if contracts.contains(contract_addr) => SystemResult::Ok(ContractResult::Ok(
+            to_json_binary(&ContractInfoResponse::default()).unwrap(),
+        )),
+        WasmQuery::ContractInfo {
+            contract_addr,
+        }
+        | WasmQuery::Smart {
+            contract_addr,
+            ..
+        }
+        | WasmQuery::Raw {
+            contract_addr,
+            ..
+        } => SystemResult::Err(SystemError::NoSuchContract {
+            addr: contract_addr.clone(),
+        }),
+        _ => SystemResult::Err(SystemError::UnsupportedRequest {
+            kind: "wasm".to_string(),
+        }),
+    });
+}
+
+/// an allocation in another splitter, that deposits into 'contract'
+pub(crate) fn fee_split_holding(name: &str, contract: &str) -> AllocationHolding {
+    AllocationHolding {
+        name: name.to_string(),
+        allocation: Uint128::new(1),
+        send_after: vec![],
+        send_after_mode: SendAfterMode::AnyOf,
+        send_type: SendType::FeeSplit {
+            contract: Addr::unchecked(contract),
+        },
+        balance: vec![],
+        balance_cw20: vec![],
+        send_after_blocks: None,
+        send_after_seconds: None,
+        payout_mode: PayoutMode::Push,
+        status: AllocationStatus::Active,
+        kind: AllocationKind::Weighted,
+        filled: vec![],
+        period_start: None,
+        schedule: vec![],
+        expires: None,
+        last_payout_height: None,
+        last_payout_time: None,
+        payout_error: None,
+    }
+}
+
+/// makes the mock querier treat these addresses as fee splitters, answering the allocations query
+pub(crate) fn mock_splitters(
+    querier: &mut MockQuerier,
+    splitters: Vec<(&str, Vec<AllocationHolding>)>,
+) {
+    let splitters: Vec<(String, Vec<AllocationHolding>)> =
+        splitters.into_iter().map(|(addr, allocations)| (addr.to_string(), allocations)).collect();
+    querier.update_wasm(move |query| {
+        let (contract_addr, msg) = match query {
+            WasmQuery::ContractInfo {
+                contract_addr,
+            } => (contract_addr, None),
+            WasmQuery::Smart {
+                contract_addr,
+                msg,
+            } => (contract_addr, Some(msg)),
+            _ => {
+                return SystemResult::Err(SystemError::UnsupportedRequest {
+                    kind: "wasm".to_string(),
+                })
+            },
+        };
+        let Some((_, allocations)) = splitters.iter().find(|(addr, _)| addr == contract_addr)
+        else {
+            return SystemResult::Err(SystemError::NoSuchContract {
+                addr: contract_addr.clone(),
+            });
+        };
+        let response = match msg.map(from_json::<QueryMsg>) {
+            None => to_json_binary(&ContractInfoResponse::default()),
+            Some(Ok(QueryMsg::Allocations {
+                start_after,
+                ..
+            })) => to_json_binary(&AllocationResponse {
+                allocations: allocations
+                    .iter()
+                    .filter(|a| start_after.as_ref().map(|s| &a.name > s).unwrap_or(true))
+                    .cloned()
+                    .collect(),
+            }),
+            _ => {
+                return SystemResult::Err(SystemError::UnsupportedRequest {
+                    kind: "smart".to_string(),
+                })
+            },
+        };
+        SystemResult::Ok(ContractResult::Ok(response.unwrap()))
+    });
+}
+
+/// makes the mock querier treat these addresses as splitters running an older version, answering
+/// the first page of the allocations query with the raw JSON 'allocations', and later pages empty
+pub(crate) fn mock_raw_splitters(querier: &mut MockQuerier, splitters: Vec<(&str, &str)>) {
+    let splitters: Vec<(String, String)> =
+        splitters.into_iter().map(|(addr, json)| (addr.to_string(), json.to_string())).collect();
+    querier.update_wasm(move |query| {
+        let (contract_addr, msg) = match query {
+            WasmQuery::ContractInfo {
+                contract_addr,
+            } => (contract_addr, None),
+            WasmQuery::Smart {
+                contract_addr,
+                msg,
+            } => (contract_addr, Some(msg)),
+            _ => {
+                return SystemResult::Err(SystemError::UnsupportedRequest {
+                    kind: "wasm".to_string(),
+                })
+            },
+        };
+        let Some((_, json)) = splitters.iter().find(|(addr, _)| addr == contract_addr) else {
+            return SystemResult::Err(SystemError::NoSuchContract {
+                addr: contract_addr.clone(),
+            });
+        };
+        let response = match msg.map(from_json::<QueryMsg>) {
+            None => {
+                return SystemResult::Ok(ContractResult::Ok(
+                    to_json_binary(&ContractInfoResponse::default()).unwrap(),
+                ))
+            },
+            Some(Ok(QueryMsg::Allocations {
+                start_after: None,
+                ..
+            })) => format!("{{\"allocations\":{}}}", json),
+            Some(Ok(QueryMsg::Allocations {
+                ..
+            })) => "{\"allocations\":[]}".to_string(),
+            _ => {
+                return SystemResult::Err(SystemError::UnsupportedRequest {
+                    kind: "smart".to_string(),
+                })
+            },
+        };
+        SystemResult::Ok(ContractResult::Ok(Binary::from(response.into_bytes())))
+    });
+}
+
+/// makes the mock querier treat 'group' as a cw4 group with these members (in address order).
+/// pages are 2 members long, to exercise the paging
+pub(crate) fn mock_cw4_group(querier: &mut MockQuerier, group: &str, members: &[(&str, u64)]) {
+    let group = group.to_string();
+    let mut members: Vec<Member> = members
+        .iter()
+        .map(|(addr, weight)| Member {
+            addr: addr.to_string(),
+            weight: *weight,
+        })
+        .collect();
+    members.sort_by(|a, b| a.addr.cmp(&b.addr));
+    querier.update_wasm(move |query| {
+        let (contract_addr, msg) = match query {
+            WasmQuery::ContractInfo {
+                contract_addr,
+            } => (contract_addr, None),
+            WasmQuery::Smart {
+                contract_addr,
+                msg,
+            } => (contract_addr, Some(msg)),
+            _ => {
+                return SystemResult::Err(SystemError::UnsupportedRequest {
+                    kind: "wasm".to_string(),
+                })
+            },
+        };
+        if *contract_addr != group {
+            return SystemResult::Err(SystemError::NoSuchContract {
+                addr: contract_addr.clone(),
+            });
+        }
+        let response = match msg.map(from_json::<Cw4QueryMsg>) {
+            None => to_json_binary(&ContractInfoResponse::default()),
+            Some(Ok(Cw4QueryMsg::ListMembers {
+                start_after,
+                ..
+            })) => to_json_binary(&MemberListResponse {
+                members: members
+                    .iter()
+                    .filter(|m| start_after.as_ref().map(|s| &m.addr > s).unwrap_or(true))
+                    .take(2)
+                    .cloned()
+                    .collect(),
+            }),
+            Some(Ok(Cw4QueryMsg::TotalWeight {
+                ..
+            })) => to_json_binary(&TotalWeightResponse {
+                weight: members.iter().map(|m| m.weight).sum(),
+            }),
+            _ => {
+                return SystemResult::Err(SystemError::UnsupportedRequest {
+                    kind: "smart".to_string(),
+                })
+            },
+        };
+        SystemResult::Ok(ContractResult::Ok(response.unwrap()))
+    });
+}
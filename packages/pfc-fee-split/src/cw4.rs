@@ -0,0 +1,47 @@
+// the parts of the cw4 group spec SendType::Cw4Group uses.
+// see https://github.com/CosmWasm/cw-plus/tree/main/packages/cw4
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Cw4QueryMsg {
+    /// paged, in address order. groups cap 'limit' at 30
+    ListMembers {
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+    TotalWeight {
+        at_height: Option<u64>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+pub struct Member {
+    pub addr: String,
+    pub weight: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+pub struct MemberListResponse {
+    pub members: Vec<Member>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+pub struct TotalWeightResponse {
+    pub weight: u64,
+}
+
+/// a member's weight before & after a change. None if they weren't/aren't a member
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+pub struct MemberDiff {
+    pub key: String,
+    pub old: Option<u64>,
+    pub new: Option<u64>,
+}
+
+/// what a group sends its hooks, after the change has been made
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+pub struct MemberChangedHookMsg {
+    pub diffs: Vec<MemberDiff>,
+}
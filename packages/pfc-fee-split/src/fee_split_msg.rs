@@ -1,11 +1,17 @@
-use std::fmt::Display;
+use std::{collections::BTreeMap, fmt::Display};
 
 use cosmwasm_std::{
-    to_json_binary, Addr, Binary, Coin, CosmosMsg, DepsMut, StdError, StdResult, WasmMsg,
+    from_json, to_json_binary, Addr, Binary, BlockInfo, Coin, CosmosMsg, DepsMut, StdError,
+    StdResult, Timestamp, Uint128, WasmMsg,
 };
-//use cw20::Cw20ReceiveMsg;
+use cw20::{Cw20CoinVerified, Cw20ReceiveMsg};
 use schemars::JsonSchema;
-use serde::{Deserialize, Serialize};
+use serde::{
+    de::{value::MapAccessDeserializer, IgnoredAny, MapAccess, SeqAccess, Visitor},
+    Deserialize, Deserializer, Serialize,
+};
+
+use crate::cw4::{Cw4QueryMsg, MemberChangedHookMsg, TotalWeightResponse};
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
 pub enum SendType {
@@ -24,6 +30,46 @@ pub enum SendType {
         steak: Addr,
         receiver: Addr,
     },
+    /// execute 'msg' on a contract, with the coins attached as funds.
+    /// CW20 tokens are sent via the token's 'send', with 'msg' as the hook message
+    Contract {
+        contract: Addr,
+        msg: Binary,
+    },
+    /// send the coins to another chain via ICS20, one transfer per coin.
+    /// 'memo' (if set) must be a JSON object, without an 'ibc_callback'.
+    /// CW20 tokens are held, not sent (and go to the other allocations if it is removed)
+    IbcTransfer {
+        channel_id: String,
+        receiver: String,
+        timeout_seconds: u64,
+        memo: Option<String>,
+    },
+    /// deposit into another fee splitter (flush:false).
+    /// CW20 tokens are sent via the token's 'send', with a deposit hook message
+    FeeSplit {
+        contract: Addr,
+    },
+    /// bond the coins into a liquid staking hub, with the LST going to 'receiver'.
+    /// CW20 tokens go directly to the receiver
+    LiquidStake {
+        hub: Addr,
+        receiver: Addr,
+        protocol: LiquidStakeProtocol,
+    },
+    /// burn the coins (and CW20 tokens)
+    Burn {},
+    /// fund the community pool. CW20 tokens are held, not sent (and go to the other allocations
+    /// if it is removed)
+    CommunityPool {},
+    /// bank send each member of a cw4 group its share, by the member weights at payout time.
+    /// CW20 tokens are transferred the same way. the group's admin should add this contract as
+    /// a hook, so balances are paid out with the old weights before they change.
+    /// while no member has weight the balance is held. if the allocation is removed (or
+    /// replaced) then, what it holds is split over the rest
+    Cw4Group {
+        group: Addr,
+    },
 }
 impl Display for SendType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -49,11 +95,121 @@ impl Display for SendType {
             } => {
                 format!("Steak:{} -> {} Transfer", steak, receiver)
             },
+            SendType::Contract {
+                contract,
+                msg,
+            } => {
+                format!("Contract:{} -> {}", contract, msg)
+            },
+            SendType::IbcTransfer {
+                channel_id,
+                receiver,
+                ..
+            } => {
+                format!("IBC:{} -> {}", channel_id, receiver)
+            },
+            SendType::FeeSplit {
+                contract,
+            } => {
+                format!("FeeSplit:{}", contract)
+            },
+            SendType::LiquidStake {
+                hub,
+                receiver,
+                protocol,
+            } => {
+                format!("LiquidStake:{}:{} -> {}", protocol, hub, receiver)
+            },
+            SendType::Burn {} => "Burn".to_string(),
+            SendType::CommunityPool {} => "CommunityPool".to_string(),
+            SendType::Cw4Group {
+                group,
+            } => format!("Cw4Group:{}", group),
         };
         write!(f, "{}", str)
     }
 }
 impl SendType {
+    /// the account on this chain the funds end up with (for claims).
+    /// None when they leave the chain, or aren't sent to anyone
+    pub fn local_receiver(&self) -> Option<&Addr> {
+        match self {
+            SendType::Wallet {
+                receiver,
+            }
+            | SendType::SteakRewards {
+                receiver,
+                ..
+            }
+            | SendType::DistributeSteakRewards {
+                receiver,
+                ..
+            }
+            | SendType::TransferSteakRewards {
+                receiver,
+                ..
+            }
+            | SendType::LiquidStake {
+                receiver,
+                ..
+            } => Some(receiver),
+            SendType::Contract {
+                contract,
+                ..
+            }
+            | SendType::FeeSplit {
+                contract,
+            } => Some(contract),
+            SendType::IbcTransfer {
+                ..
+            }
+            | SendType::Burn {}
+            | SendType::CommunityPool {}
+            | SendType::Cw4Group {
+                ..
+            } => None,
+        }
+    }
+
+    /// the 'receiver' field, for send types that have one on this chain
+    pub fn receiver_mut(&mut self) -> Option<&mut Addr> {
+        match self {
+            SendType::Wallet {
+                receiver,
+            }
+            | SendType::SteakRewards {
+                receiver,
+                ..
+            }
+            | SendType::DistributeSteakRewards {
+                receiver,
+                ..
+            }
+            | SendType::TransferSteakRewards {
+                receiver,
+                ..
+            }
+            | SendType::LiquidStake {
+                receiver,
+                ..
+            } => Some(receiver),
+            SendType::Contract {
+                ..
+            }
+            | SendType::IbcTransfer {
+                ..
+            }
+            | SendType::FeeSplit {
+                ..
+            }
+            | SendType::Burn {}
+            | SendType::CommunityPool {}
+            | SendType::Cw4Group {
+                ..
+            } => None,
+        }
+    }
+
     #[deprecated(since = "0.2.9", note = "insufficient checking. use verify_details")]
     pub fn verify(&self, address: &Addr) -> bool {
         match &self {
@@ -72,6 +228,24 @@ impl SendType {
                 receiver,
                 ..
             } => receiver != address,
+            SendType::Contract {
+                contract,
+                ..
+            } => contract != address,
+            SendType::IbcTransfer {
+                ..
+            } => true,
+            SendType::FeeSplit {
+                contract,
+            } => contract != address,
+            SendType::LiquidStake {
+                receiver,
+                ..
+            } => receiver != address,
+            SendType::Burn {} | SendType::CommunityPool {} => true,
+            SendType::Cw4Group {
+                group,
+            } => group != address,
         }
     }
 
@@ -124,26 +298,542 @@ impl SendType {
                     Err(StdError::generic_err("address recursion"))
                 }
             },
+            SendType::Contract {
+                contract,
+                ..
+            } => {
+                if contract != address {
+                    deps.api.addr_validate(contract.as_str())?;
+                    // errors if it isn't a contract
+                    deps.querier.query_wasm_contract_info(contract)?;
+                    Ok(())
+                } else {
+                    Err(StdError::generic_err("address recursion"))
+                }
+            },
+            SendType::IbcTransfer {
+                channel_id,
+                receiver,
+                timeout_seconds,
+                memo,
+            } => {
+                if !channel_id.starts_with("channel-") {
+                    return Err(StdError::generic_err("invalid channel_id"));
+                }
+                if receiver.trim().is_empty() {
+                    return Err(StdError::generic_err("missing receiver"));
+                }
+                if *timeout_seconds == 0 {
+                    return Err(StdError::generic_err("timeout_seconds must be greater than zero"));
+                }
+                if let Some(memo) = memo {
+                    validate_ibc_memo(memo)?;
+                }
+                Ok(())
+            },
+            SendType::FeeSplit {
+                contract,
+            } => {
+                if contract != address {
+                    deps.api.addr_validate(contract.as_str())?;
+                    deps.querier.query_wasm_contract_info(contract)?;
+                    Self::verify_fee_split_chain(deps, contract, address)
+                } else {
+                    Err(StdError::generic_err("address recursion"))
+                }
+            },
+            SendType::LiquidStake {
+                receiver,
+                hub,
+                ..
+            } => {
+                if receiver != address {
+                    deps.api.addr_validate(receiver.as_str())?;
+                    deps.api.addr_validate(hub.as_str())?;
+                    Ok(())
+                } else {
+                    Err(StdError::generic_err("address recursion"))
+                }
+            },
+            // nothing to point at
+            SendType::Burn {} | SendType::CommunityPool {} => Ok(()),
+            SendType::Cw4Group {
+                group,
+            } => {
+                if group != address {
+                    deps.api.addr_validate(group.as_str())?;
+                    // errors if it isn't a cw4 group. it can be empty for now
+                    let _: TotalWeightResponse = deps.querier.query_wasm_smart(
+                        group,
+                        &Cw4QueryMsg::TotalWeight {
+                            at_height: None,
+                        },
+                    )?;
+                    Ok(())
+                } else {
+                    Err(StdError::generic_err("address recursion"))
+                }
+            },
+        }
+    }
+
+    /// walks the splitters reachable from 'contract' via FeeSplit allocations (in any of their
+    /// tables), and errors if any of them would send back to 'address'.
+    /// It only follows FeeSplit allocations, not Contract/IBC/LiquidStake ones that may also end
+    /// up at a splitter, and it can't stop a splitter in the chain being changed afterwards.
+    /// Chains deeper than MAX_CHAIN_DEPTH, or reaching more than MAX_CHAIN_SPLITTERS splitters,
+    /// are rejected as too big to check
+    fn verify_fee_split_chain(deps: &DepsMut, contract: &Addr, address: &Addr) -> StdResult<()> {
+        let mut visited: Vec<Addr> = vec![];
+        let mut to_visit: Vec<(Addr, usize)> = vec![(contract.clone(), 1)];
+        while let Some((splitter, depth)) = to_visit.pop() {
+            if visited.contains(&splitter) {
+                continue;
+            }
+            if depth > MAX_CHAIN_DEPTH || visited.len() >= MAX_CHAIN_SPLITTERS {
+                return Err(StdError::generic_err("fee split chain is too big to check"));
+            }
+            let mut start_after: Option<String> = None;
+            loop {
+                let page: ChainAllocations = deps.querier.query_wasm_smart(
+                    &splitter,
+                    &QueryMsg::Allocations {
+                        start_after: start_after.clone(),
+                        limit: None,
+                        table: None,
+                    },
+                )?;
+                let Some(last) = page.allocations.last() else {
+                    break;
+                };
+                start_after = Some(last.name.clone());
+                for allocation in page.allocations {
+                    if let Some(ChainSendType {
+                        contract: Some(child),
+                    }) = allocation.send_type.get("FeeSplit")
+                    {
+                        if child == address {
+                            return Err(StdError::generic_err("address recursion"));
+                        }
+                        to_visit.push((child.clone(), depth + 1));
+                    }
+                }
+            }
+            visited.push(splitter);
+        }
+        Ok(())
+    }
+}
+
+/// an IbcTransfer memo must be a JSON object, without an 'ibc_callback' (the splitter adds its
+/// own, to hear about acks & timeouts)
+pub fn validate_ibc_memo(memo: &str) -> StdResult<()> {
+    let fields: BTreeMap<String, IgnoredAny> = from_json(memo.trim().as_bytes())
+        .map_err(|_| StdError::generic_err("memo must be a JSON object"))?;
+    if fields.contains_key("ibc_callback") {
+        return Err(StdError::generic_err("memo can't set ibc_callback"));
+    }
+    Ok(())
+}
+
+/// the longest change delay that can be set, in blocks
+pub const MAX_CHANGE_DELAY: u64 = 1_000_000;
+
+/// how many FeeSplit hops verify_fee_split_chain follows
+pub const MAX_CHAIN_DEPTH: usize = 5;
+/// how many splitters verify_fee_split_chain queries
+pub const MAX_CHAIN_SPLITTERS: usize = 20;
+/// how many denoms the quarantine holds. past that, new denoms are refunded (or, with no one to
+/// refund, left untracked)
+pub const MAX_QUARANTINE_DENOMS: usize = 20;
+
+/// the parts of another splitter's allocations verify_fee_split_chain reads. Splitters running
+/// older versions return the other fields in other shapes (eg. 'allocation' as a u8)
+#[derive(Deserialize)]
+struct ChainAllocations {
+    allocations: Vec<ChainAllocation>,
+}
+
+#[derive(Deserialize)]
+struct ChainAllocation {
+    name: String,
+    /// the send type's name -> its fields
+    send_type: BTreeMap<String, ChainSendType>,
+}
+
+#[derive(Deserialize)]
+struct ChainSendType {
+    #[serde(default)]
+    contract: Option<Addr>,
+}
+
+/// which hub's 'bond' message to use for SendType::LiquidStake.
+/// Stride isn't one: it has no hub contract, and its MsgLiquidStake mints the LST to the sender
+/// (this contract) rather than a receiver, so it isn't supported
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Eq, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum LiquidStakeProtocol {
+    /// pfc-steak (and forks)
+    Steak,
+    /// Eris Amplifier
+    Eris,
+    /// Backbone Labs
+    Backbone,
+}
+impl Display for LiquidStakeProtocol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LiquidStakeProtocol::Steak => write!(f, "steak"),
+            LiquidStakeProtocol::Eris => write!(f, "eris"),
+            LiquidStakeProtocol::Backbone => write!(f, "backbone"),
+        }
+    }
+}
+
+/// what ReplaceAllocations does with the balances held by the current allocations
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Eq, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum BalancePolicy {
+    /// every current allocation pays out what it holds
+    PayOut,
+    /// allocations with the same name keep their balances. the rest pay out
+    CarryOver,
+    /// everything held is pooled and split with the new weights, and any per-denom weights (and
+    /// held until the next payout)
+    Redistribute,
+}
+impl Display for BalancePolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BalancePolicy::PayOut => write!(f, "pay_out"),
+            BalancePolicy::CarryOver => write!(f, "carry_over"),
+            BalancePolicy::Redistribute => write!(f, "redistribute"),
+        }
+    }
+}
+
+/// how an allocation gets paid
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, Eq, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PayoutMode {
+    /// sent on a deposit once it is over its threshold (or time)
+    #[default]
+    Push,
+    /// held until the receiver (or their delegate) claims it
+    Pull,
+}
+impl Display for PayoutMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PayoutMode::Push => write!(f, "push"),
+            PayoutMode::Pull => write!(f, "pull"),
+        }
+    }
+}
+
+/// how an allocation's share of a deposit is worked out
+#[derive(Serialize, Deserialize, Clone, Debug, Default, Eq, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AllocationKind {
+    /// a share (by weight) of what is left after the fixed & capped allocations are filled
+    #[default]
+    Weighted,
+    /// up to 'amount_per_period' of each of these denoms, every 'period' seconds.
+    /// filled before the weighted split, lowest 'priority' first
+    Fixed {
+        amount_per_period: Vec<Coin>,
+        period: u64,
+        #[serde(default)]
+        priority: u32,
+    },
+    /// all of these denoms, until it has been given 'cap' in total.
+    /// filled before the weighted split, lowest 'priority' first
+    Capped {
+        cap: Vec<Coin>,
+        #[serde(default)]
+        priority: u32,
+    },
+}
+impl Display for AllocationKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AllocationKind::Weighted => write!(f, "weighted"),
+            AllocationKind::Fixed {
+                ..
+            } => write!(f, "fixed"),
+            AllocationKind::Capped {
+                ..
+            } => write!(f, "capped"),
+        }
+    }
+}
+impl AllocationKind {
+    /// the order fixed & capped allocations are filled in. None for weighted ones
+    pub fn priority(&self) -> Option<u32> {
+        match self {
+            AllocationKind::Weighted => None,
+            AllocationKind::Fixed {
+                priority,
+                ..
+            }
+            | AllocationKind::Capped {
+                priority,
+                ..
+            } => Some(*priority),
+        }
+    }
+
+    /// the most a fixed (per period) or capped (in total) allocation can be given
+    pub fn limit(&self) -> &[Coin] {
+        match self {
+            AllocationKind::Weighted => &[],
+            AllocationKind::Fixed {
+                amount_per_period,
+                ..
+            } => amount_per_period,
+            AllocationKind::Capped {
+                cap,
+                ..
+            } => cap,
+        }
+    }
+}
+
+/// a block height or time something happens at
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ScheduleAt {
+    Height(u64),
+    Time(Timestamp),
+}
+impl Display for ScheduleAt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScheduleAt::Height(height) => write!(f, "height:{}", height),
+            ScheduleAt::Time(time) => write!(f, "time:{}", time.seconds()),
+        }
+    }
+}
+impl ScheduleAt {
+    pub fn is_reached(&self, block: &BlockInfo) -> bool {
+        match self {
+            ScheduleAt::Height(height) => block.height >= *height,
+            ScheduleAt::Time(time) => block.time >= *time,
         }
     }
 }
 
+/// the weight an allocation changes to, once 'at' is reached
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+pub struct WeightChange {
+    pub at: ScheduleAt,
+    pub weight: Uint128,
+}
+
+/// whether an allocation takes part in deposits
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, Eq, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AllocationStatus {
+    #[default]
+    Active,
+    /// still gets its share of deposits, but is never paid out
+    Paused,
+    /// left out of new splits (its weight goes to the others), and never paid out
+    Frozen,
+}
+impl Display for AllocationStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AllocationStatus::Active => write!(f, "active"),
+            AllocationStatus::Paused => write!(f, "paused"),
+            AllocationStatus::Frozen => write!(f, "frozen"),
+        }
+    }
+}
+
+/// which denoms deposits take. CW20 tokens are listed by their address
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, Eq, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DenomFilterMode {
+    /// every denom
+    #[default]
+    Off,
+    /// only the denoms listed
+    Allow,
+    /// every denom except those listed
+    Deny,
+}
+impl Display for DenomFilterMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DenomFilterMode::Off => write!(f, "off"),
+            DenomFilterMode::Allow => write!(f, "allow"),
+            DenomFilterMode::Deny => write!(f, "deny"),
+        }
+    }
+}
+
+/// what happens to the denoms a deposit sends that the filter doesn't take
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, Eq, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum FilteredDenomAction {
+    /// sent back to the depositor
+    #[default]
+    Refund,
+    /// held apart from the allocations, until the admin sweeps them out. CW20 tokens (and
+    /// denoms past MAX_QUARANTINE_DENOMS) are refunded instead
+    Quarantine,
+}
+impl Display for FilteredDenomAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FilteredDenomAction::Refund => write!(f, "refund"),
+            FilteredDenomAction::Quarantine => write!(f, "quarantine"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, Eq, PartialEq, JsonSchema)]
+pub struct DenomFilter {
+    pub mode: DenomFilterMode,
+    pub denoms: Vec<String>,
+    pub on_filtered: FilteredDenomAction,
+    /// the most denoms one deposit can send. None for no limit
+    pub max_denoms: Option<u32>,
+}
+impl DenomFilter {
+    pub fn allows(&self, denom: &str) -> bool {
+        match self.mode {
+            DenomFilterMode::Off => true,
+            DenomFilterMode::Allow => self.denoms.iter().any(|d| d == denom),
+            DenomFilterMode::Deny => !self.denoms.iter().any(|d| d == denom),
+        }
+    }
+}
+
+/// how the coins in 'send_after' are combined
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, Eq, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SendAfterMode {
+    /// send when any one of the coins is over its threshold
+    #[default]
+    AnyOf,
+    /// only send when every coin is over its threshold
+    AllOf,
+}
+
+/// 'send_after' used to be a single coin. this accepts either that, or a list of coins
+fn one_or_many_coins<'de, D>(deserializer: D) -> Result<Vec<Coin>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    struct OneOrManyCoins;
+
+    impl<'de> Visitor<'de> for OneOrManyCoins {
+        type Value = Vec<Coin>;
+
+        fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+            formatter.write_str("a coin or a list of coins")
+        }
+
+        fn visit_map<A>(self, map: A) -> Result<Self::Value, A::Error>
+        where
+            A: MapAccess<'de>,
+        {
+            Ok(vec![Coin::deserialize(MapAccessDeserializer::new(map))?])
+        }
+
+        fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+        where
+            A: SeqAccess<'de>,
+        {
+            let mut coins = vec![];
+            while let Some(coin) = seq.next_element::<Coin>()? {
+                coins.push(coin);
+            }
+            Ok(coins)
+        }
+    }
+
+    deserializer.deserialize_any(OneOrManyCoins)
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
 pub struct AllocationDetail {
     pub name: String, // user-friendly name of wallet
     //   pub contract: String,    // contract/wallet to send too
-    pub allocation: u8,      // what portion should we send
-    pub send_after: Coin,    // only send $ after we have this amount in this coin
+    pub allocation: Uint128, // what portion (weight) should we send
+    /// only send $ after we have these amounts in these coins
+    #[serde(deserialize_with = "one_or_many_coins")]
+    pub send_after: Vec<Coin>,
+    #[serde(default)]
+    pub send_after_mode: SendAfterMode,
     pub send_type: SendType, // type of contract/wallet this is
+    /// also send once this many blocks have passed since the last payout
+    #[serde(default)]
+    pub send_after_blocks: Option<u64>,
+    /// also send once this many seconds have passed since the last payout
+    #[serde(default)]
+    pub send_after_seconds: Option<u64>,
+    /// push pays out on deposits. pull holds the balance until the receiver claims it
+    #[serde(default)]
+    pub payout_mode: PayoutMode,
+    /// weighted (default), fixed or capped
+    #[serde(default)]
+    pub kind: AllocationKind,
 }
-#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct AllocationHolding {
     pub name: String, // user-friendly name of wallet
     // pub contract: Addr,      // contract/wallet to send too
-    pub allocation: u8,      // what portion should we send
-    pub send_after: Coin,    // only send $ after we have this amount in this coin
+    pub allocation: Uint128, // what portion (weight) should we send
+    /// only send $ after we have these amounts in these coins
+    #[serde(deserialize_with = "one_or_many_coins")]
+    pub send_after: Vec<Coin>,
+    #[serde(default)]
+    pub send_after_mode: SendAfterMode,
     pub send_type: SendType, // type of contract/wallet this is
     pub balance: Vec<Coin>,
+    /// CW20 tokens held for this allocation, waiting to be sent
+    #[serde(default)]
+    pub balance_cw20: Vec<Cw20CoinVerified>,
+    /// also send once this many blocks have passed since the last payout
+    #[serde(default)]
+    pub send_after_blocks: Option<u64>,
+    /// also send once this many seconds have passed since the last payout
+    #[serde(default)]
+    pub send_after_seconds: Option<u64>,
+    /// push pays out on deposits. pull holds the balance until the receiver claims it
+    #[serde(default)]
+    pub payout_mode: PayoutMode,
+    /// see SetAllocationStatus
+    #[serde(default)]
+    pub status: AllocationStatus,
+    /// weighted (default), fixed or capped
+    #[serde(default)]
+    pub kind: AllocationKind,
+    /// what a fixed allocation has been given this period, or a capped one in total
+    #[serde(default)]
+    pub filled: Vec<Coin>,
+    /// when a fixed allocation's current period started
+    #[serde(default)]
+    pub period_start: Option<Timestamp>,
+    /// weight changes still to come, applied (in this order) by the first deposit after they
+    /// are reached
+    #[serde(default)]
+    pub schedule: Vec<WeightChange>,
+    /// removed (paying out what it holds) by the first deposit after this
+    #[serde(default)]
+    pub expires: Option<ScheduleAt>,
+    /// block height of the last payout (or when the allocation was added)
+    #[serde(default)]
+    pub last_payout_height: Option<u64>,
+    /// block time of the last payout (or when the allocation was added)
+    #[serde(default)]
+    pub last_payout_time: Option<Timestamp>,
+    /// set when a payout failed (and was credited back to the balance). see RetryPayout
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payout_error: Option<String>,
 }
 #[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
 pub struct InstantiateMsg {
@@ -163,32 +853,145 @@ pub struct InitHook {
 #[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
 pub struct MigrateMsg {}
 
-#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
-
 pub enum ExecuteMsg {
     /// what other contracts will call to start the fly-wheel or fee distribution
+    /// the funds are split with the sender's table (see SetSenderTable), or the default weights.
+    /// whitelisted senders can pick the 'table' themselves
     Deposit {
         flush: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        table: Option<String>,
     },
+    /// CW20 tokens sent via the token's 'send' message. see Cw20HookMsg
+    Receive(Cw20ReceiveMsg),
 
     AddAllocationDetail {
         name: String,
-        allocation: u8,
-        send_after: Coin,
+        allocation: Uint128,
+        #[serde(deserialize_with = "one_or_many_coins")]
+        send_after: Vec<Coin>,
+        #[serde(default)]
+        send_after_mode: SendAfterMode,
         send_type: SendType,
+        send_after_blocks: Option<u64>,
+        send_after_seconds: Option<u64>,
+        /// push pays out on deposits. pull holds the balance until the receiver claims it
+        #[serde(default)]
+        payout_mode: PayoutMode,
+        /// weighted (default), fixed or capped
+        #[serde(default)]
+        kind: AllocationKind,
     },
     // Modifies the fee, but does not send balance
     ModifyAllocationDetail {
         name: String,
-        allocation: u8,
-        send_after: Coin,
+        allocation: Uint128,
+        #[serde(deserialize_with = "one_or_many_coins")]
+        send_after: Vec<Coin>,
+        #[serde(default)]
+        send_after_mode: SendAfterMode,
         send_type: SendType,
+        send_after_blocks: Option<u64>,
+        send_after_seconds: Option<u64>,
+        /// push pays out on deposits. pull holds the balance until the receiver claims it
+        #[serde(default)]
+        payout_mode: PayoutMode,
+        /// weighted (default), fixed or capped
+        #[serde(default)]
+        kind: AllocationKind,
     },
     /// Removes the 'fee', sending whatever balance is there over
     RemoveAllocationDetail {
         name: String,
     },
+    /// replaces all the allocations with 'allocations', in one go. allocations that stay keep
+    /// their status and weight schedule. 'balance_policy' says what happens to the balances the
+    /// current allocations hold
+    ReplaceAllocations {
+        allocations: Vec<AllocationDetail>,
+        balance_policy: BalancePolicy,
+    },
+    /// replaces the allocation's scheduled weight changes and expiry. they are applied by the
+    /// first deposit after they are reached. once expired, an allocation gets no more deposits
+    /// and is removed when it holds nothing: an active push allocation pays out straight away,
+    /// paused & frozen ones wait to be made active, and pull ones wait to be claimed.
+    /// This is a ADMIN only function
+    SetWeightSchedule {
+        name: String,
+        schedule: Vec<WeightChange>,
+        expires: Option<ScheduleAt>,
+    },
+    /// with a change delay, allocation changes (add/modify/remove/replace, weight schedules,
+    /// statuses, denom weights, tables, sender tables, the denom filter and the delay itself)
+    /// are queued for this many blocks before they can be applied. 0 applies them straight away.
+    /// while a delay is set, changing it is queued as well. at most MAX_CHANGE_DELAY blocks
+    SetChangeDelay {
+        blocks: u64,
+    },
+    /// applies a queued change once its delay has passed. anyone can call this
+    ApplyChange {
+        id: u64,
+    },
+    /// drops a queued change. gov_contract only
+    CancelChange {
+        id: u64,
+    },
+    /// pays out everything the allocation holds. only its receiver, or their delegate, can claim
+    Claim {
+        name: String,
+    },
+    /// lets 'delegate' claim for the allocation (None removes it). only the receiver can set this,
+    /// and it stops working if the receiver changes
+    SetClaimDelegate {
+        name: String,
+        delegate: Option<String>,
+    },
+    /// proposes 'new_receiver' as the allocation's receiver. only the current receiver can call
+    /// this, and nothing changes until 'new_receiver' accepts
+    UpdateMyReceiver {
+        name: String,
+        new_receiver: String,
+    },
+    /// accepts a receiver change proposed with 'update_my_receiver'. the new receiver only
+    AcceptMyReceiver {
+        name: String,
+    },
+    /// sent by a cw4 group after its members change. active allocations paying that group are
+    /// paid out with the weights from before the change (paused & frozen ones keep their
+    /// balance). other senders get a 'no-action' attribute rather than an error, so the group can
+    /// always change
+    MemberChangedHook(MemberChangedHookMsg),
+    /// pays out everything an allocation holds, after a payout to it failed.
+    /// the gov contract & flush whitelist only
+    RetryPayout {
+        name: String,
+    },
+    /// pauses (keeps its share, but doesn't pay out) or freezes (no share, no payouts) an
+    /// allocation, without removing it. This is a ADMIN only function
+    SetAllocationStatus {
+        name: String,
+        status: AllocationStatus,
+    },
+    /// splits native funds the contract holds that no allocation has been given yet
+    /// (eg. commission withdrawn straight to this contract). anyone can call this.
+    /// denoms the denom filter doesn't take are quarantined
+    Sweep {},
+    /// sets which denoms deposits take, what happens to the rest, and how many denoms a deposit
+    /// can send. what the allocations already hold isn't touched. This is a ADMIN only function
+    SetDenomFilter {
+        mode: DenomFilterMode,
+        denoms: Vec<String>,
+        on_filtered: FilteredDenomAction,
+        max_denoms: Option<u32>,
+    },
+    /// sends what the quarantine holds of 'denom' to 'to', a denom at a time.
+    /// This is a ADMIN only function
+    SweepQuarantine {
+        to: String,
+        denom: String,
+    },
     /// Queries tokens held, and then re-assigns them to allocations, wiping out whatever was
     /// there. This is a ADMIN only function (must be called by current gov_contract)
     Reconcile {},
@@ -207,6 +1010,32 @@ pub enum ExecuteMsg {
     RemoveFromFlushWhitelist {
         address: String,
     },
+    /// split 'denom' with these weights instead of the allocations' own.
+    /// allocations not listed get none of it
+    SetDenomWeights {
+        denom: String,
+        weights: Vec<AllocationWeight>,
+    },
+    /// go back to splitting 'denom' with the allocations' own weights
+    ClearDenomWeights {
+        denom: String,
+    },
+    /// creates/replaces a named table of weights over the allocations.
+    /// deposits using it are split with these weights (all denoms), allocations not listed get
+    /// none
+    SetTable {
+        table: String,
+        weights: Vec<AllocationWeight>,
+    },
+    /// removes a table, and any senders using it go back to the default weights
+    RemoveTable {
+        table: String,
+    },
+    /// deposits from 'sender' use 'table'. None goes back to the default weights
+    SetSenderTable {
+        sender: String,
+        table: Option<String>,
+    },
 }
 impl ExecuteMsg {
     /// serializes the message
@@ -234,25 +1063,168 @@ impl ExecuteMsg {
     }
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Cw20HookMsg {
+    /// CW20 version of ExecuteMsg::Deposit. the token sent is split across the allocations
+    Deposit {
+        flush: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        table: Option<String>,
+    },
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum QueryMsg {
     /// lists all fees
     /// Return Type: AllocationResponse
+    /// with 'table', only the allocations in that table, with the table's weights
     Allocations {
         start_after: Option<String>,
         limit: Option<u32>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        table: Option<String>,
     },
-    /// Returns allocation with name 'name'
+    /// Returns allocation with name 'name' (including when it last paid out)
+    /// with 'table', the allocation has the table's weight (None if it isn't in the table)
     /// Return Type: AllocationHolding
     Allocation {
         name: String,
+        #[serde(default)]
+        table: Option<String>,
+    },
+    /// lists the named allocation tables
+    /// Return Type: TablesResponse
+    Tables {
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+    /// lists which senders' deposits go to which table
+    /// Return Type: SenderTablesResponse
+    SenderTables {
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+    /// lifetime totals for an allocation, per denom
+    /// Return Type: AllocationStatsResponse
+    AllocationStats {
+        name: String,
+    },
+    /// lifetime totals for all allocations (including removed ones)
+    /// Return Type: StatsResponse
+    Stats {
+        start_after: Option<String>,
+        limit: Option<u32>,
     },
     /// returns ownership
     Ownership {},
     /// returns list of addresses allowed to flush
     FlushWhitelist {},
+    /// lists ICS20 transfers that are still waiting on an ack or timeout
+    /// Return Type: PendingIbcTransfersResponse
+    PendingIbcTransfers {
+        start_after: Option<(String, u64)>,
+        limit: Option<u32>,
+    },
+    /// the weights a deposit of 'denom' is split with now, leaving out the frozen, fixed, capped
+    /// and expired allocations
+    /// Return Type: DenomSplitResponse
+    DenomSplit {
+        denom: String,
+    },
+    /// lists the denoms that have their own weights
+    /// Return Type: DenomWeightsResponse
+    DenomWeights {
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+    /// allocations with weight changes or an expiry to come
+    /// Return Type: WeightSchedulesResponse
+    WeightSchedules {
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+    /// how much of a fixed or capped allocation is left to fill
+    /// Return Type: TrancheResponse
+    Tranche {
+        name: String,
+    },
+    /// who can claim for an allocation
+    /// Return Type: ClaimDelegateResponse
+    ClaimDelegate {
+        name: String,
+    },
+    /// a receiver change waiting to be accepted
+    /// Return Type: PendingReceiverResponse
+    PendingReceiver {
+        name: String,
+    },
+    /// which denoms deposits take
+    /// Return Type: DenomFilter
+    DenomFilter {},
+    /// what the denom filter has held back from deposits & sweeps
+    /// Return Type: Quarantine
+    Quarantine {},
+    /// allocation changes waiting on the change delay
+    /// Return Type: PendingChangesResponse
+    PendingChanges {
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    /// what a deposit of 'funds' (and/or the CW20 'cw20', with the token address as its denom)
+    /// would do right now, without doing it.
+    /// with a 'sender', their flush & table permissions are checked and their table used
+    /// Return Type: SimulateDepositResponse
+    SimulateDeposit {
+        funds: Vec<Coin>,
+        flush: bool,
+        #[serde(default)]
+        sender: Option<String>,
+        #[serde(default)]
+        cw20: Option<Coin>,
+        #[serde(default)]
+        table: Option<String>,
+    },
 }
+/// callbacks from the ibc-hooks module, for the ICS20 transfers this contract sends
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SudoMsg {
+    IbcLifecycleComplete(IbcLifecycleComplete),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum IbcLifecycleComplete {
+    IbcAck {
+        channel: String,
+        sequence: u64,
+        ack: String,
+        success: bool,
+    },
+    IbcTimeout {
+        channel: String,
+        sequence: u64,
+    },
+}
+
+/// an ICS20 transfer that has been sent, but not yet acknowledged
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct PendingIbcTransfer {
+    /// the allocation the coins get credited back to if the transfer fails
+    pub name: String,
+    pub channel_id: String,
+    /// zero until the transfer's sequence number is known
+    pub sequence: u64,
+    pub coin: Coin,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+pub struct PendingIbcTransfersResponse {
+    pub transfers: Vec<PendingIbcTransfer>,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
 pub struct OwnershipResponse {
     pub owner: String,
@@ -265,7 +1237,205 @@ pub struct WhitelistResponse {
     pub allowed: Vec<String>,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct AllocationResponse {
     pub allocations: Vec<AllocationHolding>,
 }
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+pub struct WeightSchedule {
+    pub name: String,
+    /// the weight now, with the changes that have been reached applied
+    pub weight: Uint128,
+    /// the changes still to come
+    pub schedule: Vec<WeightChange>,
+    pub expires: Option<ScheduleAt>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+pub struct WeightSchedulesResponse {
+    pub schedules: Vec<WeightSchedule>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+pub struct TrancheResponse {
+    pub name: String,
+    pub kind: AllocationKind,
+    /// given this period (fixed) or in total (capped)
+    pub filled: Vec<Coin>,
+    /// left to give before the limit is reached
+    pub remaining: Vec<Coin>,
+    /// when a fixed allocation's period rolls over
+    pub period_ends: Option<Timestamp>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+pub struct ClaimDelegateResponse {
+    pub name: String,
+    /// None for send types without a receiver on this chain
+    pub receiver: Option<String>,
+    pub delegate: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+pub struct PendingReceiverResponse {
+    pub name: String,
+    /// the receiver that proposed the change
+    pub receiver: Option<String>,
+    pub new_receiver: Option<String>,
+}
+
+/// what the denom filter has held back, by denom. CW20 tokens are always refunded
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct Quarantine {
+    pub balance: Vec<Coin>,
+}
+
+/// an allocation change, as queued by the change delay
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AllocationChange {
+    AddAllocationDetail {
+        allocation: AllocationDetail,
+    },
+    ModifyAllocationDetail {
+        allocation: AllocationDetail,
+    },
+    RemoveAllocationDetail {
+        name: String,
+    },
+    ReplaceAllocations {
+        allocations: Vec<AllocationDetail>,
+        balance_policy: BalancePolicy,
+    },
+    SetChangeDelay {
+        blocks: u64,
+    },
+    SetWeightSchedule {
+        name: String,
+        schedule: Vec<WeightChange>,
+        expires: Option<ScheduleAt>,
+    },
+    SetAllocationStatus {
+        name: String,
+        status: AllocationStatus,
+    },
+    SetDenomWeights {
+        denom: String,
+        weights: Vec<AllocationWeight>,
+    },
+    ClearDenomWeights {
+        denom: String,
+    },
+    SetTable {
+        table: String,
+        weights: Vec<AllocationWeight>,
+    },
+    RemoveTable {
+        table: String,
+    },
+    SetSenderTable {
+        sender: String,
+        table: Option<String>,
+    },
+    SetDenomFilter {
+        mode: DenomFilterMode,
+        denoms: Vec<String>,
+        on_filtered: FilteredDenomAction,
+        max_denoms: Option<u32>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+pub struct PendingChange {
+    pub id: u64,
+    pub change: AllocationChange,
+    /// height it was queued at
+    pub queued_height: u64,
+    /// it can be applied from this height on
+    pub execute_after: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+pub struct PendingChangesResponse {
+    /// blocks a change waits before it can be applied
+    pub change_delay: u64,
+    pub changes: Vec<PendingChange>,
+}
+
+/// the messages a deposit would send, and every allocation as it would be afterwards
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SimulateDepositResponse {
+    pub messages: Vec<CosmosMsg>,
+    pub allocations: Vec<AllocationHolding>,
+    /// what the denom filter sends back (CW20s by token address)
+    #[serde(default)]
+    pub refunded: Vec<Coin>,
+    /// what the denom filter quarantines (CW20s by token address)
+    #[serde(default)]
+    pub quarantined: Vec<Coin>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+pub struct AllocationWeight {
+    pub name: String,
+    pub allocation: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+pub struct DenomSplitResponse {
+    pub denom: String,
+    /// true if 'denom' has its own weights
+    pub is_override: bool,
+    pub weights: Vec<AllocationWeight>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+pub struct DenomWeightsResponse {
+    pub denoms: Vec<DenomSplitResponse>,
+}
+
+/// lifetime totals for one denom (or CW20 token address) of an allocation
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+pub struct DenomStats {
+    pub denom: String,
+    /// its share of all deposits
+    pub deposited: Uint128,
+    pub paid_out: Uint128,
+    /// number of payouts that included this denom
+    pub payouts: u64,
+    pub last_payout_height: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+pub struct AllocationStatsResponse {
+    pub name: String,
+    pub denoms: Vec<DenomStats>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+pub struct StatsResponse {
+    pub allocations: Vec<AllocationStatsResponse>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+pub struct TableResponse {
+    pub table: String,
+    pub weights: Vec<AllocationWeight>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+pub struct TablesResponse {
+    pub tables: Vec<TableResponse>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+pub struct SenderTableResponse {
+    pub sender: String,
+    pub table: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+pub struct SenderTablesResponse {
+    pub senders: Vec<SenderTableResponse>,
+}
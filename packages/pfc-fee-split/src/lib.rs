@@ -1 +1,2 @@
+pub mod cw4;
 pub mod fee_split_msg;